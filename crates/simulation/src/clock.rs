@@ -0,0 +1,88 @@
+//! Simulated time, kept apart from wall time so headless runs and replays
+//! see the same clock no matter how fast they ran.
+
+use core::{fmt, time::Duration};
+
+/// Advanced only by [`crate::Simulation::tick`], and never backwards.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SimClock {
+    // f64 so long runs of small steps don't lose time to rounding
+    seconds: f64,
+    ticks: u64,
+}
+
+impl SimClock {
+    pub fn seconds(&self) -> f64 {
+        self.seconds
+    }
+
+    /// Saturates at [`Duration::MAX`], and is zero for a clock that somehow
+    /// isn't a positive number of seconds, e.g. one loaded from a bad save.
+    pub fn elapsed(&self) -> Duration {
+        Duration::try_from_secs_f64(self.seconds).unwrap_or(if self.seconds > 0.0 {
+            Duration::MAX
+        } else {
+            Duration::ZERO
+        })
+    }
+
+    /// How many ticks have run.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Negative steps are ignored to keep the clock monotonic.
+    pub(crate) fn advance(&mut self, dt: f32) {
+        self.seconds += f64::from(dt.max(0.0));
+        self.ticks += 1;
+    }
+}
+
+impl fmt::Display for SimClock {
+    /// Formats as e.g. `3h 24m 05s`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Saturating, so no seconds value can panic like a Duration would
+        let total = self.seconds.max(0.0) as u64;
+        let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+        if hours > 0 {
+            write!(f, "{hours}h {minutes:02}m {seconds:02}s")
+        } else if minutes > 0 {
+            write!(f, "{minutes}m {seconds:02}s")
+        } else {
+            write!(f, "{seconds}s")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_clock_display() {
+        let mut clock = SimClock::default();
+        assert_eq!(clock.to_string(), "0s");
+        clock.advance(65.0);
+        assert_eq!(clock.to_string(), "1m 05s");
+        clock.advance(3.0 * 3600.0 + 19.0 * 60.0);
+        assert_eq!(clock.to_string(), "3h 20m 05s");
+    }
+
+    #[test]
+    fn test_clock_out_of_range() {
+        let clock = SimClock {
+            seconds: f64::INFINITY,
+            ticks: 1,
+        };
+        assert_eq!(clock.elapsed(), Duration::MAX);
+        assert!(clock.to_string().ends_with("s"));
+
+        for seconds in [f64::NAN, -5.0] {
+            let clock = SimClock { seconds, ticks: 1 };
+            assert_eq!(clock.elapsed(), Duration::ZERO);
+            assert_eq!(clock.to_string(), "0s");
+        }
+    }
+}
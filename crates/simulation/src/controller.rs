@@ -0,0 +1,205 @@
+//! Controllers switching connections on and off based on the nodes, like a
+//! differential thermostat running a solar loop's pump.
+
+use crate::{Connection, Nodes};
+
+/// Decides each tick whether the connections it controls run, see
+/// [`crate::Simulation::add_controller`]. Stopped connections move no fluid
+/// but still conduct heat.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Controller {
+    Thermostat(ThermostatController),
+}
+
+impl Controller {
+    /// Whether `connection` is one this switches.
+    pub fn controls(&self, connection: &Connection) -> bool {
+        match self {
+            Self::Thermostat(thermostat) => thermostat.controls(connection),
+        }
+    }
+
+    /// Whether the connections should run this tick, given whether they ran
+    /// last tick.
+    pub fn should_run(&self, nodes: &Nodes, running: bool) -> bool {
+        match self {
+            Self::Thermostat(thermostat) => thermostat.should_run(nodes, running),
+        }
+    }
+
+    /// Moves node ids, e.g. when merging. `None` if `f` has no new id for one
+    /// of them.
+    pub(crate) fn map_nodes(self, mut f: impl FnMut(usize) -> Option<usize>) -> Option<Self> {
+        match self {
+            Self::Thermostat(thermostat) => Some(Self::Thermostat(ThermostatController {
+                source: f(thermostat.source)?,
+                dest: f(thermostat.dest)?,
+                ..thermostat
+            })),
+        }
+    }
+}
+
+impl From<ThermostatController> for Controller {
+    fn from(thermostat: ThermostatController) -> Self {
+        Self::Thermostat(thermostat)
+    }
+}
+
+/// Runs the connections between `source` and `dest` while `source` is hotter
+/// by enough, e.g. a collector and the tank it heats.
+///
+/// They start once the difference goes above `on_delta` and keep going until
+/// it drops to `off_delta`. Keeping `off_delta` below `on_delta` stops the
+/// pump from rapidly switching on and off around a single setpoint.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ThermostatController {
+    pub source: usize,
+    pub dest: usize,
+    /// In deg C.
+    pub on_delta: f32,
+    /// In deg C.
+    pub off_delta: f32,
+}
+
+impl ThermostatController {
+    /// Connections either way between `source` and `dest`, so both halves of
+    /// a loop stop together.
+    pub fn controls(&self, connection: &Connection) -> bool {
+        (connection.input == self.source && connection.output == self.dest)
+            || (connection.input == self.dest && connection.output == self.source)
+    }
+
+    pub fn should_run(&self, nodes: &Nodes, running: bool) -> bool {
+        let (Some(source), Some(dest)) = (nodes.get(self.source), nodes.get(self.dest)) else {
+            return false;
+        };
+        let delta = source.fluid.temp - dest.fluid.temp;
+        if running {
+            delta > self.off_delta
+        } else {
+            delta > self.on_delta
+        }
+    }
+}
+
+/// A controller and whether its connections ran last tick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct ControllerState {
+    pub(crate) controller: Controller,
+    pub(crate) running: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Environment, Simulation, TickStage, TickStages};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_thermostat_controller() {
+        let mut sim = Simulation::new();
+        sim.set_tick_stages(TickStages::new([TickStage::FluidTransfer]));
+        let panel = sim.add_node(10.0, 20.0, 1.0, 20.0, glam::Vec3::ZERO);
+        let tank = sim.add_node(10.0, 20.0, 1.0, 20.0, glam::Vec3::X);
+        sim.connect_node(panel, tank, 1.0);
+        sim.connect_node(tank, panel, 1.0);
+        let thermostat = sim.add_controller(ThermostatController {
+            source: panel,
+            dest: tank,
+            on_delta: 5.0,
+            off_delta: 2.0,
+        });
+        let environment = Environment::default();
+
+        // Not hot enough yet, so nothing moves
+        sim.tick(&environment, 0.1);
+        assert_eq!(sim.controller_running(thermostat), Some(false));
+        assert_eq!(sim.realized_flows(), [0.0, 0.0]);
+        assert_eq!(sim.nodes()[tank].fluid.temp, 20.0);
+
+        let heat = |sim: &mut Simulation, temp| sim.nodes[panel].fluid.temp = temp;
+        heat(&mut sim, 24.0);
+        sim.tick(&environment, 0.1);
+        assert_eq!(sim.controller_running(thermostat), Some(false));
+        assert_eq!(sim.nodes()[tank].fluid.temp, 20.0);
+
+        heat(&mut sim, 30.0);
+        sim.tick(&environment, 0.1);
+        assert_eq!(sim.controller_running(thermostat), Some(true));
+        assert!(sim.realized_flows().iter().all(|&flow| flow > 0.0));
+        assert!(sim.nodes()[tank].fluid.temp > 20.0);
+
+        // Keeps running below the on delta until the off delta
+        let tank_temp = sim.nodes()[tank].fluid.temp;
+        heat(&mut sim, tank_temp + 3.0);
+        sim.tick(&environment, 0.1);
+        assert_eq!(sim.controller_running(thermostat), Some(true));
+        let tank_temp = sim.nodes()[tank].fluid.temp;
+        heat(&mut sim, tank_temp + 1.0);
+        sim.tick(&environment, 0.1);
+        assert_eq!(sim.controller_running(thermostat), Some(false));
+        assert_eq!(sim.realized_flows(), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_controllers_switch_once_per_tick() {
+        let mut sim = Simulation::new();
+        sim.set_tick_stages(TickStages::new([TickStage::FluidTransfer]));
+        sim.set_max_substep_dt(Some(0.25));
+        let panel = sim.add_node(10.0, 30.0, 1.0, 20.0, glam::Vec3::ZERO);
+        let tank = sim.add_node(10.0, 20.0, 1.0, 20.0, glam::Vec3::X);
+        sim.connect_node(panel, tank, 5.0);
+        sim.connect_node(tank, panel, 5.0);
+        let thermostat = sim.add_controller(ThermostatController {
+            source: panel,
+            dest: tank,
+            on_delta: 9.0,
+            off_delta: 8.0,
+        });
+
+        // The first substep mixes the difference below the off delta, but the
+        // rest still run since the controller only looks at the tick's start
+        sim.tick(&Environment::default(), 1.0);
+        assert_eq!(sim.controller_running(thermostat), Some(true));
+        assert_eq!(sim.realized_flows(), [5.0, 5.0]);
+
+        sim.tick(&Environment::default(), 1.0);
+        assert_eq!(sim.controller_running(thermostat), Some(false));
+        assert_eq!(sim.realized_flows(), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_controllers_follow_nodes() {
+        let mut sim = Simulation::new();
+        let spare = sim.add_node(10.0, 20.0, 1.0, 20.0, glam::Vec3::ZERO);
+        let panel = sim.add_node(10.0, 20.0, 1.0, 20.0, glam::Vec3::X);
+        let tank = sim.add_node(10.0, 20.0, 1.0, 20.0, glam::Vec3::Y);
+        let thermostat = ThermostatController {
+            source: panel,
+            dest: tank,
+            on_delta: 5.0,
+            off_delta: 2.0,
+        };
+        let removed = sim.add_controller(ThermostatController {
+            source: spare,
+            ..thermostat
+        });
+        let kept = sim.add_controller(thermostat);
+
+        // Ids don't move, so the other controller stays as it was
+        sim.remove_node(spare);
+        assert_eq!(
+            sim.controllers().collect::<Vec<_>>(),
+            [&Controller::Thermostat(thermostat)]
+        );
+        assert_eq!(sim.controller_running(removed), None);
+        assert_eq!(sim.controller_running(kept), Some(false));
+        sim.remove_node(panel);
+        assert_eq!(sim.controllers().count(), 0);
+        assert_eq!(sim.controller_running(kept), None);
+    }
+}
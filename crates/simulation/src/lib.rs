@@ -0,0 +1,3892 @@
+//! The fluid simulation core. Builds without `std` (using `alloc`) when the
+//! default `std` feature is disabled and `libm` is enabled.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("either the `std` or `libm` feature must be enabled");
+
+extern crate alloc;
+
+mod clock;
+mod controller;
+mod math;
+pub mod properties;
+mod recorder;
+mod runner;
+mod slab;
+mod stepper;
+mod strata;
+pub mod units;
+
+pub use clock::SimClock;
+pub use controller::{Controller, ThermostatController};
+#[cfg(feature = "parquet")]
+pub use recorder::ParquetRecorder;
+pub use recorder::{RecorderRow, SimulationRecorder};
+pub use runner::{RunnerSample, SimulationRunner};
+pub use slab::Slab;
+pub use stepper::FixedStepper;
+pub use strata::NodeModel;
+
+use alloc::{boxed::Box, collections::VecDeque, string::String, vec, vec::Vec};
+use controller::ControllerState;
+use core::{
+    f32, fmt,
+    ops::{Add, AddAssign, Range},
+};
+use properties::FluidProperties;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+// Ordered so that every tick visits panels and extractors in the same order
+type Map<K, V> = alloc::collections::BTreeMap<K, V>;
+
+/// A [`Simulation`]'s nodes, by ids that stay put when others are removed.
+pub type Nodes = Slab<Node>;
+/// A [`Simulation`]'s connections, by ids that stay put when others are
+/// removed.
+pub type Connections = Slab<Connection>;
+
+/// Furthest [`Simulation::estimate_time_to_temp`] looks ahead, in ticks.
+const MAX_ESTIMATE_TICKS: u32 = 1_000_000;
+
+/// Most a node's heat loss can close of its gap to ambient in one substep.
+/// At 1 a substep can take it all the way there but never past.
+const MAX_LOSS_FACTOR: f32 = 1.0;
+/// Most substeps a single tick is split into, however large its `dt`.
+const MAX_SUBSTEPS: u32 = 1_000;
+
+/// Step [`Simulation::warm_start`] relaxes with, and for how long at most.
+const WARM_START_DT: f32 = 1.0;
+const MAX_WARM_START_TICKS: u32 = 100_000;
+
+/// Earth's gravity in m/s².
+pub const STANDARD_GRAVITY: f32 = 9.81;
+
+/// Seconds in a day.
+pub const EARTH_DAY_LENGTH: f32 = 86_400.0;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+// Scenes saved before the day/night cycle are still loadable
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Environment {
+    sun_angle: f32,
+    sun_irradiance: f32,
+    cloud_cover: f32,
+    ambient_temp: f32,
+    sun_enabled: bool,
+    /// Seconds since midnight.
+    time_of_day: f32,
+    day_length: f32,
+    /// In radians.
+    latitude: f32,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            sun_angle: f32::consts::FRAC_PI_2,
+            sun_irradiance: 1000.0,
+            cloud_cover: Default::default(),
+            ambient_temp: 20.0,
+            sun_enabled: true,
+            time_of_day: EARTH_DAY_LENGTH / 2.0,
+            day_length: EARTH_DAY_LENGTH,
+            latitude: 0.0,
+        }
+    }
+}
+
+impl Environment {
+    pub fn sun_angle(&self) -> f32 {
+        self.sun_angle
+    }
+
+    pub fn sun_irradiance(&self) -> f32 {
+        self.sun_irradiance
+    }
+
+    /// W/m² reaching a flat panel once the sun's angle and the clouds are
+    /// accounted for.
+    pub(crate) fn panel_irradiance(&self) -> f32 {
+        self.sun_irradiance * math::sin(self.sun_angle).max(0.0) * (1.0 - self.cloud_cover)
+    }
+
+    pub fn cloud_cover(&self) -> f32 {
+        self.cloud_cover
+    }
+
+    pub fn ambient_temp(&self) -> f32 {
+        self.ambient_temp
+    }
+
+    pub fn sun_enabled(&self) -> bool {
+        self.sun_enabled
+    }
+
+    /// Sets the sun angle in radians, wrapped into `0..TAU`. Non-finite
+    /// angles are ignored.
+    pub fn set_sun_angle(&mut self, angle: f32) {
+        if !angle.is_finite() {
+            log::warn!("Ignoring non-finite sun angle {angle}");
+            return;
+        }
+        self.sun_angle = wrap_angle(angle);
+    }
+
+    pub fn advance_sun_angle(&mut self, delta: f32) {
+        self.set_sun_angle(self.sun_angle + delta);
+    }
+
+    pub fn set_sun_irradiance(&mut self, irradiance: f32) {
+        if !irradiance.is_finite() {
+            log::warn!("Ignoring non-finite sun irradiance {irradiance}");
+            return;
+        }
+        self.sun_irradiance = irradiance.max(0.0);
+    }
+
+    pub fn set_cloud_cover(&mut self, cloud_cover: f32) {
+        if !cloud_cover.is_finite() {
+            log::warn!("Ignoring non-finite cloud cover {cloud_cover}");
+            return;
+        }
+        self.cloud_cover = cloud_cover.clamp(0.0, 1.0);
+    }
+
+    pub fn set_ambient_temp(&mut self, ambient_temp: f32) {
+        if !ambient_temp.is_finite() {
+            log::warn!("Ignoring non-finite ambient temperature {ambient_temp}");
+            return;
+        }
+        self.ambient_temp = ambient_temp;
+    }
+
+    /// Turning the sun off stops all solar gain without touching the sun
+    /// angle, e.g. to simulate an eclipse or a sensor outage.
+    pub fn set_sun_enabled(&mut self, enabled: bool) {
+        self.sun_enabled = enabled;
+    }
+
+    /// Seconds since midnight, see [`Self::advance_time`].
+    pub fn time_of_day(&self) -> f32 {
+        self.time_of_day
+    }
+
+    pub fn day_length(&self) -> f32 {
+        self.day_length
+    }
+
+    /// Seconds from one midnight to the next. Defaults to
+    /// [`EARTH_DAY_LENGTH`], shorter days are handy for watching a full cycle.
+    /// Non-positive lengths are ignored.
+    pub fn set_day_length(&mut self, day_length: f32) {
+        if !(day_length.is_finite() && day_length > 0.0) {
+            log::warn!("Ignoring invalid day length {day_length}");
+            return;
+        }
+        self.time_of_day *= day_length / self.day_length;
+        self.day_length = day_length;
+    }
+
+    pub fn latitude(&self) -> f32 {
+        self.latitude
+    }
+
+    /// In radians, clamped to the poles. Further from the equator the sun
+    /// stays lower, so panels collect less at noon.
+    pub fn set_latitude(&mut self, latitude: f32) {
+        if !latitude.is_finite() {
+            log::warn!("Ignoring non-finite latitude {latitude}");
+            return;
+        }
+        self.latitude = latitude.clamp(-f32::consts::FRAC_PI_2, f32::consts::FRAC_PI_2);
+    }
+
+    /// Moves the time of day on by `dt` seconds and puts the sun where it is
+    /// then, replacing any angle set by hand. The sun follows its arc at an
+    /// equinox: rising at 0 rad a quarter of the day in, highest at noon and
+    /// setting at PI, with the angle past PI all night so there's no gain.
+    pub fn advance_time(&mut self, dt: f32) {
+        if !dt.is_finite() {
+            log::warn!("Ignoring non-finite time step {dt}");
+            return;
+        }
+        self.time_of_day = math::rem_euclid(self.time_of_day + dt, self.day_length);
+
+        // The sun's hour angle, 0 at noon and negative in the morning
+        let hour_angle = f32::consts::TAU * (self.time_of_day / self.day_length - 0.5);
+        let elevation = math::asin(math::cos(self.latitude) * math::cos(hour_angle));
+        self.set_sun_angle(if hour_angle < 0.0 {
+            elevation
+        } else {
+            f32::consts::PI - elevation
+        });
+    }
+}
+
+fn wrap_angle(angle: f32) -> f32 {
+    let wrapped = math::rem_euclid(angle, f32::consts::TAU);
+    // rem_euclid can round up to TAU for tiny negative angles
+    if wrapped >= f32::consts::TAU {
+        0.0
+    } else {
+        wrapped
+    }
+}
+
+impl fmt::Display for Environment {
+    // Sticks to ASCII so the summary can be drawn with the HUD font
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sun {:.0} deg{}, {:.0} W/m2, {:.0}% cloud, {:.0} C ambient",
+            self.sun_angle.to_degrees(),
+            if self.sun_enabled { "" } else { " (off)" },
+            self.sun_irradiance,
+            self.cloud_cover * 100.0,
+            self.ambient_temp,
+        )
+    }
+}
+
+/// One of the steps [`Simulation::tick`] runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TickStage {
+    HeatLoss,
+    SolarPanels,
+    Extractors,
+    FluidTransfer,
+    /// Heat through the walls of connections with a conductance.
+    Conduction,
+}
+
+/// Which stages a tick runs and in what order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TickStages {
+    order: Vec<TickStage>,
+    frozen_volumes: bool,
+}
+
+impl TickStages {
+    /// Runs `order` in sequence. Stages that are left out are disabled and
+    /// repeated stages only run the first time.
+    pub fn new(order: impl IntoIterator<Item = TickStage>) -> Self {
+        let mut stages = Self {
+            order: Vec::new(),
+            frozen_volumes: false,
+        };
+        for stage in order {
+            if !stages.contains(stage) {
+                stages.order.push(stage);
+            }
+        }
+        stages
+    }
+
+    pub fn without(mut self, stage: TickStage) -> Self {
+        self.order.retain(|&s| s != stage);
+        self
+    }
+
+    /// Stops [`TickStage::FluidTransfer`] from moving fluid. Connections still
+    /// exchange heat as if the same amount of fluid flowed both ways, which is
+    /// handy for looking at thermal behavior on its own.
+    pub fn with_frozen_volumes(mut self, frozen: bool) -> Self {
+        self.frozen_volumes = frozen;
+        self
+    }
+
+    pub fn frozen_volumes(&self) -> bool {
+        self.frozen_volumes
+    }
+
+    pub fn contains(&self, stage: TickStage) -> bool {
+        self.order.contains(&stage)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = TickStage> + '_ {
+        self.order.iter().copied()
+    }
+}
+
+impl Default for TickStages {
+    fn default() -> Self {
+        Self::new([
+            TickStage::HeatLoss,
+            TickStage::SolarPanels,
+            TickStage::Extractors,
+            TickStage::FluidTransfer,
+            TickStage::Conduction,
+        ])
+    }
+}
+
+/// How the heat loss and solar stages step temperatures forward in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Integrator {
+    /// Forward Euler. Cheap, but overshoots when `dt` is large.
+    #[default]
+    Euler,
+    /// Heun's method, which averages the rate at the start and the Euler
+    /// estimate of the end of the step.
+    Rk2,
+}
+
+/// Called before or after each tick, see [`Simulation::add_pre_tick_hook`].
+pub type TickHook = Box<dyn FnMut(&mut Simulation, &Environment) + Send + Sync>;
+
+/// Watches each finished tick, see [`Simulation::on_tick`].
+pub type TickCallback = Box<dyn FnMut(&Simulation, f32) + Send + Sync>;
+
+#[derive(Default)]
+struct TickHooks {
+    pre_tick: Vec<TickHook>,
+    post_tick: Vec<TickHook>,
+    on_tick: Vec<TickCallback>,
+}
+
+impl TickHooks {
+    fn append(&mut self, other: &mut Self) {
+        self.pre_tick.append(&mut other.pre_tick);
+        self.post_tick.append(&mut other.post_tick);
+        self.on_tick.append(&mut other.on_tick);
+    }
+}
+
+// Hooks can't be cloned, so a cloned simulation starts without any
+impl Clone for TickHooks {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for TickHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TickHooks")
+            .field("pre_tick", &self.pre_tick.len())
+            .field("post_tick", &self.post_tick.len())
+            .field("on_tick", &self.on_tick.len())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Simulation {
+    nodes: Nodes,
+    connections: Connections,
+    /// Flow rate each connection actually achieved last tick, indexed by id.
+    realized_flows: Vec<f32>,
+    /// Thermal power each connection carried last tick, indexed by id.
+    heat_fluxes: Vec<f32>,
+    /// Fluid in transit along each connection, indexed by id.
+    pipes: Vec<Pipe>,
+    /// Net energy each node gained last tick, indexed by id.
+    energy_deltas: Vec<f32>,
+    solar_panels: Map<usize, Vec<SolarPanel>>,
+    extractors: Map<usize, Extractor>,
+    names: Map<String, usize>,
+    /// Node state before the last tick, kept when interpolation is enabled.
+    previous: Option<Vec<Option<NodeSnapshot>>>,
+    /// State before each of the last `history_len` ticks, oldest first.
+    history: VecDeque<TickState>,
+    history_len: usize,
+    /// Closures can't be saved, so hooks have to be added again after loading.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hooks: TickHooks,
+    stages: TickStages,
+    integrator: Integrator,
+    fluid_table: FluidTable,
+    /// Heat in J each node has lost to its max temperature so far.
+    vented_energy: Map<usize, f32>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    controllers: Slab<ControllerState>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    max_substep_dt: Option<f32>,
+    /// Layers of each stratified node, bottom first. The node's fluid is
+    /// kept as all of them mixed.
+    #[cfg_attr(feature = "serde", serde(default))]
+    strata: Map<usize, Vec<Fluid>>,
+    /// In m/s².
+    gravity: f32,
+    clock: SimClock,
+    /// Runs the per node stages one node at a time even with the `rayon`
+    /// feature, so tests can check both ways agree.
+    #[cfg(all(test, feature = "rayon"))]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    force_serial: bool,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulation {
+    pub fn new() -> Self {
+        Self {
+            nodes: Nodes::default(),
+            connections: Connections::default(),
+            realized_flows: Vec::new(),
+            heat_fluxes: Vec::new(),
+            pipes: Vec::new(),
+            energy_deltas: Vec::new(),
+            solar_panels: Map::new(),
+            extractors: Map::new(),
+            names: Map::new(),
+            previous: None,
+            history: VecDeque::new(),
+            history_len: 0,
+            hooks: TickHooks::default(),
+            stages: TickStages::default(),
+            integrator: Integrator::default(),
+            fluid_table: FluidTable::default(),
+            vented_energy: Map::new(),
+            controllers: Slab::default(),
+            max_substep_dt: None,
+            strata: Map::new(),
+            gravity: STANDARD_GRAVITY,
+            clock: SimClock::default(),
+            #[cfg(all(test, feature = "rayon"))]
+            force_serial: false,
+        }
+    }
+
+    /// Everything but the hooks, which have to be added again after loading.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Shorthand for [`Self::add_node_desc`] with the default tint.
+    pub fn add_node(
+        &mut self,
+        volume: f32,
+        temp: f32,
+        insulation: f32,
+        capacity: f32,
+        position: glam::Vec3,
+    ) -> usize {
+        self.add_node_desc(NodeDesc {
+            volume,
+            temp,
+            insulation,
+            capacity,
+            position,
+            ..Default::default()
+        })
+    }
+
+    pub fn add_node_desc(&mut self, desc: NodeDesc) -> usize {
+        self.nodes.push(Node {
+            fluid: Fluid {
+                volume: desc.volume,
+                temp: desc.temp,
+            },
+            insulation: desc.insulation,
+            capacity: desc.capacity,
+            position: desc.position,
+            name: None,
+            tint: desc.tint,
+            min_temp: desc.min_temp,
+            max_temp: desc.max_temp,
+        })
+    }
+
+    /// Adds a node at each of `positions`, all starting in the same state.
+    /// Returns the range of the new node ids.
+    pub fn add_nodes_bulk(
+        &mut self,
+        positions: &[glam::Vec3],
+        volume: f32,
+        temp: f32,
+        insulation: f32,
+        capacity: f32,
+    ) -> Range<usize> {
+        let start = self.nodes.id_bound();
+        self.nodes.extend_slots(positions.iter().map(|&position| {
+            Some(Node {
+                fluid: Fluid { volume, temp },
+                insulation,
+                capacity,
+                position,
+                name: None,
+                tint: glam::Vec3::ONE,
+                min_temp: None,
+                max_temp: None,
+            })
+        }));
+        start..self.nodes.id_bound()
+    }
+
+    /// Names a node so it can be looked up with [`Self::node_by_name`].
+    /// Returns `false` if the node doesn't exist or another node already has
+    /// the name.
+    pub fn set_node_name(&mut self, id: usize, name: impl Into<String>) -> bool {
+        let name = name.into();
+        if !self.contains_node(id) || self.names.get(&name).is_some_and(|&other| other != id) {
+            return false;
+        }
+
+        if let Some(old_name) = self.nodes[id].name.take() {
+            self.names.remove(&old_name);
+        }
+        self.names.insert(name.clone(), id);
+        self.nodes[id].name = Some(name);
+        true
+    }
+
+    /// Keeps the node's temperature within `min..=max`, e.g. at 100 deg C for
+    /// an open water loop that would boil. Heat that would push it past `max`
+    /// is vented, see [`Self::node_vented_energy`]. A node that would cool
+    /// below `min` is held at `min` instead, as if heated, and that heat isn't
+    /// counted anywhere. Returns `false` if the node doesn't exist.
+    pub fn set_node_temp_limits(&mut self, id: usize, min: Option<f32>, max: Option<f32>) -> bool {
+        match self.nodes.get_mut(id) {
+            Some(node) => {
+                node.min_temp = min;
+                node.max_temp = max;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Heat in J the node has vented at its max temperature since it was
+    /// added. Unlike the node state, [`Self::step_back`] doesn't rewind it.
+    pub fn node_vented_energy(&self, id: usize) -> f32 {
+        self.vented_energy.get(&id).copied().unwrap_or(0.0)
+    }
+
+    /// Returns `false` if the node doesn't exist.
+    pub fn set_node_tint(&mut self, id: usize, tint: glam::Vec3) -> bool {
+        match self.nodes.get_mut(id) {
+            Some(node) => {
+                node.tint = tint;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn node_by_name(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+
+    /// Like [`Self::connect_node`] but with node names. Returns `false` if
+    /// either name is unknown.
+    pub fn connect_named(&mut self, input: &str, output: &str, flow_rate: f32) -> bool {
+        match (self.node_by_name(input), self.node_by_name(output)) {
+            (Some(input), Some(output)) => {
+                self.connect_node(input, output, flow_rate);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Appends `other`'s nodes, connections, panels and extractors, e.g. to
+    /// join separately built loops. Returns the new id of each of `other`'s
+    /// nodes, indexed by its old id, so the loops can then be connected.
+    ///
+    /// Everything else, like the gravity and clock, stays as `self` had it,
+    /// though `other`'s nodes keep the fluid they hold. Names `self` already
+    /// uses are dropped, as are `other`'s hooks since they'd refer to the old
+    /// ids. So are connections, panels and the like referring to nodes
+    /// `other` doesn't have, e.g. from a hand edited save. Clears the tick
+    /// history.
+    pub fn merge(&mut self, other: Simulation) -> Vec<usize> {
+        let offset = self.nodes.id_bound();
+        let ids = (offset..offset + other.nodes.id_bound()).collect::<Vec<_>>();
+
+        // Keep deltas and snapshots lined up with the nodes, and leave other's
+        // nodes without them if self's are already behind
+        if self.energy_deltas.len() == offset && other.energy_deltas.len() == other.nodes.id_bound()
+        {
+            self.energy_deltas.extend(&other.energy_deltas);
+        }
+        if let Some(previous) = &mut self.previous {
+            if previous.len() == offset {
+                previous.extend(match &other.previous {
+                    Some(other_previous) => other_previous.clone(),
+                    None => other.snapshot(),
+                });
+            }
+        }
+
+        // Removed nodes come along as gaps, so ids only shift by the offset
+        let mut slots = other.nodes.into_slots();
+        for (id, slot) in slots.iter_mut().enumerate() {
+            let Some(node) = slot else {
+                continue;
+            };
+            if let Some(name) = node.name.take() {
+                if self.names.contains_key(&name) {
+                    log::warn!("Dropping duplicate node name {name:?} while merging");
+                } else {
+                    self.names.insert(name.clone(), ids[id]);
+                    node.name = Some(name);
+                }
+            }
+        }
+        self.nodes.extend_slots(slots);
+        // Where each of other's nodes ended up, or `None` for removed ones
+        let new_ids = ids
+            .iter()
+            .map(|&id| self.contains_node(id).then_some(id))
+            .collect::<Vec<_>>();
+        let remap = |kind: &str, id: usize| {
+            let new_id = new_ids.get(id).copied().flatten();
+            if new_id.is_none() {
+                log::warn!("Dropping {kind} on missing node {id} while merging");
+            }
+            new_id
+        };
+
+        let transit = other
+            .realized_flows
+            .into_iter()
+            .zip(other.heat_fluxes)
+            .zip(other.pipes);
+        for (slot, ((realized_flow, heat_flux), pipe)) in
+            other.connections.into_slots().into_iter().zip(transit)
+        {
+            let Some(connection) = slot else {
+                continue;
+            };
+            let (Some(input), Some(output)) = (
+                remap("a connection", connection.input),
+                remap("a connection", connection.output),
+            ) else {
+                continue;
+            };
+            self.connections.push(Connection {
+                input,
+                output,
+                ..connection
+            });
+            self.realized_flows.push(realized_flow);
+            self.heat_fluxes.push(heat_flux);
+            self.pipes.push(pipe);
+        }
+
+        for (id, panels) in other.solar_panels {
+            if let Some(id) = remap("solar panels", id) {
+                self.solar_panels.insert(id, panels);
+            }
+        }
+        for (id, extractor) in other.extractors {
+            if let Some(id) = remap("an extractor", id) {
+                self.extractors.insert(id, extractor);
+            }
+        }
+        for (id, vented) in other.vented_energy {
+            if let Some(id) = remap("vented energy", id) {
+                self.vented_energy.insert(id, vented);
+            }
+        }
+        for state in other.controllers.into_slots().into_iter().flatten() {
+            if let Some(controller) = state.controller.map_nodes(|id| remap("a controller", id)) {
+                self.controllers.push(ControllerState {
+                    controller,
+                    ..state
+                });
+            }
+        }
+        for (id, layers) in other.strata {
+            if let Some(id) = remap("layers", id) {
+                self.strata.insert(id, layers);
+            }
+        }
+        // Keep what other's nodes hold even where its default differs
+        for (id, &new_id) in new_ids.iter().enumerate() {
+            let Some(new_id) = new_id else {
+                continue;
+            };
+            let properties = match other.fluid_table.node_fluid_properties.get(&id) {
+                Some(properties) => properties,
+                None if other.fluid_table.fluid_properties != self.fluid_table.fluid_properties => {
+                    &other.fluid_table.fluid_properties
+                }
+                None => continue,
+            };
+            self.fluid_table
+                .node_fluid_properties
+                .insert(new_id, properties.clone());
+        }
+
+        self.history.clear();
+        ids
+    }
+
+    pub fn connect_node(&mut self, input: usize, output: usize, flow_rate: f32) {
+        if self.contains_node(input) && self.contains_node(output) {
+            self.connections.push(Connection {
+                flow_rate,
+                input,
+                output,
+                conductance: 0.0,
+                heat_loss_coeff: 0.0,
+                length: None,
+            });
+            self.realized_flows.push(0.0);
+            self.heat_fluxes.push(0.0);
+            self.pipes.push(Pipe::default());
+        }
+    }
+
+    /// Adds a controller switching connections on and off each tick, before
+    /// fluid moves. Returns its id.
+    pub fn add_controller(&mut self, controller: impl Into<Controller>) -> usize {
+        self.controllers.push(ControllerState {
+            controller: controller.into(),
+            running: false,
+        })
+    }
+
+    pub fn controllers(&self) -> impl Iterator<Item = &Controller> + '_ {
+        self.controllers.iter().map(|state| &state.controller)
+    }
+
+    /// Whether the controller let its connections run last tick. `None` if it
+    /// doesn't exist, e.g. after its nodes were removed.
+    pub fn controller_running(&self, id: usize) -> Option<bool> {
+        self.controllers.get(id).map(|state| state.running)
+    }
+
+    /// Removes the node and every connection to or from it, returning the
+    /// node. Every other node keeps its id, and `id` isn't handed out again,
+    /// so ids held elsewhere, e.g. by hooks, never end up at a different
+    /// node. See [`Nodes`].
+    pub fn remove_node(&mut self, id: usize) -> Option<Node> {
+        let node = self.nodes.remove(id)?;
+
+        let connections = self
+            .connections
+            .iter_with_ids()
+            .filter(|(_, connection)| connection.input == id || connection.output == id)
+            .map(|(connection, _)| connection)
+            .collect::<Vec<_>>();
+        for connection in connections {
+            self.remove_connection(connection);
+        }
+        if let Some(previous) = &mut self.previous {
+            if let Some(snapshot) = previous.get_mut(id) {
+                *snapshot = None;
+            }
+        }
+        self.names.retain(|_, node| *node != id);
+        self.solar_panels.remove(&id);
+        self.extractors.remove(&id);
+        self.fluid_table.node_fluid_properties.remove(&id);
+        self.vented_energy.remove(&id);
+        self.strata.remove(&id);
+        self.controllers.retain(|state| {
+            state
+                .controller
+                .map_nodes(|node| (node != id).then_some(node))
+                .is_some()
+        });
+        Some(node)
+    }
+
+    /// Removes the connection, returning it. Fluid in transit along it is
+    /// lost. Every other connection keeps its id, and `id` isn't handed out
+    /// again, see [`Connections`].
+    pub fn remove_connection(&mut self, id: usize) -> Option<Connection> {
+        let connection = self.connections.remove(id)?;
+
+        for per_connection in [&mut self.realized_flows, &mut self.heat_fluxes] {
+            if let Some(value) = per_connection.get_mut(id) {
+                *value = 0.0;
+            }
+        }
+        let pipes = self
+            .history
+            .iter_mut()
+            .map(|state| &mut state.pipes)
+            .chain([&mut self.pipes]);
+        for pipes in pipes {
+            if let Some(pipe) = pipes.get_mut(id) {
+                *pipe = Pipe::default();
+            }
+        }
+        Some(connection)
+    }
+
+    /// Gives a connection an internal volume (mL) that fluid has to fill
+    /// before any reaches the output, so it arrives after a transport delay.
+    /// For a pipe that's its length times its cross-section area.
+    ///
+    /// Fluid in transit doesn't belong to any node, so it's left out of the
+    /// node metrics and doesn't lose heat. Returns `false` if the connection
+    /// doesn't exist.
+    pub fn set_connection_volume(&mut self, id: usize, volume: f32) -> bool {
+        if !self.connections.contains(id) {
+            return false;
+        }
+        match self.pipes.get_mut(id) {
+            Some(pipe) => {
+                pipe.volume = volume.max(0.0);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Lets heat through the connection's wall at `conductance` W per deg C
+    /// of difference, e.g. for tanks sharing a wall. Use a flow rate of 0 for
+    /// no fluid to move. Returns `false` if the connection doesn't exist.
+    pub fn set_connection_conductance(&mut self, id: usize, conductance: f32) -> bool {
+        match self.connections.get_mut(id) {
+            Some(connection) => {
+                connection.conductance = conductance.max(0.0);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Cools fluid on its way through the connection towards the ambient
+    /// temperature, by `heat_loss_coeff * length` of the difference per
+    /// second, e.g. for long outdoor pipes. Returns `false` if the connection
+    /// doesn't exist.
+    pub fn set_connection_heat_loss(
+        &mut self,
+        id: usize,
+        heat_loss_coeff: f32,
+        length: Option<f32>,
+    ) -> bool {
+        match self.connections.get_mut(id) {
+            Some(connection) => {
+                connection.heat_loss_coeff = heat_loss_coeff.max(0.0);
+                connection.length = length.map(|length| length.max(0.0));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// How much fluid (mL) is currently in transit along the connection.
+    pub fn connection_in_transit(&self, id: usize) -> Option<f32> {
+        if !self.connections.contains(id) {
+            return None;
+        }
+        self.pipes.get(id).map(Pipe::held)
+    }
+
+    pub fn connections(&self) -> &Connections {
+        &self.connections
+    }
+
+    /// The flow rate each connection achieved last tick, indexed by its id in
+    /// [`Self::connections`], with 0 for removed ones. This is less than the
+    /// configured rate when the input runs dry or the output fills up, and
+    /// negative like it when the flow is reversed.
+    pub fn realized_flows(&self) -> &[f32] {
+        &self.realized_flows
+    }
+
+    /// The thermal power in W the connection's realized flow carried out of
+    /// its input last tick, relative to 0 deg C, negative when it flowed into
+    /// the input instead. 0 for unknown connections.
+    pub fn connection_heat_flux(&self, id: usize) -> f32 {
+        self.heat_fluxes.get(id).copied().unwrap_or(0.0)
+    }
+
+    pub fn nodes(&self) -> &Nodes {
+        &self.nodes
+    }
+
+    #[allow(unused)]
+    pub fn get_node(&self, id: usize) -> Option<&Node> {
+        self.nodes.get(id)
+    }
+
+    pub fn connected_nodes(&self) -> IterConnections<'_> {
+        IterConnections {
+            simulation: self,
+            index: 0,
+        }
+    }
+
+    /// Adds a panel to the node. Panels on the same node add up, like an
+    /// array of collectors on one tank.
+    pub fn attach_solar_panel(&mut self, id: usize, panel: SolarPanel) {
+        if self.contains_node(id) {
+            self.solar_panels.entry(id).or_default().push(panel);
+        }
+    }
+
+    /// The panels attached to the node, in the order they were attached.
+    pub fn node_solar_panels(&self, id: usize) -> &[SolarPanel] {
+        self.solar_panels.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    /// The first panel attached to the node, see [`Self::node_solar_panels`]
+    /// for all of them.
+    pub fn solar_panel(&self, id: usize) -> Option<&SolarPanel> {
+        self.node_solar_panels(id).first()
+    }
+
+    /// Every panel with the node it's attached to, in node order. Nodes with
+    /// several panels come up once for each.
+    pub fn solar_panels(&self) -> impl Iterator<Item = (usize, &SolarPanel)> + '_ {
+        self.solar_panels
+            .iter()
+            .flat_map(|(&id, panels)| panels.iter().map(move |panel| (id, panel)))
+    }
+
+    pub fn attach_extractor(&mut self, id: usize, extractor: Extractor) {
+        if self.contains_node(id) {
+            self.extractors.insert(id, extractor);
+        }
+    }
+
+    /// Keeps the state from before each of the last `len` ticks so
+    /// [`Self::step_back`] can rewind through them. 0 turns it off.
+    pub fn set_history_len(&mut self, len: usize) {
+        self.history_len = len;
+        while self.history.len() > len {
+            self.history.pop_front();
+        }
+    }
+
+    /// Restores the state from before the last recorded tick. Ticks aren't
+    /// reversible, so this replays history rather than running the physics
+    /// backwards. Nodes and connections added since keep their current state.
+    /// Returns `false` if there's no history left.
+    pub fn step_back(&mut self) -> bool {
+        let Some(state) = self.history.pop_back() else {
+            return false;
+        };
+
+        for (slot, fluid) in self.nodes.slots_mut().iter_mut().zip(state.fluids) {
+            if let (Some(node), Some(fluid)) = (slot, fluid) {
+                node.fluid = fluid;
+            }
+        }
+        for (pipe, previous) in self.pipes.iter_mut().zip(state.pipes) {
+            *pipe = previous;
+        }
+        if self.previous.is_some() {
+            self.previous = Some(self.snapshot());
+        }
+        true
+    }
+
+    /// Keeps the node state from before each tick so [`Self::interpolate`]
+    /// can blend between ticks.
+    pub fn set_interpolation(&mut self, enabled: bool) {
+        self.previous = enabled.then(|| self.snapshot());
+    }
+
+    /// Indexed by id, with `None` for removed nodes.
+    fn snapshot(&self) -> Vec<Option<NodeSnapshot>> {
+        self.nodes
+            .slots()
+            .iter()
+            .map(|slot| slot.as_ref().map(NodeSnapshot::from))
+            .collect()
+    }
+
+    /// Blends the node state from before the last tick (`alpha = 0`) with the
+    /// current state (`alpha = 1`), one per node in the order of
+    /// [`Nodes::iter`]. Without interpolation enabled this is just the
+    /// current state.
+    pub fn interpolate(&self, alpha: f32) -> Vec<NodeSnapshot> {
+        let previous = self.previous.as_deref().unwrap_or_default();
+        self.nodes
+            .iter_with_ids()
+            .map(|(id, node)| {
+                let current = NodeSnapshot::from(node);
+                // Nodes added since the last tick have nothing to blend from
+                match previous.get(id) {
+                    Some(Some(previous)) => previous.lerp(&current, alpha),
+                    _ => current,
+                }
+            })
+            .collect()
+    }
+
+    pub fn fluid_properties(&self) -> &FluidProperties {
+        &self.fluid_table.fluid_properties
+    }
+
+    pub fn set_fluid_properties(&mut self, fluid_properties: FluidProperties) {
+        self.fluid_table.fluid_properties = fluid_properties;
+    }
+
+    /// What the node holds, [`Self::fluid_properties`] unless set otherwise.
+    pub fn node_fluid_properties(&self, id: usize) -> &FluidProperties {
+        self.fluid_table.fluid_properties_of(id)
+    }
+
+    /// Fills the node with a different fluid, e.g. glycol in a collector loop
+    /// feeding an oil store. Returns `false` if the node doesn't exist.
+    pub fn set_node_fluid_properties(
+        &mut self,
+        id: usize,
+        fluid_properties: FluidProperties,
+    ) -> bool {
+        if !self.contains_node(id) {
+            return false;
+        }
+        self.fluid_table
+            .node_fluid_properties
+            .insert(id, fluid_properties);
+        true
+    }
+
+    pub fn node_model(&self, id: usize) -> NodeModel {
+        match self.strata.get(&id) {
+            Some(layers) => NodeModel::Stratified {
+                layers: layers.len(),
+            },
+            None => NodeModel::Mixed,
+        }
+    }
+
+    /// Switches how the node holds its fluid. Stratifying splits what it
+    /// holds into equal layers, and going back to mixed mixes them. Returns
+    /// `false` if the node doesn't exist.
+    pub fn set_node_model(&mut self, id: usize, model: NodeModel) -> bool {
+        let Some(node) = self.nodes.get(id) else {
+            return false;
+        };
+        match model {
+            NodeModel::Mixed => {
+                self.strata.remove(&id);
+            }
+            NodeModel::Stratified { layers } => {
+                self.strata.insert(id, strata::split(node.fluid, layers));
+            }
+        }
+        true
+    }
+
+    /// The node's layers from the bottom up, or just its fluid if it's mixed.
+    /// Empty if the node doesn't exist.
+    pub fn node_layers(&self, id: usize) -> &[Fluid] {
+        match (self.strata.get(&id), self.nodes.get(id)) {
+            (Some(layers), _) => layers,
+            (None, Some(node)) => core::slice::from_ref(&node.fluid),
+            (None, None) => &[],
+        }
+    }
+
+    pub fn tick_stages(&self) -> &TickStages {
+        &self.stages
+    }
+
+    /// Changes which stages run each tick, e.g. to disable heat loss while
+    /// looking at solar gain.
+    pub fn set_tick_stages(&mut self, stages: TickStages) {
+        self.stages = stages;
+    }
+
+    /// Simulated time so far. Unlike the node state, [`Self::step_back`]
+    /// doesn't rewind it.
+    pub fn clock(&self) -> SimClock {
+        self.clock
+    }
+
+    pub fn gravity(&self) -> f32 {
+        self.gravity
+    }
+
+    /// Defaults to [`STANDARD_GRAVITY`].
+    pub fn set_gravity(&mut self, gravity: f32) {
+        self.gravity = gravity;
+    }
+
+    /// Flow in L/s that `power` W of hydraulic power can lift `head` m, for
+    /// fluid at `temp`. From `power = density * gravity * flow * head`, so
+    /// `head` should be positive.
+    pub fn pump_flow_rate(&self, power: f32, head: f32, temp: f32) -> f32 {
+        let density = self.fluid_table.fluid_properties.density.get(temp);
+        power / (density * self.gravity * head)
+    }
+
+    pub fn integrator(&self) -> Integrator {
+        self.integrator
+    }
+
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        self.integrator = integrator;
+    }
+
+    /// Runs `hook` at the start of every tick, before any of the physics.
+    /// For thermostats and other switching on temperatures, see
+    /// [`Self::add_controller`] instead.
+    pub fn add_pre_tick_hook(
+        &mut self,
+        hook: impl FnMut(&mut Simulation, &Environment) + Send + Sync + 'static,
+    ) {
+        self.hooks.pre_tick.push(Box::new(hook));
+    }
+
+    /// Runs `hook` at the end of every tick. Useful for recording results.
+    pub fn add_post_tick_hook(
+        &mut self,
+        hook: impl FnMut(&mut Simulation, &Environment) + Send + Sync + 'static,
+    ) {
+        self.hooks.post_tick.push(Box::new(hook));
+    }
+
+    /// Calls `callback` with the finished state and the `dt` used after every
+    /// tick, once the clock has moved on too. Unlike hooks, callbacks can
+    /// only look, which suits loggers, alarms and UI updates. They're called
+    /// in the order they were added.
+    pub fn on_tick(&mut self, callback: impl FnMut(&Simulation, f32) + Send + Sync + 'static) {
+        self.hooks.on_tick.push(Box::new(callback));
+    }
+
+    /// Advances the simulation by `dt` seconds.
+    ///
+    /// Ticks are deterministic: the same state, `environment` and `dt` always
+    /// produce bit-identical results. Nothing reads a clock or iterates in an
+    /// unspecified order, so replays and lockstep runs stay in sync as long
+    /// as any hooks are deterministic too.
+    pub fn tick(&mut self, environment: &Environment, dt: f32) {
+        if self.previous.is_some() {
+            self.previous = Some(self.snapshot());
+        }
+        if self.history_len > 0 {
+            if self.history.len() == self.history_len {
+                self.history.pop_front();
+            }
+            self.history.push_back(TickState {
+                fluids: self
+                    .nodes
+                    .slots()
+                    .iter()
+                    .map(|slot| slot.as_ref().map(|node| node.fluid))
+                    .collect(),
+                pipes: self.pipes.clone(),
+            });
+        }
+
+        // Hooks get the simulation mutably, so they're moved out while running
+        let mut hooks = core::mem::take(&mut self.hooks);
+        for hook in &mut hooks.pre_tick {
+            hook(self, environment);
+        }
+
+        self.energy_deltas.clear();
+        self.energy_deltas
+            .extend(self.nodes.slots().iter().enumerate().map(|(id, slot)| {
+                let Some(node) = slot else {
+                    return 0.0;
+                };
+                let properties = self.fluid_table.fluid_properties_of(id);
+                -properties.thermal_energy(&node.fluid)
+            }));
+
+        // Hooks may have changed stratified nodes as a whole
+        self.sync_strata();
+        // Controllers switch once a tick, so substeps all run the same rates
+        let flow_rates = if self.stages.contains(TickStage::FluidTransfer) {
+            self.controlled_flow_rates()
+        } else {
+            Vec::new()
+        };
+        let substeps = self.substeps(dt);
+        let substep_dt = dt / substeps as f32;
+        for _ in 0..substeps {
+            for stage in 0..self.stages.order.len() {
+                match self.stages.order[stage] {
+                    TickStage::HeatLoss => self.handle_heat_losses(environment, substep_dt),
+                    TickStage::SolarPanels => self.handle_solar_panels(environment, substep_dt),
+                    TickStage::Extractors => self.handle_extractors(substep_dt),
+                    TickStage::FluidTransfer if self.stages.frozen_volumes => {
+                        self.handle_heat_exchange(&flow_rates, substep_dt)
+                    }
+                    TickStage::FluidTransfer => {
+                        self.handle_fluid_transfer(environment, &flow_rates, substep_dt)
+                    }
+                    TickStage::Conduction => self.handle_conduction(substep_dt),
+                }
+                // Before the next stage can spread the excess around
+                self.apply_temp_limits();
+                self.sync_strata();
+            }
+        }
+
+        let slots = self.nodes.slots();
+        for (id, (delta, slot)) in self.energy_deltas.iter_mut().zip(slots).enumerate() {
+            let Some(node) = slot else {
+                continue;
+            };
+            let properties = self.fluid_table.fluid_properties_of(id);
+            *delta += properties.thermal_energy(&node.fluid);
+        }
+
+        for hook in &mut hooks.post_tick {
+            hook(self, environment);
+        }
+
+        self.clock.advance(dt);
+        for callback in &mut hooks.on_tick {
+            callback(self, dt);
+        }
+        // Keep any hooks that were added while running
+        hooks.append(&mut self.hooks);
+        self.hooks = hooks;
+    }
+
+    /// The longest substep a tick runs, or `None` to only split ticks as much
+    /// as heat loss needs to stay stable.
+    pub fn max_substep_dt(&self) -> Option<f32> {
+        self.max_substep_dt
+    }
+
+    /// Splits each tick into substeps of at most `max_substep_dt`. Smaller
+    /// substeps are more accurate but cost a full pass over the nodes and
+    /// connections each. Values of 0 or less are ignored.
+    pub fn set_max_substep_dt(&mut self, max_substep_dt: Option<f32>) {
+        self.max_substep_dt = max_substep_dt.filter(|&max| max > 0.0);
+    }
+
+    /// How many substeps a tick of `dt` is split into, so that no node loses
+    /// more than [`MAX_LOSS_FACTOR`] of its difference to ambient in one and
+    /// none is longer than [`Self::max_substep_dt`].
+    fn substeps(&self, dt: f32) -> u32 {
+        if dt <= 0.0 || !dt.is_finite() {
+            return 1;
+        }
+        let loss_rate = if self.stages.contains(TickStage::HeatLoss) {
+            self.nodes
+                .iter()
+                .map(|node| 1.0 - node.insulation)
+                .fold(0.0, f32::max)
+        } else {
+            0.0
+        };
+        let for_stability = math::ceil(dt * loss_rate / MAX_LOSS_FACTOR);
+        let for_max_dt = self
+            .max_substep_dt
+            .map_or(1.0, |max_dt| math::ceil(dt / max_dt));
+        (for_stability.max(for_max_dt) as u32).clamp(1, MAX_SUBSTEPS)
+    }
+
+    fn apply_temp_limits(&mut self) {
+        for (id, node) in self.nodes.iter_mut_with_ids() {
+            if let Some(min_temp) = node.min_temp {
+                node.fluid.temp = node.fluid.temp.max(min_temp);
+            }
+            let Some(max_temp) = node.max_temp else {
+                continue;
+            };
+            if node.fluid.temp <= max_temp {
+                continue;
+            }
+
+            let properties = self.fluid_table.fluid_properties_of(id);
+            let vented = properties.heat_capacity(&node.fluid) * (node.fluid.temp - max_temp);
+            node.fluid.temp = max_temp;
+            log::trace!("Node {id} vented {vented} J");
+            *self.vented_energy.entry(id).or_default() += vented;
+        }
+    }
+
+    /// Passes changes stages made to stratified nodes as a whole on to their
+    /// layers. Nothing changes for nodes whose layers are already in line.
+    fn sync_strata(&mut self) {
+        for (id, layers) in &mut self.strata {
+            if let Some(node) = self.nodes.get(*id) {
+                strata::sync(layers, node.fluid);
+            }
+        }
+    }
+
+    /// Whether the per node stages run in parallel, which they do with the
+    /// `rayon` feature.
+    fn runs_parallel(&self) -> bool {
+        #[cfg(all(test, feature = "rayon"))]
+        return !self.force_serial;
+        #[cfg(not(all(test, feature = "rayon")))]
+        cfg!(feature = "rayon")
+    }
+
+    /// Per node, so it runs in parallel with the `rayon` feature.
+    fn handle_heat_losses(&mut self, environment: &Environment, dt: f32) {
+        let integrator = self.integrator;
+        let lose_heat = |node: &mut Node| lose_heat(node, environment, integrator, dt);
+        if self.runs_parallel() {
+            #[cfg(feature = "rayon")]
+            self.nodes
+                .slots_mut()
+                .par_iter_mut()
+                .flatten()
+                .for_each(lose_heat);
+        } else {
+            self.nodes.iter_mut().for_each(lose_heat);
+        }
+    }
+
+    /// Each node's panels only heat that node, so with the `rayon` feature
+    /// the nodes are heated in parallel. Stratified nodes only heat their
+    /// top layer.
+    fn handle_solar_panels(&mut self, environment: &Environment, dt: f32) {
+        if !environment.sun_enabled {
+            return;
+        }
+
+        // The same for all of them
+        let irradiance = environment.panel_irradiance();
+        let integrator = self.integrator;
+        let properties = |id: usize| self.fluid_table.fluid_properties_of(id);
+
+        if self.runs_parallel() {
+            #[cfg(feature = "rayon")]
+            self.nodes
+                .slots_mut()
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(id, slot)| {
+                    let Some(node) = slot else {
+                        return;
+                    };
+                    if self.strata.contains_key(&id) {
+                        return;
+                    }
+                    if let Some(panels) = self.solar_panels.get(&id) {
+                        let q = panel_heat(panels, irradiance, dt);
+                        gain_heat(&mut node.fluid, properties(id), q, integrator);
+                    }
+                });
+        } else {
+            for (id, panels) in &self.solar_panels {
+                // Skip panels left behind by a node that no longer exists
+                let Some(node) = self.nodes.get_mut(*id) else {
+                    continue;
+                };
+                if self.strata.contains_key(id) {
+                    continue;
+                }
+                let q = panel_heat(panels, irradiance, dt);
+                gain_heat(&mut node.fluid, properties(*id), q, integrator);
+            }
+        }
+
+        for (id, layers) in &mut self.strata {
+            let (Some(panels), Some(node)) = (self.solar_panels.get(id), self.nodes.get_mut(*id))
+            else {
+                continue;
+            };
+            if let Some(top) = strata::top(layers) {
+                let q = panel_heat(panels, irradiance, dt);
+                gain_heat(top, properties(*id), q, integrator);
+            }
+            strata::settle(layers);
+            node.fluid = strata::total(layers);
+        }
+    }
+
+    fn handle_extractors(&mut self, dt: f32) {
+        for (id, extractor) in &self.extractors {
+            let Some(node) = self.nodes.get_mut(*id) else {
+                continue;
+            };
+            let properties = self.fluid_table.fluid_properties_of(*id);
+
+            let q = extractor.power_draw * extractor.efficiency * dt;
+            let d_temp = properties.temp_change(&node.fluid, q);
+
+            node.fluid.temp -= d_temp;
+        }
+    }
+
+    /// Updates the controllers and returns the rate each connection runs at
+    /// this tick, which is 0 for connections they've stopped.
+    fn controlled_flow_rates(&mut self) -> Vec<f32> {
+        for state in self.controllers.iter_mut() {
+            state.running = state.controller.should_run(&self.nodes, state.running);
+        }
+        self.connections
+            .slots()
+            .iter()
+            .map(|slot| {
+                let Some(connection) = slot else {
+                    return 0.0;
+                };
+                let stopped = self
+                    .controllers
+                    .iter()
+                    .any(|state| !state.running && state.controller.controls(connection));
+                if stopped {
+                    0.0
+                } else {
+                    connection.flow_rate
+                }
+            })
+            .collect()
+    }
+
+    /// Moves fluid along each connection at its rate in `flow_rates`, see
+    /// [`Self::controlled_flow_rates`].
+    fn handle_fluid_transfer(&mut self, environment: &Environment, flow_rates: &[f32], dt: f32) {
+        // Sum up how much each node is asked to give this tick so that a node
+        // feeding several connections splits what it has proportionally
+        // instead of draining into whichever connection comes first.
+        let mut demand = vec![0.0; self.nodes.id_bound()];
+        for (id, connection) in self.connections.iter_with_ids() {
+            if is_valid_connection(&self.nodes, connection) {
+                let (source, _) = connection.source_and_sink();
+                demand[source] += flow_rates[id].abs() * dt;
+            }
+        }
+        let supply = self
+            .nodes
+            .slots()
+            .iter()
+            .map(|slot| slot.as_ref().map_or(0.0, |node| node.fluid.volume))
+            .collect::<Vec<_>>();
+
+        let flows = self
+            .realized_flows
+            .iter_mut()
+            .zip(&mut self.heat_fluxes)
+            .zip(&mut self.pipes)
+            .zip(flow_rates);
+        for (slot, (((realized_flow, heat_flux), pipe), flow_rate)) in
+            self.connections.slots().iter().zip(flows)
+        {
+            *realized_flow = 0.0;
+            *heat_flux = 0.0;
+            let Some(connection) = slot else {
+                continue;
+            };
+            if !is_valid_connection(&self.nodes, connection) {
+                continue;
+            }
+
+            let (source, sink) = connection.source_and_sink();
+            let requested = flow_rate.abs() * dt;
+            let amount_available = if demand[source] > supply[source] {
+                requested * supply[source] / demand[source]
+            } else {
+                requested
+            };
+            let space_available = self.nodes[sink].capacity - self.nodes[sink].fluid.volume;
+
+            // Whatever doesn't fit in the pipe pushes the oldest fluid out
+            let amount_transfered = amount_available.min(pipe.space() + space_available);
+
+            // Stratified nodes give up their coldest fluid from the bottom
+            let fluid_sent = match self.strata.get_mut(&source) {
+                Some(layers) => {
+                    let fluid_sent = strata::draw(layers, amount_transfered);
+                    self.nodes[source].fluid = strata::total(layers);
+                    fluid_sent
+                }
+                None => {
+                    self.nodes[source].fluid.volume -= amount_transfered;
+                    Fluid {
+                        temp: self.nodes[source].fluid.temp,
+                        volume: amount_transfered,
+                    }
+                }
+            };
+            let mut fluid_transferred = if source == connection.input {
+                pipe.push(fluid_sent)
+            } else {
+                pipe.push_reversed(fluid_sent)
+            };
+            // Stops at ambient however large the step
+            let cooling =
+                (connection.heat_loss_coeff * connection.length.unwrap_or(1.0) * dt).min(1.0);
+            fluid_transferred.temp += (environment.ambient_temp - fluid_transferred.temp) * cooling;
+
+            // What's in the pipe is taken to be the source's fluid
+            let source_properties = self.fluid_table.fluid_properties_of(source);
+            let sink_properties = self.fluid_table.fluid_properties_of(sink);
+            match self.strata.get_mut(&sink) {
+                Some(layers) => {
+                    strata::add_inflow(
+                        layers,
+                        sink_properties,
+                        source_properties,
+                        fluid_transferred,
+                    );
+                    self.nodes[sink].fluid = strata::total(layers);
+                }
+                None => {
+                    let sink = &mut self.nodes[sink].fluid;
+                    *sink = sink_properties.mix(*sink, source_properties, fluid_transferred);
+                }
+            }
+
+            if dt > 0.0 {
+                // Negative when flowing from the output to the input
+                let direction = connection.flow_rate.signum();
+                *realized_flow = direction * amount_transfered / dt;
+                *heat_flux = direction * source_properties.thermal_energy(&fluid_sent) / dt;
+            }
+        }
+    }
+
+    fn handle_conduction(&mut self, dt: f32) {
+        for connection in self.connections.iter() {
+            if connection.conductance <= 0.0 || !is_valid_connection(&self.nodes, connection) {
+                continue;
+            }
+
+            let input = self.nodes[connection.input].fluid;
+            let output = self.nodes[connection.output].fluid;
+            let input_capacity = self
+                .fluid_table
+                .fluid_properties_of(connection.input)
+                .heat_capacity(&input);
+            let output_capacity = self
+                .fluid_table
+                .fluid_properties_of(connection.output)
+                .heat_capacity(&output);
+            if input_capacity <= 0.0 || output_capacity <= 0.0 {
+                continue;
+            }
+
+            // Moving more than this would push them past equilibrium
+            let d_temp = input.temp - output.temp;
+            let q_max =
+                d_temp * input_capacity * output_capacity / (input_capacity + output_capacity);
+            let q = connection.conductance * d_temp * dt;
+            let q = if q.abs() > q_max.abs() { q_max } else { q };
+
+            self.nodes[connection.input].fluid.temp -= q / input_capacity;
+            self.nodes[connection.output].fluid.temp += q / output_capacity;
+        }
+    }
+
+    /// mL in the nodes and in transit along connections. Ticks only move
+    /// fluid around, so this stays the same.
+    pub fn total_volume(&self) -> f32 {
+        let in_nodes = self.nodes.iter().map(|node| node.fluid.volume).sum::<f32>();
+        let in_transit = self.pipes.iter().map(Pipe::held).sum::<f32>();
+        in_nodes + in_transit
+    }
+
+    /// J relative to 0 deg C in the nodes and in transit along connections.
+    /// Only heat loss, solar panels, extractors and temperature limits change
+    /// this, so it's handy for checking the rest of the physics.
+    pub fn total_thermal_energy(&self) -> f32 {
+        let in_nodes = self
+            .nodes
+            .iter_with_ids()
+            .map(|(id, node)| {
+                self.fluid_table
+                    .fluid_properties_of(id)
+                    .thermal_energy(&node.fluid)
+            })
+            .sum::<f32>();
+        // Fluid in a pipe is taken to be its input's
+        let in_transit = self
+            .connections
+            .iter_with_ids()
+            .filter_map(|(id, connection)| Some((connection, self.pipes.get(id)?)))
+            .flat_map(|(connection, pipe)| {
+                let properties = self.fluid_table.fluid_properties_of(connection.input);
+                pipe.contents
+                    .iter()
+                    .map(move |fluid| properties.thermal_energy(fluid))
+            })
+            .sum::<f32>();
+        in_nodes + in_transit
+    }
+
+    pub fn metrics(&self, environment: &Environment) -> SimMetrics {
+        let total_volume = self.nodes.iter().map(|node| node.fluid.volume).sum::<f32>();
+        let weighted_temp = self
+            .nodes
+            .iter()
+            .map(|node| node.fluid.volume * node.fluid.temp)
+            .sum::<f32>();
+        let thermal_energy = self
+            .nodes
+            .iter_with_ids()
+            .map(|(id, node)| {
+                self.fluid_table
+                    .fluid_properties_of(id)
+                    .thermal_energy(&node.fluid)
+            })
+            .sum::<f32>();
+        let max_temp = self
+            .nodes
+            .iter()
+            .filter(|node| node.fluid.volume > 0.0)
+            .map(|node| node.fluid.temp)
+            .reduce(f32::max);
+
+        SimMetrics {
+            node_count: self.nodes.len(),
+            total_volume,
+            thermal_energy,
+            average_temp: (total_volume > 0.0).then(|| weighted_temp / total_volume),
+            max_temp,
+            sun_angle: environment.sun_angle,
+        }
+    }
+
+    fn handle_heat_exchange(&mut self, flow_rates: &[f32], dt: f32) {
+        let flows = self
+            .realized_flows
+            .iter_mut()
+            .zip(&mut self.heat_fluxes)
+            .zip(flow_rates);
+        for (slot, ((realized_flow, heat_flux), flow_rate)) in
+            self.connections.slots().iter().zip(flows)
+        {
+            *realized_flow = 0.0;
+            *heat_flux = 0.0;
+            let Some(connection) = slot else {
+                continue;
+            };
+            if !is_valid_connection(&self.nodes, connection) {
+                continue;
+            }
+
+            let input = self.nodes[connection.input].fluid;
+            let output = self.nodes[connection.output].fluid;
+            let exchanged = (flow_rate.abs() * dt).min(input.volume).min(output.volume);
+            if exchanged <= 0.0 {
+                continue;
+            }
+
+            let input_properties = self.fluid_table.fluid_properties_of(connection.input);
+            let output_properties = self.fluid_table.fluid_properties_of(connection.output);
+            let swap = |fluid: Fluid, properties: &FluidProperties, temp, other_properties| {
+                let kept = Fluid {
+                    volume: fluid.volume - exchanged,
+                    ..fluid
+                };
+                let incoming = Fluid {
+                    volume: exchanged,
+                    temp,
+                };
+                properties.mix(kept, other_properties, incoming).temp
+            };
+
+            // Swapping equal volumes, so the heat one node gains the other loses
+            self.nodes[connection.input].fluid.temp =
+                swap(input, input_properties, output.temp, output_properties);
+            self.nodes[connection.output].fluid.temp =
+                swap(output, output_properties, input.temp, input_properties);
+
+            if dt > 0.0 {
+                *realized_flow = connection.flow_rate.signum() * exchanged / dt;
+                // Only the difference crosses over when the volumes swap
+                let sent = |properties: &FluidProperties, temp| {
+                    let fluid = Fluid {
+                        volume: exchanged,
+                        temp,
+                    };
+                    properties.thermal_energy(&fluid)
+                };
+                *heat_flux = (sent(input_properties, input.temp)
+                    - sent(output_properties, output.temp))
+                    / dt;
+            }
+        }
+    }
+
+    /// The net energy in J the node gained (positive) or lost (negative) over
+    /// the last tick from solar gain, heat loss, extractors, conduction and
+    /// fluid moving in and out. `None` for nodes added since the last tick
+    /// or removed.
+    pub fn node_energy_delta(&self, id: usize) -> Option<f32> {
+        self.energy_deltas
+            .get(id)
+            .copied()
+            .filter(|_| self.contains_node(id))
+    }
+
+    /// Seconds until node `id` reaches `target`, found by ticking a copy of
+    /// the simulation by `dt`. `None` if the node doesn't exist or the
+    /// simulation settles first, e.g. when `target` is above the temperature
+    /// the node stagnates at. Controllers run in the copy as they would here,
+    /// but tick hooks aren't copied, so they don't.
+    pub fn estimate_time_to_temp(
+        &self,
+        id: usize,
+        target: f32,
+        environment: &Environment,
+        dt: f32,
+    ) -> Option<f32> {
+        let start = self.nodes.get(id)?.fluid.temp;
+        if start == target {
+            return Some(0.0);
+        }
+        if dt <= 0.0 {
+            return None;
+        }
+
+        let heating = target > start;
+        let mut sim = self.clone();
+        sim.set_history_len(0);
+        let settling = sim.tick_until_settled(environment, dt, MAX_ESTIMATE_TICKS, |sim| {
+            let temp = sim.nodes[id].fluid.temp;
+            (heating && temp >= target) || (!heating && temp <= target)
+        });
+        match settling {
+            Settling::Done(ticks) => Some(ticks as f32 * dt),
+            Settling::Settled | Settling::OutOfTicks => None,
+        }
+    }
+
+    /// Sets every node to roughly the temperature it would settle at, so
+    /// demos don't start with a long warm up. Only temperatures change, and
+    /// calling it again leaves them about where they are.
+    pub fn warm_start(&mut self, environment: &Environment) {
+        self.warm_start_for(environment, MAX_WARM_START_TICKS);
+    }
+
+    /// Like [`Self::warm_start`], but gives up after `max_ticks` so the work
+    /// can be spread out, e.g. over frames. Each call picks up from the
+    /// temperatures the last one left. Returns whether they settled.
+    pub fn warm_start_for(&mut self, environment: &Environment, max_ticks: u32) -> bool {
+        // Relaxes a copy until nothing changes, which avoids solving the
+        // coupled system directly
+        let mut sim = self.clone();
+        sim.set_history_len(0);
+        let settling = sim.tick_until_settled(environment, WARM_START_DT, max_ticks, |_| false);
+        for (node, settled) in self.nodes.iter_mut().zip(&sim.nodes) {
+            node.fluid.temp = settled.fluid.temp;
+        }
+        matches!(settling, Settling::Settled)
+    }
+
+    /// Ticks until `done` returns true, the node fluids stop changing or
+    /// `max_ticks` have run.
+    fn tick_until_settled(
+        &mut self,
+        environment: &Environment,
+        dt: f32,
+        max_ticks: u32,
+        mut done: impl FnMut(&Self) -> bool,
+    ) -> Settling {
+        let mut previous = Vec::with_capacity(self.nodes.id_bound());
+        for ticks in 1..=max_ticks {
+            previous.clear();
+            previous.extend(self.nodes.iter().map(|node| node.fluid));
+            self.tick(environment, dt);
+
+            if done(self) {
+                return Settling::Done(ticks);
+            }
+            if self
+                .nodes
+                .iter()
+                .map(|node| node.fluid)
+                .eq(previous.iter().copied())
+            {
+                return Settling::Settled;
+            }
+        }
+        Settling::OutOfTicks
+    }
+
+    /// The min and max corners of the box around every node's position, or
+    /// `None` if there are no nodes.
+    pub fn bounding_box(&self) -> Option<(glam::Vec3, glam::Vec3)> {
+        self.nodes.iter().fold(None, |bounds, node| match bounds {
+            Some((min, max)) => Some((node.position.min(min), node.position.max(max))),
+            None => Some((node.position, node.position)),
+        })
+    }
+
+    /// False for ids of removed nodes.
+    pub fn contains_node(&self, id: usize) -> bool {
+        self.nodes.contains(id)
+    }
+
+    /// Groups of nodes joined by connections, whichever way they flow. Each
+    /// group is in ascending order and the groups are ordered by their first
+    /// node. A node without connections is a group of its own.
+    pub fn connected_components(&self) -> Vec<Vec<usize>> {
+        // Union-find, with each node pointing towards its group's root
+        fn root(parents: &mut [usize], mut id: usize) -> usize {
+            while parents[id] != id {
+                parents[id] = parents[parents[id]];
+                id = parents[id];
+            }
+            id
+        }
+        let mut parents = (0..self.nodes.id_bound()).collect::<Vec<_>>();
+        for connection in self.connections.iter() {
+            if !self.contains_node(connection.input) || !self.contains_node(connection.output) {
+                continue;
+            }
+            let a = root(&mut parents, connection.input);
+            let b = root(&mut parents, connection.output);
+            // The lower root wins so roots are each group's first node
+            parents[a.max(b)] = a.min(b);
+        }
+
+        let mut components = Vec::<Vec<usize>>::new();
+        let mut component_of_root = Map::new();
+        for id in self.nodes.ids() {
+            let root = root(&mut parents, id);
+            let component = *component_of_root.entry(root).or_insert_with(|| {
+                components.push(Vec::new());
+                components.len() - 1
+            });
+            components[component].push(id);
+        }
+        components
+    }
+
+    /// Whether every node can reach every other through connections. True
+    /// without any nodes.
+    pub fn is_fully_connected(&self) -> bool {
+        self.connected_components().len() <= 1
+    }
+}
+
+/// Moves the node's temperature towards ambient, see
+/// [`Simulation::handle_heat_losses`].
+fn lose_heat(node: &mut Node, environment: &Environment, integrator: Integrator, dt: f32) {
+    // Substepping keeps this below 1, but past the substep cap it stops at
+    // ambient rather than overshooting
+    let factor = ((1.0 - node.insulation) * dt).min(MAX_LOSS_FACTOR);
+    let delta = |temp: f32| (environment.ambient_temp - temp) * factor;
+    let d_temp = delta(node.fluid.temp);
+    node.fluid.temp += match integrator {
+        Integrator::Euler => d_temp,
+        Integrator::Rk2 => 0.5 * (d_temp + delta(node.fluid.temp + d_temp)),
+    };
+}
+
+/// J `panels` collect over `dt` under `irradiance` W/m².
+fn panel_heat(panels: &[SolarPanel], irradiance: f32, dt: f32) -> f32 {
+    let collected = panels
+        .iter()
+        .map(|panel| panel.area * panel.efficiency)
+        .sum::<f32>();
+    irradiance * collected * dt
+}
+
+/// Adds `q` J to `fluid`. Empty nodes have nothing to heat.
+fn gain_heat(fluid: &mut Fluid, properties: &FluidProperties, q: f32, integrator: Integrator) {
+    if fluid.volume == 0.0 {
+        return;
+    }
+
+    let d_temp = properties.temp_change(fluid, q);
+    fluid.temp += match integrator {
+        Integrator::Euler => d_temp,
+        // Only differs from Euler when the heat capacity depends on temperature
+        Integrator::Rk2 => {
+            let end = Fluid {
+                temp: fluid.temp + d_temp,
+                ..*fluid
+            };
+            0.5 * (d_temp + properties.temp_change(&end, q))
+        }
+    };
+}
+
+fn is_valid_connection(nodes: &Nodes, connection: &Connection) -> bool {
+    nodes.contains(connection.input)
+        && nodes.contains(connection.output)
+        && connection.input != connection.output
+}
+
+/// Aggregate stats for the whole simulation, see [`Simulation::metrics`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimMetrics {
+    pub node_count: usize,
+    /// mL
+    pub total_volume: f32,
+    /// J, relative to 0 deg C
+    pub thermal_energy: f32,
+    /// Volume weighted, so it's the temperature everything would settle at if
+    /// mixed together. `None` if there's no fluid.
+    pub average_temp: Option<f32>,
+    pub max_temp: Option<f32>,
+    /// Radians
+    pub sun_angle: f32,
+}
+
+impl fmt::Display for SimMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes, {:.0} mL, {:.1} kJ",
+            self.node_count,
+            self.total_volume,
+            self.thermal_energy / 1000.0,
+        )?;
+        if let (Some(average_temp), Some(max_temp)) = (self.average_temp, self.max_temp) {
+            write!(f, ", avg {average_temp:.1} C, max {max_temp:.1} C")?;
+        }
+        write!(f, ", sun {:.0} deg", self.sun_angle.to_degrees())
+    }
+}
+
+pub struct IterConnections<'a> {
+    simulation: &'a Simulation,
+    index: usize,
+}
+
+impl<'a> Iterator for IterConnections<'a> {
+    type Item = (f32, &'a Node, &'a Node);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let simulation = self.simulation;
+        while let Some(slot) = simulation.connections.slots().get(self.index) {
+            self.index += 1;
+
+            // Skip removed connections and ones to nodes that don't exist
+            let Some(connection) = slot else {
+                continue;
+            };
+            if simulation.contains_node(connection.input)
+                && simulation.contains_node(connection.output)
+            {
+                return Some((
+                    connection.flow_rate,
+                    &simulation.nodes[connection.input],
+                    &simulation.nodes[connection.output],
+                ));
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fluid {
+    pub volume: f32,
+    pub temp: f32,
+}
+
+impl Add for Fluid {
+    type Output = Fluid;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        let volume = self.volume + rhs.volume;
+        // Mixing nothing with nothing shouldn't change the temperature, and
+        // rounding with very small volumes must not push the result outside
+        // the range of the inputs.
+        let temp = if volume == 0.0 {
+            self.temp
+        } else {
+            let t = rhs.volume / volume;
+            (self.temp + (rhs.temp - self.temp) * t)
+                .max(self.temp.min(rhs.temp))
+                .min(self.temp.max(rhs.temp))
+        };
+
+        Self { volume, temp }
+    }
+}
+
+impl AddAssign for Fluid {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Node {
+    pub fluid: Fluid,
+    pub capacity: f32,
+    pub insulation: f32,
+    pub position: glam::Vec3,
+    /// Set with [`Simulation::set_node_name`].
+    pub name: Option<String>,
+    /// Multiplied with the temperature color when drawn, e.g. to tell a
+    /// glycol loop from a water one. White by default.
+    pub tint: glam::Vec3,
+    /// Set with [`Simulation::set_node_temp_limits`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub min_temp: Option<f32>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_temp: Option<f32>,
+}
+
+/// What [`Simulation::add_node_desc`] creates a node from. Fill in what
+/// matters and take the rest from `Default`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeDesc {
+    /// mL
+    pub volume: f32,
+    pub temp: f32,
+    pub insulation: f32,
+    /// mL
+    pub capacity: f32,
+    pub position: glam::Vec3,
+    pub tint: glam::Vec3,
+    pub min_temp: Option<f32>,
+    pub max_temp: Option<f32>,
+}
+
+impl Default for NodeDesc {
+    /// A small, well insulated tank at room temperature.
+    fn default() -> Self {
+        Self {
+            volume: 10.0,
+            temp: 20.0,
+            insulation: 0.9,
+            capacity: 100.0,
+            position: glam::Vec3::ZERO,
+            tint: glam::Vec3::ONE,
+            min_temp: None,
+            max_temp: None,
+        }
+    }
+}
+
+/// The parts of a [`Node`] the renderer cares about.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeSnapshot {
+    pub volume: f32,
+    pub temp: f32,
+    pub position: glam::Vec3,
+}
+
+impl NodeSnapshot {
+    pub fn lerp(&self, other: &Self, alpha: f32) -> Self {
+        Self {
+            volume: self.volume + (other.volume - self.volume) * alpha,
+            temp: self.temp + (other.temp - self.temp) * alpha,
+            position: self.position.lerp(other.position, alpha),
+        }
+    }
+}
+
+impl From<&Node> for NodeSnapshot {
+    fn from(node: &Node) -> Self {
+        Self {
+            volume: node.fluid.volume,
+            temp: node.fluid.temp,
+            position: node.position,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Connection {
+    /// mL/s from `input` to `output`, or the other way when negative.
+    pub flow_rate: f32,
+    pub input: usize,
+    pub output: usize,
+    /// W / deg C, see [`Simulation::set_connection_conductance`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub conductance: f32,
+    /// 1 / (m s), see [`Simulation::set_connection_heat_loss`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub heat_loss_coeff: f32,
+    /// In m, taken as 1 when unset.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub length: Option<f32>,
+}
+
+impl Connection {
+    /// The node fluid leaves from and the one it arrives at.
+    fn source_and_sink(&self) -> (usize, usize) {
+        if self.flow_rate < 0.0 {
+            (self.output, self.input)
+        } else {
+            (self.input, self.output)
+        }
+    }
+}
+
+/// What each node holds, apart from the nodes so it can be looked up while
+/// they change.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct FluidTable {
+    fluid_properties: FluidProperties,
+    /// Nodes holding something other than `fluid_properties`.
+    node_fluid_properties: Map<usize, FluidProperties>,
+}
+
+impl FluidTable {
+    fn fluid_properties_of(&self, id: usize) -> &FluidProperties {
+        self.node_fluid_properties
+            .get(&id)
+            .unwrap_or(&self.fluid_properties)
+    }
+}
+
+/// Why [`Simulation::tick_until_settled`] stopped.
+enum Settling {
+    /// `done` returned true after this many ticks.
+    Done(u32),
+    /// The node fluids stopped changing.
+    Settled,
+    OutOfTicks,
+}
+
+/// What [`Simulation::step_back`] needs to undo a tick.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct TickState {
+    /// Indexed by id, with `None` for removed nodes.
+    fluids: Vec<Option<Fluid>>,
+    pipes: Vec<Pipe>,
+}
+
+/// The fluid inside a connection, from the input end to the output end.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Pipe {
+    volume: f32,
+    contents: VecDeque<Fluid>,
+}
+
+impl Pipe {
+    fn held(&self) -> f32 {
+        self.contents.iter().map(|fluid| fluid.volume).sum()
+    }
+
+    fn space(&self) -> f32 {
+        (self.volume - self.held()).max(0.0)
+    }
+
+    /// Adds `fluid` at the input end and returns what overflows out the
+    /// output end.
+    fn push(&mut self, fluid: Fluid) -> Fluid {
+        if self.volume <= 0.0 && self.contents.is_empty() {
+            return fluid;
+        }
+
+        if fluid.volume > 0.0 {
+            self.contents.push_back(fluid);
+        }
+
+        let mut overflow = Fluid {
+            volume: 0.0,
+            temp: 0.0,
+        };
+        let mut excess = self.held() - self.volume;
+        while excess > 0.0 {
+            let Some(front) = self.contents.front_mut() else {
+                break;
+            };
+            if front.volume <= excess {
+                excess -= front.volume;
+                overflow += *front;
+                self.contents.pop_front();
+            } else {
+                front.volume -= excess;
+                overflow += Fluid {
+                    volume: excess,
+                    temp: front.temp,
+                };
+                excess = 0.0;
+            }
+        }
+        overflow
+    }
+
+    /// Like [`Self::push`] for flow going from the output to the input.
+    fn push_reversed(&mut self, fluid: Fluid) -> Fluid {
+        self.contents.make_contiguous().reverse();
+        let overflow = self.push(fluid);
+        self.contents.make_contiguous().reverse();
+        overflow
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SolarPanel {
+    pub area: f32,
+    pub efficiency: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Extractor {
+    pub power_draw: f32,
+    pub efficiency: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_fluid_add() {
+        let a = Fluid {
+            volume: 1.0,
+            temp: 30.0,
+        };
+        let b = Fluid {
+            volume: 2.0,
+            temp: 90.0,
+        };
+
+        assert_eq!(
+            a + b,
+            Fluid {
+                volume: 3.0,
+                temp: 70.0
+            }
+        );
+    }
+
+    #[test]
+    fn test_heat_loss() {
+        let environment = Environment::default();
+        let mut simulation = Simulation::new();
+
+        let num_nodes = 10;
+        let nodes = (0..num_nodes)
+            .map(|i| {
+                let x = i as f32;
+                simulation.add_node(x, x * 10.0, 0.9, 100.0, glam::Vec3 { x, y: 0.0, z: 0.0 })
+            })
+            .collect::<Vec<_>>();
+
+        let original_sim = simulation.clone();
+
+        simulation.handle_heat_losses(&environment, 0.1);
+
+        for node in nodes {
+            let original = original_sim.get_node(node).unwrap();
+            let updated = simulation.get_node(node).unwrap();
+
+            assert_eq!(original.fluid.volume, updated.fluid.volume);
+
+            if original.fluid.temp < environment.ambient_temp {
+                assert!(original.fluid.temp < updated.fluid.temp);
+            } else if original.fluid.temp > environment.ambient_temp {
+                assert!(original.fluid.temp > updated.fluid.temp);
+            } else {
+                assert_eq!(original.fluid.temp, updated.fluid.temp);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fluid_transfer() {
+        let mut sim = Simulation::new();
+
+        let a = sim.add_node(10.0, 100.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let b = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+
+        sim.connect_node(a, b, 1.0);
+
+        let original = sim.clone();
+
+        let flow_rates = sim.controlled_flow_rates();
+        sim.handle_fluid_transfer(&Environment::default(), &flow_rates, 1.0);
+
+        assert!(
+            original.get_node(a).unwrap().fluid.volume > sim.get_node(a).unwrap().fluid.volume,
+            "{}",
+            sim.get_node(a).unwrap().fluid.volume
+        );
+        assert!(
+            original.get_node(b).unwrap().fluid.volume < sim.get_node(b).unwrap().fluid.volume,
+            "{}",
+            sim.get_node(a).unwrap().fluid.volume
+        );
+    }
+
+    #[test]
+    fn test_fluid_transfer_proportional_split() {
+        let mut sim = Simulation::new();
+
+        let source = sim.add_node(10.0, 50.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let a = sim.add_node(0.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let b = sim.add_node(0.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+
+        // 40 mL requested from a node that only holds 10 mL
+        sim.connect_node(source, a, 10.0);
+        sim.connect_node(source, b, 30.0);
+
+        let flow_rates = sim.controlled_flow_rates();
+        sim.handle_fluid_transfer(&Environment::default(), &flow_rates, 1.0);
+
+        assert_eq!(sim.get_node(source).unwrap().fluid.volume, 0.0);
+        assert_eq!(sim.get_node(a).unwrap().fluid.volume, 2.5);
+        assert_eq!(sim.get_node(b).unwrap().fluid.volume, 7.5);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_fluid_add_invariants(
+            a_volume in 0.0f32..1.0e6,
+            a_temp in -1.0e3f32..1.0e4,
+            b_volume in 0.0f32..1.0e6,
+            b_temp in -1.0e3f32..1.0e4,
+        ) {
+            check_fluid_add(a_volume, a_temp, b_volume, b_temp)?;
+        }
+
+        #[test]
+        fn test_fluid_add_tiny_volumes(
+            a_volume in proptest::num::f32::POSITIVE | proptest::num::f32::ZERO | proptest::num::f32::SUBNORMAL,
+            a_temp in -1.0e3f32..1.0e4,
+            b_volume in proptest::num::f32::POSITIVE | proptest::num::f32::ZERO | proptest::num::f32::SUBNORMAL,
+            b_temp in -1.0e3f32..1.0e4,
+        ) {
+            proptest::prop_assume!((a_volume + b_volume).is_finite());
+            check_fluid_add(a_volume, a_temp, b_volume, b_temp)?;
+        }
+    }
+
+    fn check_fluid_add(
+        a_volume: f32,
+        a_temp: f32,
+        b_volume: f32,
+        b_temp: f32,
+    ) -> Result<(), proptest::test_runner::TestCaseError> {
+        let a = Fluid {
+            volume: a_volume,
+            temp: a_temp,
+        };
+        let b = Fluid {
+            volume: b_volume,
+            temp: b_temp,
+        };
+        let c = a + b;
+
+        proptest::prop_assert_eq!(c.volume, a_volume + b_volume);
+        proptest::prop_assert!(
+            c.temp >= a_temp.min(b_temp) && c.temp <= a_temp.max(b_temp),
+            "{a:?} + {b:?} = {c:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_connection_equality() {
+        let mut sim = Simulation::new();
+
+        let a = sim.add_node(10.0, 100.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let b = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+
+        sim.connect_node(a, b, 1.0);
+        sim.connect_node(a, b, 1.0);
+        sim.connect_node(b, a, 1.0);
+
+        assert_eq!(sim.connections[0], sim.connections[1]);
+        assert_ne!(sim.connections[0], sim.connections[2]);
+        assert_eq!(sim.nodes()[0], sim.clone().nodes()[0]);
+    }
+
+    #[test]
+    fn test_environment_display() {
+        let environment = Environment {
+            sun_angle: f32::consts::FRAC_PI_4,
+            cloud_cover: 0.2,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            environment.to_string(),
+            "sun 45 deg, 1000 W/m2, 20% cloud, 20 C ambient"
+        );
+    }
+
+    #[test]
+    fn test_sun_angle_wraps() {
+        let mut environment = Environment::default();
+
+        environment.set_sun_angle(0.0);
+        for _ in 0..5 {
+            environment.advance_sun_angle(f32::consts::FRAC_PI_2);
+        }
+        assert!((environment.sun_angle() - f32::consts::FRAC_PI_2).abs() < 1e-5);
+
+        environment.set_sun_angle(-f32::consts::FRAC_PI_2);
+        assert!((environment.sun_angle() - 3.0 * f32::consts::FRAC_PI_2).abs() < 1e-5);
+
+        environment.set_sun_angle(-1e-9);
+        assert!(environment.sun_angle() < f32::consts::TAU);
+    }
+
+    #[test]
+    fn test_environment_rejects_non_finite() {
+        let mut environment = Environment::default();
+        let original = environment.sun_angle();
+
+        environment.set_sun_angle(f32::NAN);
+        environment.advance_sun_angle(f32::INFINITY);
+        environment.set_cloud_cover(f32::NAN);
+        environment.set_ambient_temp(f32::NEG_INFINITY);
+        environment.set_sun_irradiance(f32::NAN);
+
+        assert_eq!(environment.sun_angle(), original);
+        assert_eq!(environment.cloud_cover(), 0.0);
+        assert_eq!(environment.ambient_temp(), 20.0);
+        assert_eq!(environment.sun_irradiance(), 1000.0);
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let mut sim = Simulation::new();
+        let a = sim.add_node(10.0, 100.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let b = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::X);
+        sim.connect_node(a, b, 1.0);
+        sim.set_interpolation(true);
+
+        let before = sim.interpolate(1.0);
+        sim.tick(&Environment::default(), 1.0);
+        let after = sim.interpolate(1.0);
+        assert_ne!(before, after);
+
+        assert_eq!(sim.interpolate(0.0), before);
+        for ((mid, before), after) in sim.interpolate(0.5).iter().zip(&before).zip(&after) {
+            assert!((mid.temp - (before.temp + after.temp) * 0.5).abs() < 1e-4);
+            assert!((mid.volume - (before.volume + after.volume) * 0.5).abs() < 1e-4);
+            assert_eq!(mid.position, before.position);
+        }
+    }
+
+    #[test]
+    fn test_realized_flow() {
+        let mut sim = Simulation::new();
+        let source = sim.add_node(5.0, 50.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let sink = sim.add_node(0.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+        sim.connect_node(source, sink, 10.0);
+        assert_eq!(sim.realized_flows(), &[0.0]);
+
+        // Only 5 mL is available, so the 10 mL/s connection is halved
+        sim.tick(&Environment::default(), 1.0);
+        assert_eq!(sim.realized_flows(), &[5.0]);
+
+        // The source is now dry
+        sim.tick(&Environment::default(), 1.0);
+        assert_eq!(sim.realized_flows(), &[0.0]);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let mut sim = Simulation::new();
+        sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+        sim.add_node(30.0, 60.0, 1.0, 100.0, glam::Vec3::ZERO);
+        // Empty nodes don't count towards the max
+        sim.add_node(0.0, 90.0, 1.0, 100.0, glam::Vec3::ZERO);
+
+        let metrics = sim.metrics(&Environment::default());
+        assert_eq!(metrics.node_count, 3);
+        assert_eq!(metrics.total_volume, 40.0);
+        assert!((metrics.thermal_energy - 4.186 * (10.0 * 20.0 + 30.0 * 60.0)).abs() < 1e-2);
+        assert!((metrics.average_temp.unwrap() - 50.0).abs() < 1e-4);
+        assert_eq!(metrics.max_temp, Some(60.0));
+        assert_eq!(metrics.sun_angle, f32::consts::FRAC_PI_2);
+
+        let metrics = Simulation::new().metrics(&Environment::default());
+        assert_eq!(metrics.average_temp, None);
+        assert_eq!(metrics.max_temp, None);
+    }
+
+    #[test]
+    fn test_add_node_desc() {
+        let mut sim = Simulation::new();
+        let a = sim.add_node(10.0, 20.0, 0.9, 100.0, glam::Vec3::X);
+        let b = sim.add_node_desc(NodeDesc {
+            position: glam::Vec3::X,
+            ..Default::default()
+        });
+        assert_eq!(sim.nodes()[a], sim.nodes()[b]);
+
+        let c = sim.add_node_desc(NodeDesc {
+            temp: 60.0,
+            tint: glam::Vec3::X,
+            ..Default::default()
+        });
+        assert_eq!(sim.nodes()[c].fluid.temp, 60.0);
+        assert_eq!(sim.nodes()[c].tint, glam::Vec3::X);
+    }
+
+    #[test]
+    fn test_connected_nodes() {
+        let mut sim = Simulation::new();
+        for i in 0..4 {
+            sim.add_node(10.0, i as f32 * 10.0, 1.0, 100.0, glam::Vec3::ZERO);
+        }
+        sim.connect_node(0, 1, 1.0);
+        sim.connect_node(1, 2, 2.0);
+        sim.connect_node(2, 3, 3.0);
+        // Out of range, so never added
+        sim.connect_node(3, 4, 4.0);
+
+        assert_eq!(sim.connected_nodes().count(), 3);
+        let flows = sim
+            .connected_nodes()
+            .map(|(flow_rate, input, output)| (flow_rate, input.fluid.temp, output.fluid.temp))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            flows,
+            [(1.0, 0.0, 10.0), (2.0, 10.0, 20.0), (3.0, 20.0, 30.0)]
+        );
+    }
+
+    #[test]
+    fn test_connect_named() {
+        let mut sim = Simulation::new();
+        let a = sim.add_node(10.0, 100.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let b = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+        assert!(sim.set_node_name(a, "tank_a"));
+        assert!(sim.set_node_name(b, "tank_b"));
+        assert!(!sim.set_node_name(b, "tank_a"));
+        assert!(!sim.set_node_name(42, "tank_c"));
+
+        assert!(sim.connect_named("tank_a", "tank_b", 1.0));
+        assert!(!sim.connect_named("tank_a", "missing", 1.0));
+        assert_eq!(
+            sim.connections().iter().collect::<Vec<_>>(),
+            [&Connection {
+                flow_rate: 1.0,
+                input: a,
+                output: b,
+                conductance: 0.0,
+                heat_loss_coeff: 0.0,
+                length: None,
+            }]
+        );
+
+        // Renaming frees up the old name
+        assert!(sim.set_node_name(a, "hot_tank"));
+        assert_eq!(sim.node_by_name("hot_tank"), Some(a));
+        assert_eq!(sim.node_by_name("tank_a"), None);
+        assert_eq!(sim.nodes()[a].name.as_deref(), Some("hot_tank"));
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let mut sim = Simulation::new();
+        assert_eq!(sim.bounding_box(), None);
+
+        sim.add_node(10.0, 20.0, 1.0, 100.0, glam::vec3(1.0, -2.0, 0.5));
+        assert_eq!(
+            sim.bounding_box(),
+            Some((glam::vec3(1.0, -2.0, 0.5), glam::vec3(1.0, -2.0, 0.5)))
+        );
+
+        sim.add_node(10.0, 20.0, 1.0, 100.0, glam::vec3(-3.0, 4.0, 0.0));
+        sim.add_node(10.0, 20.0, 1.0, 100.0, glam::vec3(0.0, 0.0, 2.0));
+        assert_eq!(
+            sim.bounding_box(),
+            Some((glam::vec3(-3.0, -2.0, 0.0), glam::vec3(1.0, 4.0, 2.0)))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_tick_hooks() {
+        use std::sync::{Arc, Mutex};
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut sim = Simulation::new();
+        let node = sim.add_node(10.0, 100.0, 0.5, 100.0, glam::Vec3::ZERO);
+
+        let pre_calls = calls.clone();
+        sim.add_pre_tick_hook(move |sim, _| {
+            pre_calls
+                .lock()
+                .unwrap()
+                .push(("pre", sim.nodes()[node].fluid.temp));
+        });
+        let post_calls = calls.clone();
+        sim.add_post_tick_hook(move |sim, _| {
+            post_calls
+                .lock()
+                .unwrap()
+                .push(("post", sim.nodes()[node].fluid.temp));
+        });
+
+        sim.tick(&Environment::default(), 1.0);
+        sim.tick(&Environment::default(), 1.0);
+
+        let calls = calls.lock().unwrap();
+        let order = calls.iter().map(|(hook, _)| *hook).collect::<Vec<_>>();
+        assert_eq!(order, ["pre", "post", "pre", "post"]);
+        // The pre hook sees the state before heat loss, the post hook after
+        assert_eq!(calls[0].1, 100.0);
+        assert_eq!(calls[1].1, 60.0);
+        assert_eq!(calls[2].1, 60.0);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_on_tick() {
+        use std::sync::{Arc, Mutex};
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut sim = Simulation::new();
+        let node = sim.add_node(10.0, 100.0, 0.5, 100.0, glam::Vec3::ZERO);
+        for name in ["first", "second"] {
+            let calls = calls.clone();
+            sim.on_tick(move |sim, dt| {
+                let temp = sim.nodes()[node].fluid.temp;
+                calls
+                    .lock()
+                    .unwrap()
+                    .push((name, sim.clock().seconds(), dt, temp));
+            });
+        }
+
+        sim.tick(&Environment::default(), 1.0);
+        sim.tick(&Environment::default(), 0.5);
+
+        // Once per tick each, in order, seeing the state after the step
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                ("first", 1.0, 1.0, 60.0),
+                ("second", 1.0, 1.0, 60.0),
+                ("first", 1.5, 0.5, 50.0),
+                ("second", 1.5, 0.5, 50.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tick_stages() {
+        let mut sim = Simulation::new();
+        let node = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+        sim.attach_solar_panel(
+            node,
+            SolarPanel {
+                area: 1.0,
+                efficiency: 0.9,
+            },
+        );
+
+        sim.set_tick_stages(TickStages::default().without(TickStage::SolarPanels));
+        sim.tick(&Environment::default(), 1.0);
+        assert_eq!(sim.nodes()[node].fluid.temp, 20.0);
+
+        sim.set_tick_stages(TickStages::default());
+        sim.tick(&Environment::default(), 1.0);
+        assert!(sim.nodes()[node].fluid.temp > 20.0);
+
+        let stages = TickStages::new([
+            TickStage::FluidTransfer,
+            TickStage::HeatLoss,
+            TickStage::FluidTransfer,
+        ]);
+        assert_eq!(
+            stages.iter().collect::<Vec<_>>(),
+            [TickStage::FluidTransfer, TickStage::HeatLoss]
+        );
+        assert!(!stages.contains(TickStage::SolarPanels));
+    }
+
+    #[test]
+    fn test_frozen_volumes() {
+        let mut sim = Simulation::new();
+        let a = sim.add_node(10.0, 100.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let b = sim.add_node(30.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+        sim.connect_node(a, b, 1.0);
+        sim.set_tick_stages(TickStages::default().with_frozen_volumes(true));
+
+        for _ in 0..1000 {
+            sim.tick(&Environment::default(), 0.1);
+        }
+
+        assert_eq!(sim.nodes()[a].fluid.volume, 10.0);
+        assert_eq!(sim.nodes()[b].fluid.volume, 30.0);
+        // Settles where the mixed temperature would be
+        assert!((sim.nodes()[a].fluid.temp - 40.0).abs() < 1e-2);
+        assert!((sim.nodes()[b].fluid.temp - 40.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_add_nodes_bulk() {
+        let mut sim = Simulation::new();
+        let first = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+
+        let positions = (0..1000)
+            .map(|i| glam::vec3((i % 10) as f32, (i / 10) as f32, 0.0))
+            .collect::<Vec<_>>();
+        let range = sim.add_nodes_bulk(&positions, 5.0, 30.0, 0.9, 50.0);
+
+        assert_eq!(range, first + 1..first + 1001);
+        assert_eq!(sim.nodes().len(), 1001);
+        assert_eq!(
+            sim.nodes()[range.end - 1].position,
+            glam::vec3(9.0, 99.0, 0.0)
+        );
+        assert!(range.into_iter().all(|id| sim.nodes()[id].fluid
+            == Fluid {
+                volume: 5.0,
+                temp: 30.0
+            }));
+    }
+
+    #[test]
+    fn test_node_energy_delta() {
+        let mut sim = Simulation::new();
+        let panel = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let tank = sim.add_node(10.0, 80.0, 0.5, 100.0, glam::Vec3::ZERO);
+        sim.attach_solar_panel(
+            panel,
+            SolarPanel {
+                area: 1.0,
+                efficiency: 0.9,
+            },
+        );
+        assert_eq!(sim.node_energy_delta(panel), None);
+
+        // The default environment has the sun straight overhead
+        sim.tick(&Environment::default(), 1.0);
+        let gained = sim.node_energy_delta(panel).unwrap();
+        assert!((gained - 900.0).abs() < 0.1, "{gained}");
+        assert!(sim.node_energy_delta(tank).unwrap() < 0.0);
+    }
+
+    #[test]
+    fn test_stale_attachments() {
+        let mut sim = Simulation::new();
+        sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+        // Attaching checks the id, so fake a node having been removed
+        sim.solar_panels.insert(
+            42,
+            vec![SolarPanel {
+                area: 1.0,
+                efficiency: 0.9,
+            }],
+        );
+        sim.extractors.insert(
+            42,
+            Extractor {
+                power_draw: 100.0,
+                efficiency: 0.9,
+            },
+        );
+
+        sim.tick(&Environment::default(), 1.0);
+        assert_eq!(sim.nodes()[0].fluid.temp, 20.0);
+    }
+
+    #[test]
+    fn test_deterministic_tick() {
+        let mut a = Simulation::new();
+        let tank = a.add_node(50.0, 20.0, 0.9, 100.0, glam::Vec3::ZERO);
+        let pipe = a.add_node(5.0, 40.0, 0.5, 10.0, glam::Vec3::X);
+        let sink = a.add_node(0.0, 0.0, 0.8, 100.0, glam::Vec3::Y);
+        a.connect_node(tank, pipe, 2.0);
+        a.connect_node(pipe, sink, 3.0);
+        a.connect_node(sink, tank, 1.0);
+        a.attach_solar_panel(
+            pipe,
+            SolarPanel {
+                area: 2.0,
+                efficiency: 0.7,
+            },
+        );
+        a.attach_extractor(
+            tank,
+            Extractor {
+                power_draw: 50.0,
+                efficiency: 0.8,
+            },
+        );
+        let mut b = a.clone();
+
+        let mut environment = Environment::default();
+        environment.set_sun_angle(1.0);
+        for _ in 0..100 {
+            a.tick(&environment, 0.1);
+            b.tick(&environment, 0.1);
+        }
+
+        for (a, b) in a.nodes().iter().zip(b.nodes()) {
+            assert_eq!(a.fluid.volume.to_bits(), b.fluid.volume.to_bits());
+            assert_eq!(a.fluid.temp.to_bits(), b.fluid.temp.to_bits());
+        }
+        assert_eq!(a.realized_flows(), b.realized_flows());
+    }
+
+    #[test]
+    fn test_connection_transport_delay() {
+        let mut sim = Simulation::new();
+        let tank = sim.add_node(50.0, 80.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let radiator = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::X);
+        sim.connect_node(tank, radiator, 1.0);
+        assert!(sim.set_connection_volume(0, 3.0));
+        assert!(!sim.set_connection_volume(1, 3.0));
+
+        let environment = Environment::default();
+        for _ in 0..3 {
+            sim.tick(&environment, 1.0);
+            assert_eq!(sim.nodes()[radiator].fluid.temp, 20.0);
+            assert_eq!(sim.nodes()[radiator].fluid.volume, 10.0);
+        }
+        assert_eq!(sim.connection_in_transit(0), Some(3.0));
+        assert_eq!(sim.nodes()[tank].fluid.volume, 47.0);
+
+        sim.tick(&environment, 1.0);
+        assert!(sim.nodes()[radiator].fluid.temp > 20.0);
+        assert_eq!(sim.nodes()[radiator].fluid.volume, 11.0);
+        assert_eq!(sim.connection_in_transit(0), Some(3.0));
+    }
+
+    #[test]
+    fn test_rk2_heat_loss() {
+        let environment = Environment::default();
+        let ambient = environment.ambient_temp();
+        let (insulation, dt, ticks) = (0.8, 2.0, 5);
+
+        let error = |integrator| {
+            let mut sim = Simulation::new();
+            sim.set_integrator(integrator);
+            sim.add_node(10.0, 90.0, insulation, 100.0, glam::Vec3::ZERO);
+            for _ in 0..ticks {
+                sim.tick(&environment, dt);
+            }
+
+            let t = dt * ticks as f32;
+            let exact = ambient + (90.0 - ambient) * (-(1.0 - insulation) * t).exp();
+            (sim.nodes()[0].fluid.temp - exact).abs()
+        };
+
+        let euler = error(Integrator::Euler);
+        let rk2 = error(Integrator::Rk2);
+        assert!(rk2 < euler * 0.5, "rk2 {rk2} vs euler {euler}");
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_stages_match_serial() {
+        let environment = Environment::default();
+        let mut sim = Simulation::new();
+        sim.set_integrator(Integrator::Rk2);
+        for i in 0..50_000 {
+            let id = sim.add_node(
+                (i % 7) as f32,
+                (i % 100) as f32,
+                (i % 10) as f32 * 0.1,
+                100.0,
+                glam::Vec3::X * i as f32,
+            );
+            if i % 3 == 0 {
+                let panel = SolarPanel {
+                    area: (i % 4) as f32,
+                    efficiency: 0.5,
+                };
+                sim.attach_solar_panel(id, panel);
+            }
+            if i % 5 == 4 {
+                sim.connect_node(id - 1, id, 1.0);
+            }
+        }
+        sim.remove_node(42);
+
+        // The same ticks one node at a time
+        let start = sim.nodes.clone();
+        let mut serial = sim.clone();
+        serial.force_serial = true;
+        assert!(sim.runs_parallel() && !serial.runs_parallel());
+        for _ in 0..5 {
+            sim.tick(&environment, 0.5);
+            serial.tick(&environment, 0.5);
+        }
+        assert!(sim.nodes != start);
+        assert!(sim.nodes == serial.nodes);
+    }
+
+    #[test]
+    fn test_large_dt_substeps() {
+        let environment = Environment::default();
+        let ambient = environment.ambient_temp();
+
+        for integrator in [Integrator::Euler, Integrator::Rk2] {
+            let mut sim = Simulation::new();
+            sim.set_integrator(integrator);
+            sim.add_node(10.0, 90.0, 0.1, 100.0, glam::Vec3::ZERO);
+            sim.add_node(10.0, -10.0, 0.1, 100.0, glam::Vec3::X);
+
+            // Without substeps the first tick would land far on the other side
+            let mut previous = [90.0, -10.0];
+            for _ in 0..10 {
+                sim.tick(&environment, 60.0);
+                let temps = [sim.nodes()[0].fluid.temp, sim.nodes()[1].fluid.temp];
+                assert!(temps[0] >= ambient && temps[0] <= previous[0], "{temps:?}");
+                assert!(temps[1] <= ambient && temps[1] >= previous[1], "{temps:?}");
+                previous = temps;
+            }
+
+            // Far past the substep cap it still stops at ambient
+            sim.tick(&environment, 1e9);
+            assert_eq!(sim.nodes()[0].fluid.temp, ambient);
+            assert_eq!(sim.nodes()[1].fluid.temp, ambient);
+        }
+    }
+
+    #[test]
+    fn test_max_substep_dt() {
+        let environment = Environment::default();
+        let new_sim = || {
+            let mut sim = Simulation::new();
+            let hot = sim.add_node(10.0, 80.0, 0.9, 100.0, glam::Vec3::ZERO);
+            let cold = sim.add_node(10.0, 20.0, 0.9, 100.0, glam::Vec3::X);
+            sim.connect_node(hot, cold, 20.0);
+            sim
+        };
+
+        let mut substepped = new_sim();
+        substepped.set_max_substep_dt(Some(0.25));
+        assert_eq!(substepped.max_substep_dt(), Some(0.25));
+        substepped.tick(&environment, 1.0);
+        let mut stepped = new_sim();
+        for _ in 0..4 {
+            stepped.tick(&environment, 0.25);
+        }
+        for (a, b) in substepped.nodes().iter().zip(stepped.nodes()) {
+            assert!((a.fluid.volume - b.fluid.volume).abs() < 1e-4);
+            assert!((a.fluid.temp - b.fluid.temp).abs() < 1e-4);
+        }
+        // The hot node runs dry partway through rather than going negative
+        assert_eq!(substepped.nodes()[0].fluid.volume, 0.0);
+        assert_eq!(substepped.nodes()[1].fluid.volume, 20.0);
+
+        substepped.set_max_substep_dt(Some(0.0));
+        assert_eq!(substepped.max_substep_dt(), None);
+    }
+
+    #[test]
+    fn test_connection_heat_flux() {
+        let mut sim = Simulation::new();
+        let empty = sim.add_node(0.0, 80.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let hot = sim.add_node(50.0, 80.0, 1.0, 100.0, glam::Vec3::X);
+        let cold = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::Y);
+        sim.connect_node(empty, cold, 1.0);
+        sim.connect_node(hot, cold, 2.0);
+
+        sim.tick(&Environment::default(), 0.5);
+        assert_eq!(sim.connection_heat_flux(0), 0.0);
+        // 2 mL/s of water at 80 deg C
+        assert!((sim.connection_heat_flux(1) - 2.0 * 4.186 * 80.0).abs() < 1e-3);
+        assert_eq!(sim.connection_heat_flux(2), 0.0);
+    }
+
+    #[test]
+    fn test_connected_components() {
+        let mut sim = Simulation::new();
+        assert!(sim.connected_components().is_empty());
+        assert!(sim.is_fully_connected());
+
+        let ids = (0..4)
+            .map(|i| sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::X * i as f32))
+            .collect::<Vec<_>>();
+        sim.connect_node(ids[0], ids[2], 1.0);
+        // Direction doesn't matter
+        sim.connect_node(ids[3], ids[1], 1.0);
+        assert_eq!(sim.connected_components(), [[0, 2], [1, 3]]);
+        assert!(!sim.is_fully_connected());
+
+        sim.connect_node(ids[2], ids[3], 1.0);
+        assert_eq!(sim.connected_components(), [[0, 1, 2, 3]]);
+        assert!(sim.is_fully_connected());
+
+        // Isolated nodes come up on their own
+        let isolated = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::Y);
+        assert_eq!(
+            sim.connected_components(),
+            [vec![0, 1, 2, 3], vec![isolated]]
+        );
+    }
+
+    #[test]
+    fn test_solar_panel_queries() {
+        let mut sim = Simulation::new();
+        let plain = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let collector = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::X);
+        let panel = SolarPanel {
+            area: 2.0,
+            efficiency: 0.7,
+        };
+        assert_eq!(sim.solar_panels().count(), 0);
+
+        sim.attach_solar_panel(collector, panel);
+        assert_eq!(sim.solar_panel(collector), Some(&panel));
+        assert_eq!(sim.solar_panel(plain), None);
+        assert_eq!(sim.solar_panel(5), None);
+        assert_eq!(
+            sim.solar_panels().collect::<Vec<_>>(),
+            [(collector, &panel)]
+        );
+
+        // Extra panels show up separately, but the first stays put
+        let extra = SolarPanel {
+            area: 1.0,
+            efficiency: 0.5,
+        };
+        sim.attach_solar_panel(collector, extra);
+        assert_eq!(sim.solar_panel(collector), Some(&panel));
+        assert_eq!(
+            sim.solar_panels().collect::<Vec<_>>(),
+            [(collector, &panel), (collector, &extra)]
+        );
+    }
+
+    #[test]
+    fn test_stratified_inflow_rises() {
+        let mut sim = Simulation::new();
+        sim.set_tick_stages(TickStages::new([TickStage::FluidTransfer]));
+        let heater = sim.add_node(10.0, 80.0, 1.0, 10.0, glam::Vec3::ZERO);
+        let tank = sim.add_node(40.0, 20.0, 1.0, 50.0, glam::Vec3::X);
+        sim.connect_node(heater, tank, 1.0);
+        sim.connect_node(tank, heater, 1.0);
+        assert_eq!(sim.node_model(tank), NodeModel::Mixed);
+        assert_eq!(sim.node_layers(tank), [sim.nodes[tank].fluid]);
+
+        let mut mixed = sim.clone();
+        assert!(sim.set_node_model(tank, NodeModel::Stratified { layers: 4 }));
+        assert!(!sim.set_node_model(5, NodeModel::Stratified { layers: 4 }));
+        assert_eq!(sim.node_model(tank), NodeModel::Stratified { layers: 4 });
+        let layer = Fluid {
+            volume: 10.0,
+            temp: 20.0,
+        };
+        assert_eq!(sim.node_layers(tank), [layer; 4]);
+
+        // Hot water settles on top while the cold return comes off the bottom
+        let environment = Environment::default();
+        for _ in 0..3 {
+            sim.tick(&environment, 1.0);
+            mixed.tick(&environment, 1.0);
+        }
+        let layers = sim.node_layers(tank);
+        assert!(layers[3].temp > mixed.nodes[tank].fluid.temp);
+        assert!(layers[..3].iter().all(|layer| layer.temp == 20.0));
+        assert!(sim.nodes[heater].fluid.temp < mixed.nodes[heater].fluid.temp);
+        let tank_fluid = sim.nodes[tank].fluid;
+        assert_eq!(tank_fluid, strata::total(layers));
+        assert!((tank_fluid.volume - 40.0).abs() < 1e-4);
+
+        // Panels only heat the top layer
+        sim.attach_solar_panel(
+            tank,
+            SolarPanel {
+                area: 1.0,
+                efficiency: 1.0,
+            },
+        );
+        let before = sim.node_layers(tank).to_vec();
+        sim.handle_solar_panels(&environment, 1.0);
+        let after = sim.node_layers(tank);
+        assert!(after[3].temp > before[3].temp);
+        assert_eq!(after[..3], before[..3]);
+
+        assert!(sim.set_node_model(tank, NodeModel::Mixed));
+        assert_eq!(sim.node_layers(tank), [sim.nodes[tank].fluid]);
+    }
+
+    #[test]
+    fn test_multiple_solar_panels() {
+        let small = SolarPanel {
+            area: 1.0,
+            efficiency: 0.9,
+        };
+        let large = SolarPanel {
+            area: 3.0,
+            efficiency: 0.6,
+        };
+        let mut environment = Environment::default();
+        environment.set_sun_angle(f32::consts::FRAC_PI_2);
+
+        let gain = |panels: &[SolarPanel]| {
+            let mut sim = Simulation::new();
+            let node = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+            for &panel in panels {
+                sim.attach_solar_panel(node, panel);
+            }
+            assert_eq!(sim.node_solar_panels(node), panels);
+            sim.tick(&environment, 1.0);
+            sim.node_energy_delta(node).unwrap()
+        };
+
+        let combined = gain(&[small, large]);
+        let separate = gain(&[small]) + gain(&[large]);
+        assert!(
+            (combined - separate).abs() < 1e-2,
+            "{combined} vs {separate}"
+        );
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut sim = Simulation::new();
+        let tank = sim.add_node(50.0, 80.0, 0.9, 100.0, glam::Vec3::ZERO);
+        let radiator = sim.add_node(10.0, 20.0, 0.5, 100.0, glam::Vec3::X);
+        sim.connect_node(tank, radiator, 1.0);
+        sim.set_connection_volume(0, 2.0);
+        sim.set_history_len(5);
+        assert!(!sim.step_back());
+
+        let environment = Environment::default();
+        let mut states = vec![sim.nodes().clone()];
+        for _ in 0..3 {
+            sim.tick(&environment, 1.0);
+            states.push(sim.nodes().clone());
+        }
+
+        assert!(sim.step_back());
+        assert_eq!(sim.nodes(), &states[2]);
+        assert!(sim.step_back());
+        assert_eq!(sim.nodes(), &states[1]);
+        assert_eq!(sim.connection_in_transit(0), Some(1.0));
+
+        // Ticking again from the rewound state gives the same result
+        sim.tick(&environment, 1.0);
+        assert_eq!(sim.nodes(), &states[2]);
+    }
+
+    #[test]
+    fn test_clock_advances_by_dt() {
+        let mut sim = Simulation::new();
+        let environment = Environment::default();
+        sim.add_node(10.0, 20.0, 0.9, 100.0, glam::Vec3::ZERO);
+        sim.set_history_len(1);
+
+        let steps = [0.016, 0.5, 1.0, 0.016, 2.25];
+        for dt in steps {
+            sim.tick(&environment, dt);
+        }
+        let expected = steps.iter().map(|&dt| f64::from(dt)).sum::<f64>();
+        assert_eq!(sim.clock().seconds(), expected);
+        assert_eq!(sim.clock().ticks(), steps.len() as u64);
+
+        sim.step_back();
+        assert_eq!(sim.clock().seconds(), expected);
+    }
+
+    #[test]
+    fn test_warm_start() {
+        let environment = Environment::default();
+        let mut sim = Simulation::new();
+        let panel = sim.add_node(10.0, 20.0, 0.9, 100.0, glam::Vec3::ZERO);
+        let tank = sim.add_node(50.0, 20.0, 0.95, 100.0, glam::Vec3::X);
+        sim.connect_node(panel, tank, 1.0);
+        sim.connect_node(tank, panel, 1.0);
+        sim.attach_solar_panel(
+            panel,
+            SolarPanel {
+                area: 1.0,
+                efficiency: 0.9,
+            },
+        );
+        let connections = sim.connections().clone();
+
+        sim.warm_start(&environment);
+        assert_eq!(sim.nodes().len(), 2);
+        assert_eq!(sim.connections(), &connections);
+        assert!(sim.nodes()[tank].fluid.temp > 30.0);
+
+        let warm = sim.nodes().clone();
+        sim.warm_start(&environment);
+        for (a, b) in warm.iter().zip(sim.nodes()) {
+            assert!((a.fluid.temp - b.fluid.temp).abs() < 0.1);
+        }
+
+        sim.tick(&environment, 1.0);
+        for (a, b) in warm.iter().zip(sim.nodes()) {
+            assert!((a.fluid.temp - b.fluid.temp).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn test_warm_start_for() {
+        let environment = Environment::default();
+        let mut sim = Simulation::new();
+        let panel = sim.add_node(10.0, 20.0, 0.9, 100.0, glam::Vec3::ZERO);
+        let tank = sim.add_node(50.0, 20.0, 0.95, 100.0, glam::Vec3::X);
+        sim.connect_node(panel, tank, 1.0);
+        sim.connect_node(tank, panel, 1.0);
+        sim.attach_solar_panel(
+            panel,
+            SolarPanel {
+                area: 1.0,
+                efficiency: 0.9,
+            },
+        );
+        let mut at_once = sim.clone();
+        at_once.warm_start(&environment);
+
+        // A few ticks at a time gets about as far, just over more calls
+        let mut calls = 1;
+        while !sim.warm_start_for(&environment, 10) {
+            calls += 1;
+        }
+        assert!(calls > 1);
+        for (a, b) in at_once.nodes().iter().zip(sim.nodes()) {
+            assert!((a.fluid.temp - b.fluid.temp).abs() < 0.1);
+        }
+        assert_eq!(sim.clock().seconds(), 0.0);
+    }
+
+    #[test]
+    fn test_sun_disabled() {
+        let mut environment = Environment::default();
+        let mut sim = Simulation::new();
+        let node = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+        sim.attach_solar_panel(
+            node,
+            SolarPanel {
+                area: 1.0,
+                efficiency: 0.9,
+            },
+        );
+
+        sim.tick(&environment, 1.0);
+        let gain = sim.node_energy_delta(node).unwrap();
+        assert!(gain > 0.0);
+
+        environment.set_sun_enabled(false);
+        sim.tick(&environment, 1.0);
+        assert_eq!(sim.node_energy_delta(node), Some(0.0));
+        assert_eq!(environment.sun_angle(), f32::consts::FRAC_PI_2);
+        assert!(environment.to_string().starts_with("sun 90 deg (off),"));
+
+        environment.set_sun_enabled(true);
+        sim.tick(&environment, 1.0);
+        assert!((sim.node_energy_delta(node).unwrap() - gain).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_node_fluid_properties() {
+        let environment = Environment::default();
+        let mut sim = Simulation::new();
+        let water = sim.add_node(1000.0, 20.0, 1.0, 2000.0, glam::Vec3::ZERO);
+        let oil = sim.add_node(1000.0, 20.0, 1.0, 2000.0, glam::Vec3::X);
+        assert!(sim.set_node_fluid_properties(oil, FluidProperties::oil()));
+        assert!(!sim.set_node_fluid_properties(5, FluidProperties::oil()));
+        assert_eq!(sim.node_fluid_properties(water), &FluidProperties::water());
+
+        // Overhead sun on 0.5 m² of panel is 500 J a second
+        let panel = SolarPanel {
+            area: 1.0,
+            efficiency: 0.5,
+        };
+        sim.attach_solar_panel(water, panel);
+        sim.attach_solar_panel(oil, panel);
+        sim.tick(&environment, 1.0);
+
+        let d_temp = |id: usize| sim.nodes()[id].fluid.temp - 20.0;
+        assert!((d_temp(oil) - 500.0 / (1000.0 * 0.87 * 2.0)).abs() < 1e-4);
+        assert!((d_temp(water) - 500.0 / (1000.0 * 4.186)).abs() < 1e-4);
+        assert!((sim.node_energy_delta(oil).unwrap() - 500.0).abs() < 1e-1);
+
+        // Merged nodes keep their fluid even where the default differs
+        let mut store = Simulation::new();
+        store.set_fluid_properties(FluidProperties::oil());
+        store.add_node(1000.0, 20.0, 1.0, 2000.0, glam::Vec3::ZERO);
+        let ids = sim.merge(store);
+        assert_eq!(sim.node_fluid_properties(ids[0]), &FluidProperties::oil());
+    }
+
+    #[test]
+    fn test_conduction() {
+        let mut sim = Simulation::new();
+        let hot = sim.add_node(1000.0, 100.0, 1.0, 2000.0, glam::Vec3::ZERO);
+        let cold = sim.add_node(1000.0, 0.0, 1.0, 2000.0, glam::Vec3::X);
+        sim.connect_node(hot, cold, 0.0);
+        assert!(sim.set_connection_conductance(0, 100.0));
+        assert!(!sim.set_connection_conductance(1, 100.0));
+        sim.set_tick_stages(TickStages::new([TickStage::Conduction]));
+
+        let energy = |sim: &Simulation| sim.metrics(&Environment::default()).thermal_energy;
+        let start = energy(&sim);
+        let mut previous = 100.0;
+        for _ in 0..500 {
+            sim.tick(&Environment::default(), 1.0);
+            let [hot, cold] = [hot, cold].map(|id| sim.nodes()[id].fluid.temp);
+            // Closer every tick without crossing over
+            assert!(hot >= cold && hot - cold <= previous);
+            assert!((hot + cold - 100.0).abs() < 1e-3);
+            previous = hot - cold;
+        }
+        assert!(previous < 1.0);
+        assert!((energy(&sim) - start).abs() / start < 1e-5);
+        assert_eq!(sim.nodes()[hot].fluid.volume, 1000.0);
+
+        // A dt that would overshoot stops at equilibrium instead
+        sim.nodes[hot].fluid.temp = 100.0;
+        sim.nodes[cold].fluid.temp = 0.0;
+        sim.tick(&Environment::default(), 1000.0);
+        assert!((sim.nodes()[hot].fluid.temp - 50.0).abs() < 1e-3);
+        assert!((sim.nodes()[cold].fluid.temp - 50.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_long_run() {
+        let environment = Environment::default();
+        let mut sim = Simulation::new();
+        let panel = sim.add_node(10.0, 20.0, 0.9, 100.0, glam::Vec3::ZERO);
+        let tank = sim.add_node(10.0, 20.0, 0.9, 100.0, glam::Vec3::X);
+        sim.connect_node(panel, tank, 1.0);
+        sim.connect_node(tank, panel, 1.0);
+        sim.attach_solar_panel(
+            panel,
+            SolarPanel {
+                area: 1.0,
+                efficiency: 0.9,
+            },
+        );
+
+        for _ in 0..10_000 {
+            sim.tick(&environment, 0.016);
+        }
+        assert!(sim.nodes().iter().all(|node| node.fluid.temp.is_finite()));
+        assert_eq!(sim.clock().ticks(), 10_000);
+    }
+
+    #[test]
+    fn test_day_night() {
+        let mut environment = Environment::default();
+        environment.set_day_length(100.0);
+        assert_eq!(environment.time_of_day(), 50.0);
+        environment.set_day_length(-1.0);
+        assert_eq!(environment.day_length(), 100.0);
+
+        let mut sim = Simulation::new();
+        let node = sim.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+        sim.attach_solar_panel(
+            node,
+            SolarPanel {
+                area: 1.0,
+                efficiency: 0.9,
+            },
+        );
+
+        // Sunrise, noon and sunset
+        for (dt, angle) in [(-25.0, 0.0), (25.0, 1.0), (25.0, 2.0)] {
+            environment.advance_time(dt);
+            let expected = angle * f32::consts::FRAC_PI_2;
+            assert!((environment.sun_angle() - expected).abs() < 1e-3);
+        }
+
+        // No gain all night
+        for _ in 0..10 {
+            environment.advance_time(5.0);
+            assert!(math::sin(environment.sun_angle()) <= 1e-3);
+            sim.tick(&environment, 1.0);
+            assert!(sim.node_energy_delta(node).unwrap() <= 1e-2);
+        }
+        assert_eq!(environment.time_of_day(), 25.0);
+
+        // Wraps around to the next day, with a lower sun further north
+        environment.set_latitude(f32::consts::FRAC_PI_4);
+        environment.advance_time(125.0);
+        assert_eq!(environment.time_of_day(), 50.0);
+        assert!((math::sin(environment.sun_angle()) - f32::consts::FRAC_1_SQRT_2).abs() < 1e-3);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_round_trip() {
+        let mut sim = Simulation::new();
+        let panel = sim.add_node(5.0, 40.0, 0.5, 10.0, glam::Vec3::ZERO);
+        let tank = sim.add_node(50.0, 60.0, 0.9, 100.0, glam::Vec3::X);
+        let load = sim.add_node(20.0, 30.0, 0.8, 50.0, glam::Vec3::Y);
+        let removed = sim.add_node(1.0, 20.0, 0.5, 10.0, glam::Vec3::Z);
+        sim.connect_node(panel, tank, 2.0);
+        sim.connect_node(tank, load, 1.0);
+        sim.connect_node(load, panel, 1.0);
+        sim.set_connection_conductance(1, 5.0);
+        sim.remove_node(removed);
+        sim.attach_solar_panel(
+            panel,
+            SolarPanel {
+                area: 2.0,
+                efficiency: 0.8,
+            },
+        );
+        sim.set_node_fluid_properties(load, FluidProperties::glycol());
+        sim.tick(&Environment::default(), 1.0);
+
+        let mut loaded = Simulation::from_json(&sim.to_json().unwrap()).unwrap();
+        assert_eq!(loaded.nodes(), sim.nodes());
+        assert!(!loaded.contains_node(removed));
+        assert_eq!(loaded.connections(), sim.connections());
+        assert_eq!(
+            loaded.node_solar_panels(panel),
+            sim.node_solar_panels(panel)
+        );
+        assert_eq!(
+            loaded.node_fluid_properties(load),
+            &FluidProperties::glycol()
+        );
+        assert_eq!(loaded.clock(), sim.clock());
+
+        loaded.tick(&Environment::default(), 1.0);
+        sim.tick(&Environment::default(), 1.0);
+        assert_eq!(loaded.nodes(), sim.nodes());
+
+        assert!(Simulation::from_json("{}").is_err());
+    }
+
+    #[test]
+    fn test_temp_limits() {
+        let mut environment = Environment::default();
+        environment.set_sun_irradiance(50_000.0);
+        let mut sim = Simulation::new();
+        let node = sim.add_node(100.0, 20.0, 1.0, 200.0, glam::Vec3::ZERO);
+        assert!(sim.set_node_temp_limits(node, Some(0.0), Some(90.0)));
+        assert!(!sim.set_node_temp_limits(1, None, Some(90.0)));
+        sim.attach_solar_panel(
+            node,
+            SolarPanel {
+                area: 1.0,
+                efficiency: 1.0,
+            },
+        );
+
+        let mut gained = 0.0;
+        for _ in 0..100 {
+            sim.tick(&environment, 1.0);
+            assert!(sim.nodes()[node].fluid.temp <= 90.0);
+            gained += 50_000.0;
+        }
+        assert_eq!(sim.nodes()[node].fluid.temp, 90.0);
+        // Whatever didn't go into heating it up was vented
+        let heated = 100.0 * 4.186 * 70.0;
+        assert!((sim.node_vented_energy(node) - (gained - heated)).abs() / gained < 1e-3);
+
+        // The floor holds against an extractor draining it
+        sim.attach_extractor(
+            node,
+            Extractor {
+                power_draw: 1e6,
+                efficiency: 1.0,
+            },
+        );
+        environment.set_sun_enabled(false);
+        sim.tick(&environment, 1.0);
+        assert_eq!(sim.nodes()[node].fluid.temp, 0.0);
+    }
+
+    #[test]
+    fn test_transfer_conserves_totals() {
+        let mut sim = Simulation::new();
+        for (volume, temp) in [(40.0, 80.0), (10.0, 20.0), (30.0, 50.0), (0.0, 10.0)] {
+            sim.add_node(volume, temp, 0.5, 50.0, glam::Vec3::ZERO);
+        }
+        // A node feeding two connections, one with fluid in transit, and
+        // one filling up
+        sim.connect_node(0, 1, 20.0);
+        sim.connect_node(0, 2, 30.0);
+        sim.connect_node(2, 3, 5.0);
+        sim.connect_node(1, 0, 10.0);
+        sim.connect_node(3, 0, 1.0);
+        sim.set_connection_volume(1, 4.0);
+        sim.set_tick_stages(TickStages::new([TickStage::FluidTransfer]));
+
+        let volume = sim.total_volume();
+        let energy = sim.total_thermal_energy();
+        assert_eq!(volume, 80.0);
+        assert!((energy - 4.186 * (40.0 * 80.0 + 10.0 * 20.0 + 30.0 * 50.0)).abs() < 1e-2);
+        for _ in 0..100 {
+            sim.tick(&Environment::default(), 0.1);
+            assert!((sim.total_volume() - volume).abs() < 1e-3);
+            assert!((sim.total_thermal_energy() - energy).abs() / energy < 1e-5);
+        }
+        assert!(sim.connection_in_transit(1).unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_reversed_flow() {
+        let mut sim = Simulation::new();
+        let input = sim.add_node(10.0, 20.0, 1.0, 50.0, glam::Vec3::ZERO);
+        let output = sim.add_node(40.0, 80.0, 1.0, 100.0, glam::Vec3::X);
+        sim.connect_node(input, output, -10.0);
+        sim.set_tick_stages(TickStages::new([TickStage::FluidTransfer]));
+
+        sim.tick(&Environment::default(), 1.0);
+        assert_eq!(
+            sim.nodes()[input].fluid,
+            Fluid {
+                volume: 20.0,
+                temp: 50.0
+            }
+        );
+        assert_eq!(sim.nodes()[output].fluid.volume, 30.0);
+        assert_eq!(sim.realized_flows()[0], -10.0);
+        assert!(sim.connection_heat_flux(0) < 0.0);
+
+        // Until the input is full
+        for _ in 0..5 {
+            sim.tick(&Environment::default(), 1.0);
+        }
+        assert_eq!(sim.nodes()[input].fluid.volume, 50.0);
+        assert_eq!(sim.nodes()[output].fluid.volume, 0.0);
+
+        // A pipe fills from the output end, and flowing forwards again
+        // pushes the same fluid back out
+        sim.set_connection_volume(0, 5.0);
+        sim.connections[0].flow_rate = 5.0;
+        sim.tick(&Environment::default(), 1.0);
+        assert_eq!(sim.connection_in_transit(0), Some(5.0));
+        sim.connections[0].flow_rate = -5.0;
+        sim.tick(&Environment::default(), 1.0);
+        assert_eq!(sim.nodes()[input].fluid.volume, 45.0);
+        assert_eq!(sim.total_volume(), 50.0);
+    }
+
+    #[test]
+    fn test_connection_heat_loss() {
+        let environment = Environment::default();
+        let mut sim = Simulation::new();
+        let hot = sim.add_node(50.0, 80.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let cold = sim.add_node(0.0, 20.0, 1.0, 100.0, glam::Vec3::X);
+        sim.connect_node(hot, cold, 10.0);
+        assert!(sim.set_connection_heat_loss(0, 0.05, Some(2.0)));
+        assert!(!sim.set_connection_heat_loss(1, 0.05, None));
+        sim.set_tick_stages(TickStages::new([TickStage::FluidTransfer]));
+
+        // 10% of the way to 20 deg C ambient
+        sim.tick(&environment, 1.0);
+        assert_eq!(sim.nodes()[hot].fluid.temp, 80.0);
+        assert!((sim.nodes()[cold].fluid.temp - 74.0).abs() < 1e-4);
+
+        // Never past ambient, so 10 mL at 20 deg C arrives
+        sim.set_connection_heat_loss(0, 10.0, None);
+        sim.tick(&environment, 1.0);
+        assert!((sim.nodes()[cold].fluid.temp - 47.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_remove_node() {
+        let mut sim = Simulation::new();
+        let a = sim.add_node(10.0, 10.0, 0.9, 100.0, glam::Vec3::ZERO);
+        let b = sim.add_node(10.0, 20.0, 0.9, 100.0, glam::Vec3::X);
+        let c = sim.add_node(10.0, 30.0, 0.9, 100.0, glam::Vec3::Y);
+        let d = sim.add_node(10.0, 40.0, 0.9, 100.0, glam::Vec3::Z);
+        sim.connect_node(a, b, 1.0);
+        sim.connect_node(c, d, 2.0);
+        sim.connect_node(b, c, 3.0);
+        sim.connect_node(d, a, 4.0);
+        sim.set_node_name(b, "b");
+        sim.set_node_name(d, "d");
+        sim.attach_solar_panel(
+            d,
+            SolarPanel {
+                area: 1.0,
+                efficiency: 0.5,
+            },
+        );
+        sim.set_history_len(1);
+        sim.tick(&Environment::default(), 0.1);
+
+        let removed = sim.remove_node(b).unwrap();
+        assert_eq!(removed.name.as_deref(), Some("b"));
+        assert_eq!(sim.nodes().len(), 3);
+        assert_eq!(sim.node_by_name("b"), None);
+
+        // Connections to b are gone and everything else keeps its ids
+        assert_eq!(
+            sim.connections()
+                .iter()
+                .map(|connection| (connection.input, connection.output, connection.flow_rate))
+                .collect::<Vec<_>>(),
+            [(c, d, 2.0), (d, a, 4.0)]
+        );
+        // Still indexed by id, with nothing for the removed ones
+        assert_eq!(sim.realized_flows().len(), 4);
+        assert_eq!(
+            (sim.realized_flows()[0], sim.realized_flows()[2]),
+            (0.0, 0.0)
+        );
+        assert_eq!(sim.node_by_name("d"), Some(d));
+        assert_eq!(sim.node_solar_panels(d).len(), 1);
+        assert_eq!(sim.nodes()[d].position, glam::Vec3::Z);
+        assert_eq!(sim.nodes().ids().collect::<Vec<_>>(), [a, c, d]);
+
+        // b's id is a gap now, and isn't reused
+        assert!(!sim.contains_node(b));
+        assert!(sim.get_node(b).is_none());
+        assert_eq!(sim.node_energy_delta(b), None);
+        assert!(!sim.set_node_tint(b, glam::Vec3::ONE));
+        sim.connect_node(a, b, 1.0);
+        assert_eq!(sim.connections().len(), 2);
+        let e = sim.add_node(10.0, 50.0, 0.9, 100.0, glam::Vec3::ONE);
+        assert_eq!(e, 4);
+
+        // History stays lined up with what's left
+        assert!(sim.step_back());
+        assert_eq!(sim.nodes()[c].fluid.temp, 30.0);
+        assert_eq!(sim.nodes()[d].fluid.temp, 40.0);
+        assert_eq!(sim.nodes()[e].fluid.temp, 50.0);
+        sim.tick(&Environment::default(), 0.1);
+        assert_eq!(sim.interpolate(1.0).len(), 4);
+
+        assert!(sim.remove_node(b).is_none());
+        assert!(sim.remove_node(5).is_none());
+        assert!(sim.remove_connection(0).is_none());
+        assert_eq!(sim.remove_connection(1).unwrap().flow_rate, 2.0);
+        assert_eq!(sim.connections().len(), 1);
+        assert_eq!(sim.connections()[3].flow_rate, 4.0);
+        assert!(sim.remove_connection(1).is_none());
+    }
+
+    #[test]
+    fn test_ids_survive_removal() {
+        let mut sim = Simulation::new();
+        let first = sim.add_node(10.0, 10.0, 0.9, 100.0, glam::Vec3::ZERO);
+        let kept = sim.add_node(10.0, 20.0, 0.9, 100.0, glam::Vec3::X);
+        sim.set_node_name(kept, "kept");
+
+        // Taken before a lower id is removed, still the same node after
+        sim.remove_node(first);
+        assert_eq!(sim.nodes()[kept].position, glam::Vec3::X);
+        assert_eq!(sim.node_by_name("kept"), Some(kept));
+        assert_eq!(sim.get_node(first), None);
+        assert_eq!(sim.connected_components(), [vec![kept]]);
+
+        // Gaps come along when merging, so ids only shift by the offset
+        let mut other = Simulation::new();
+        other.add_node(10.0, 10.0, 0.9, 100.0, glam::Vec3::ZERO);
+        let ids = other.merge(sim);
+        assert_eq!(ids, [1, 2]);
+        assert!(!other.contains_node(ids[first]));
+        assert_eq!(other.nodes()[ids[kept]].position, glam::Vec3::X);
+        assert_eq!(other.node_by_name("kept"), Some(ids[kept]));
+    }
+
+    #[test]
+    fn test_connection_ids_survive_removal() {
+        let mut sim = Simulation::new();
+        sim.set_tick_stages(TickStages::new([TickStage::FluidTransfer]));
+        let a = sim.add_node(10.0, 80.0, 0.9, 100.0, glam::Vec3::ZERO);
+        let b = sim.add_node(10.0, 20.0, 0.9, 100.0, glam::Vec3::X);
+        let c = sim.add_node(10.0, 50.0, 0.9, 100.0, glam::Vec3::Y);
+        sim.connect_node(a, b, 1.0);
+        sim.connect_node(b, c, 2.0);
+        sim.connect_node(c, a, 3.0);
+        let (middle, last) = (1, 2);
+        sim.set_connection_volume(last, 10.0);
+        sim.tick(&Environment::default(), 1.0);
+        let in_transit = sim.connection_in_transit(last);
+
+        // Taken before the middle one is removed, still the same connection
+        sim.remove_connection(middle);
+        assert_eq!(sim.connections()[last].flow_rate, 3.0);
+        assert_eq!(
+            (
+                sim.connections()[last].input,
+                sim.connections()[last].output
+            ),
+            (c, a)
+        );
+        assert_eq!(sim.connection_in_transit(last), in_transit);
+        assert_eq!(sim.connection_in_transit(middle), None);
+        assert!(!sim.set_connection_volume(middle, 10.0));
+        assert!(sim.set_connection_conductance(last, 1.0));
+
+        sim.tick(&Environment::default(), 1.0);
+        assert_eq!(sim.realized_flows()[middle], 0.0);
+        assert_eq!(sim.realized_flows()[last], 3.0);
+        assert!(sim.connection_heat_flux(last) > 0.0);
+        // The id isn't handed out again
+        sim.connect_node(b, c, 2.0);
+        assert!(!sim.connections().contains(middle));
+        assert_eq!(sim.connections().ids().collect::<Vec<_>>(), [0, last, 3]);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut collector = Simulation::new();
+        let panel = collector.add_node(10.0, 60.0, 0.9, 100.0, glam::Vec3::ZERO);
+        let pump = collector.add_node(10.0, 40.0, 0.9, 100.0, glam::Vec3::X);
+        collector.connect_node(panel, pump, 1.0);
+        collector.set_node_name(pump, "pump");
+
+        let mut storage = Simulation::new();
+        let tank = storage.add_node(100.0, 20.0, 0.95, 200.0, glam::Vec3::Y);
+        let load = storage.add_node(10.0, 30.0, 0.9, 100.0, glam::Vec3::Z);
+        storage.connect_node(tank, load, 2.0);
+        storage.connect_node(load, tank, 2.0);
+        storage.set_node_name(tank, "tank");
+        storage.set_node_name(load, "pump");
+        storage.attach_solar_panel(
+            load,
+            SolarPanel {
+                area: 1.0,
+                efficiency: 0.5,
+            },
+        );
+
+        let ids = collector.merge(storage);
+        assert_eq!(ids, vec![2, 3]);
+        assert_eq!(collector.nodes().len(), 4);
+        assert_eq!(collector.nodes()[ids[tank]].fluid.temp, 20.0);
+        assert_eq!(collector.node_by_name("tank"), Some(ids[tank]));
+        // The collector's pump keeps its name
+        assert_eq!(collector.node_by_name("pump"), Some(pump));
+        assert_eq!(collector.nodes()[ids[load]].name, None);
+        assert_eq!(collector.node_solar_panels(ids[load]).len(), 1);
+        assert_eq!(
+            collector
+                .connections()
+                .iter()
+                .skip(1)
+                .copied()
+                .collect::<Vec<_>>(),
+            [
+                Connection {
+                    flow_rate: 2.0,
+                    input: 2,
+                    output: 3,
+                    conductance: 0.0,
+                    heat_loss_coeff: 0.0,
+                    length: None,
+                },
+                Connection {
+                    flow_rate: 2.0,
+                    input: 3,
+                    output: 2,
+                    conductance: 0.0,
+                    heat_loss_coeff: 0.0,
+                    length: None,
+                },
+            ]
+        );
+
+        collector.connect_node(pump, ids[tank], 1.0);
+        assert_eq!(collector.connections().len(), 4);
+        collector.tick(&Environment::default(), 1.0);
+        assert!(collector.realized_flows()[3] > 0.0);
+    }
+
+    #[test]
+    fn test_merge_dangling() {
+        let mut sim = Simulation::new();
+        sim.add_node(10.0, 20.0, 0.9, 100.0, glam::Vec3::ZERO);
+
+        let mut other = Simulation::new();
+        let tank = other.add_node(10.0, 60.0, 0.9, 100.0, glam::Vec3::X);
+        let removed = other.add_node(10.0, 40.0, 0.9, 100.0, glam::Vec3::Y);
+        let load = other.add_node(10.0, 30.0, 0.9, 100.0, glam::Vec3::Z);
+        other.connect_node(tank, load, 1.0);
+        other.remove_node(removed);
+        // Like a hand edited save could have
+        let dangling = Connection {
+            input: tank,
+            output: 7,
+            ..other.connections[0]
+        };
+        for connection in [
+            dangling,
+            Connection {
+                output: removed,
+                ..dangling
+            },
+        ] {
+            other.connections.push(connection);
+            other.realized_flows.push(0.0);
+            other.heat_fluxes.push(0.0);
+            other.pipes.push(Pipe::default());
+        }
+        let panel = SolarPanel {
+            area: 1.0,
+            efficiency: 0.5,
+        };
+        other.solar_panels.insert(9, vec![panel]);
+        other.solar_panels.insert(removed, vec![panel]);
+        other.extractors.insert(
+            9,
+            Extractor {
+                power_draw: 1.0,
+                efficiency: 1.0,
+            },
+        );
+        other.vented_energy.insert(removed, 1.0);
+        other.strata.insert(
+            9,
+            strata::split(
+                Fluid {
+                    volume: 10.0,
+                    temp: 20.0,
+                },
+                2,
+            ),
+        );
+
+        let ids = sim.merge(other);
+        assert_eq!(ids, [1, 2, 3]);
+        assert_eq!(
+            sim.connections()
+                .iter()
+                .map(|connection| (connection.input, connection.output))
+                .collect::<Vec<_>>(),
+            [(ids[tank], ids[load])]
+        );
+        assert_eq!(sim.realized_flows().len(), 1);
+        assert_eq!(sim.solar_panels().count(), 0);
+        assert!(sim.extractors.is_empty());
+        assert_eq!(sim.node_vented_energy(ids[removed]), 0.0);
+        assert!(sim.strata.is_empty());
+        sim.tick(&Environment::default(), 1.0);
+        assert!(sim.realized_flows()[0] > 0.0);
+    }
+
+    #[test]
+    fn test_gravity_pump_flow() {
+        let mut sim = Simulation::new();
+        assert_eq!(sim.gravity(), STANDARD_GRAVITY);
+
+        let earth = sim.pump_flow_rate(100.0, 5.0, 20.0);
+        assert!((earth - 100.0 / (STANDARD_GRAVITY * 5.0)).abs() < 1e-6);
+
+        sim.set_gravity(2.0 * STANDARD_GRAVITY);
+        let heavy = sim.pump_flow_rate(100.0, 5.0, 20.0);
+        assert!((heavy - earth / 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_estimate_time_to_temp() {
+        let environment = Environment::default();
+        let mut sim = Simulation::new();
+        let tank = sim.add_node(10.0, 20.0, 0.9, 100.0, glam::Vec3::ZERO);
+        sim.attach_solar_panel(
+            tank,
+            SolarPanel {
+                area: 1.0,
+                efficiency: 0.9,
+            },
+        );
+
+        let time = sim
+            .estimate_time_to_temp(tank, 60.0, &environment, 0.1)
+            .unwrap();
+        let ticks = (time / 0.1).round() as usize;
+        for _ in 0..ticks - 1 {
+            sim.tick(&environment, 0.1);
+        }
+        assert!(sim.nodes()[tank].fluid.temp < 60.0);
+        sim.tick(&environment, 0.1);
+        assert!(sim.nodes()[tank].fluid.temp >= 60.0);
+
+        // Heat loss balances the panel long before this
+        assert_eq!(
+            sim.estimate_time_to_temp(tank, 1000.0, &environment, 0.1),
+            None
+        );
+        assert_eq!(
+            sim.estimate_time_to_temp(tank + 1, 60.0, &environment, 0.1),
+            None
+        );
+    }
+}
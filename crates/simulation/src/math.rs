@@ -0,0 +1,57 @@
+//! Float functions that live in `std`, with `libm` fallbacks for `no_std`
+//! builds.
+
+#[cfg(feature = "std")]
+pub(crate) fn sin(x: f32) -> f32 {
+    x.sin()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn sin(x: f32) -> f32 {
+    libm::sinf(x)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn rem_euclid(x: f32, y: f32) -> f32 {
+    x.rem_euclid(y)
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn rem_euclid(x: f32, y: f32) -> f32 {
+    let r = libm::fmodf(x, y);
+    if r < 0.0 {
+        r + y.abs()
+    } else {
+        r
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn cos(x: f32) -> f32 {
+    x.cos()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn cos(x: f32) -> f32 {
+    libm::cosf(x)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn asin(x: f32) -> f32 {
+    x.asin()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn asin(x: f32) -> f32 {
+    libm::asinf(x)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn ceil(x: f32) -> f32 {
+    x.ceil()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn ceil(x: f32) -> f32 {
+    libm::ceilf(x)
+}
@@ -0,0 +1,188 @@
+//! Physical properties of the fluid, which may vary with temperature.
+
+use alloc::vec::Vec;
+
+use crate::Fluid;
+
+/// A value that's either constant or looked up by temperature.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Lookup(Repr);
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Repr {
+    Constant(f32),
+    /// `(temp, value)` pairs sorted by temp.
+    Table(Vec<(f32, f32)>),
+}
+
+impl Lookup {
+    pub fn constant(value: f32) -> Self {
+        Self(Repr::Constant(value))
+    }
+
+    /// Linearly interpolates between `(temp, value)` points, holding the end
+    /// values outside of them. Returns `None` if there are no points or any
+    /// aren't finite.
+    pub fn table(points: impl IntoIterator<Item = (f32, f32)>) -> Option<Self> {
+        let mut points = points.into_iter().collect::<Vec<_>>();
+        if points.is_empty()
+            || points
+                .iter()
+                .any(|(temp, value)| !temp.is_finite() || !value.is_finite())
+        {
+            return None;
+        }
+
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Some(Self(Repr::Table(points)))
+    }
+
+    pub fn get(&self, temp: f32) -> f32 {
+        let points = match &self.0 {
+            Repr::Constant(value) => return *value,
+            Repr::Table(points) => points,
+        };
+
+        let i = points.partition_point(|&(t, _)| t < temp);
+        if i == 0 {
+            return points[0].1;
+        }
+        if i == points.len() {
+            return points[i - 1].1;
+        }
+
+        let (t0, v0) = points[i - 1];
+        let (t1, v1) = points[i];
+        v0 + (v1 - v0) * (temp - t0) / (t1 - t0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FluidProperties {
+    /// g / mL
+    pub density: Lookup,
+    /// J / (g deg C)
+    pub specific_heat: Lookup,
+}
+
+impl FluidProperties {
+    /// Water with its properties at room temperature.
+    pub fn water() -> Self {
+        Self {
+            density: Lookup::constant(1.0),
+            specific_heat: Lookup::constant(4.186),
+        }
+    }
+
+    /// A 40% propylene glycol antifreeze mix.
+    pub fn glycol() -> Self {
+        Self {
+            density: Lookup::constant(1.03),
+            specific_heat: Lookup::constant(3.7),
+        }
+    }
+
+    /// Mineral oil as used in thermal stores.
+    pub fn oil() -> Self {
+        Self {
+            density: Lookup::constant(0.87),
+            specific_heat: Lookup::constant(2.0),
+        }
+    }
+
+    /// J / deg C
+    pub fn heat_capacity(&self, fluid: &Fluid) -> f32 {
+        // Volume is in mL
+        fluid.volume * self.density.get(fluid.temp) * self.specific_heat.get(fluid.temp)
+    }
+
+    /// J, relative to 0 deg C
+    pub fn thermal_energy(&self, fluid: &Fluid) -> f32 {
+        self.heat_capacity(fluid) * fluid.temp
+    }
+
+    /// How much `q` J of heat changes the temperature of `fluid`.
+    pub fn temp_change(&self, fluid: &Fluid, q: f32) -> f32 {
+        q / self.heat_capacity(fluid)
+    }
+
+    /// Adds `incoming`, a fluid with `incoming_properties`, to `fluid` and
+    /// treats the result as this fluid. Like fluids mix by volume, different
+    /// ones by how much heat each holds per degree.
+    pub fn mix(&self, fluid: Fluid, incoming_properties: &Self, incoming: Fluid) -> Fluid {
+        let mut mixed = fluid + incoming;
+        if self == incoming_properties {
+            return mixed;
+        }
+
+        let capacity = self.heat_capacity(&fluid);
+        let incoming_capacity = incoming_properties.heat_capacity(&incoming);
+        if capacity + incoming_capacity > 0.0 {
+            mixed.temp = (capacity * fluid.temp + incoming_capacity * incoming.temp)
+                / (capacity + incoming_capacity);
+        }
+        mixed
+    }
+}
+
+impl Default for FluidProperties {
+    fn default() -> Self {
+        Self::water()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_table() {
+        assert_eq!(Lookup::table([]), None);
+        assert_eq!(Lookup::table([(f32::NAN, 1.0)]), None);
+
+        let lookup = Lookup::table([(100.0, 3.0), (0.0, 1.0), (50.0, 2.0)]).unwrap();
+        assert_eq!(lookup.get(-10.0), 1.0);
+        assert_eq!(lookup.get(25.0), 1.5);
+        assert_eq!(lookup.get(50.0), 2.0);
+        assert_eq!(lookup.get(75.0), 2.5);
+        assert_eq!(lookup.get(150.0), 3.0);
+    }
+
+    #[test]
+    fn test_temperature_dependent_gain() {
+        // Water gets less dense and holds a bit more heat as it warms up
+        let properties = FluidProperties {
+            density: Lookup::table([(20.0, 0.998), (90.0, 0.965)]).unwrap(),
+            specific_heat: Lookup::table([(20.0, 4.182), (90.0, 4.205)]).unwrap(),
+        };
+
+        let q = 1000.0;
+        let cool = properties.temp_change(&Fluid::from_liters_celsius(1.0, 20.0), q);
+        let hot = properties.temp_change(&Fluid::from_liters_celsius(1.0, 90.0), q);
+
+        assert!((cool - q / (1000.0 * 0.998 * 4.182)).abs() < 1e-6);
+        assert!((hot - q / (1000.0 * 0.965 * 4.205)).abs() < 1e-6);
+        assert!(hot > cool);
+    }
+
+    #[test]
+    fn test_mix_unlike_fluids() {
+        let water = FluidProperties::water();
+        let oil = FluidProperties::oil();
+        let cold = Fluid::from_liters_celsius(1.0, 20.0);
+        let hot = Fluid::from_liters_celsius(1.0, 80.0);
+
+        assert_eq!(water.mix(cold, &water, hot), cold + hot);
+
+        // A liter of water holds more heat per degree than a liter of oil
+        let mixed = water.mix(cold, &oil, hot);
+        let (c_water, c_oil) = (1000.0 * 4.186, 1000.0 * 0.87 * 2.0);
+        let expected = (c_water * 20.0 + c_oil * 80.0) / (c_water + c_oil);
+        assert_eq!(mixed.volume, 2000.0);
+        assert!((mixed.temp - expected).abs() < 1e-4);
+        assert!(mixed.temp < 50.0);
+    }
+}
@@ -0,0 +1,331 @@
+//! Records node state over time for analysis elsewhere, e.g. in a
+//! spreadsheet.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use crate::Simulation;
+
+/// One node's fluid at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecorderRow {
+    /// Simulated seconds, see [`crate::SimClock::seconds`].
+    pub seconds: f64,
+    pub node: usize,
+    /// In deg C.
+    pub temp: f32,
+    /// In mL.
+    pub volume: f32,
+}
+
+/// Collects a row per node each time [`Self::record`] is called and writes
+/// them out as CSV, with the columns in [`Self::CSV_HEADER`]. See
+/// `ParquetRecorder` with the `parquet` feature for runs too long to keep in
+/// memory.
+///
+/// ```
+/// use passive_logic_simulation::{Environment, Simulation, SimulationRecorder};
+///
+/// let mut simulation = Simulation::new();
+/// simulation.add_node(10.0, 40.0, 0.5, 100.0, glam::Vec3::ZERO);
+/// let mut recorder = SimulationRecorder::new();
+/// for _ in 0..3 {
+///     simulation.tick(&Environment::default(), 1.0);
+///     recorder.record(&simulation);
+/// }
+///
+/// let csv = recorder.to_csv();
+/// assert_eq!(csv.lines().next(), Some(SimulationRecorder::CSV_HEADER));
+/// assert_eq!(csv.lines().count(), 4);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SimulationRecorder {
+    rows: Vec<RecorderRow>,
+}
+
+impl SimulationRecorder {
+    /// Time in seconds, node id, temperature in deg C and volume in mL.
+    pub const CSV_HEADER: &'static str = "t,node_id,temp,volume";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a row for every node at the simulation's current time.
+    pub fn record(&mut self, simulation: &Simulation) {
+        let seconds = simulation.clock().seconds();
+        self.rows.extend(
+            simulation
+                .nodes()
+                .iter_with_ids()
+                .map(|(node, state)| RecorderRow {
+                    seconds,
+                    node,
+                    temp: state.fluid.temp,
+                    volume: state.fluid.volume,
+                }),
+        );
+    }
+
+    /// Oldest first, and in node order within each recording.
+    pub fn rows(&self) -> &[RecorderRow] {
+        &self.rows
+    }
+
+    pub fn clear(&mut self) {
+        self.rows.clear();
+    }
+
+    /// Writes the header then a line per row.
+    pub fn write_csv_to(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        writeln!(out, "{}", Self::CSV_HEADER)?;
+        for row in &self.rows {
+            writeln!(
+                out,
+                "{},{},{},{}",
+                row.seconds, row.node, row.temp, row.volume
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        // Writing to a String can't fail
+        let _ = self.write_csv_to(&mut csv);
+        csv
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write_csv(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_csv())
+    }
+}
+
+/// The rows as one batch, with the same columns as
+/// [`SimulationRecorder::CSV_HEADER`].
+#[cfg(feature = "parquet")]
+fn record_batch(rows: &[RecorderRow]) -> arrow_array::RecordBatch {
+    use alloc::sync::Arc;
+    use arrow_array::{ArrayRef, Float32Array, Float64Array, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema};
+
+    let schema = Schema::new(vec![
+        Field::new("t", DataType::Float64, false),
+        Field::new("node_id", DataType::UInt64, false),
+        Field::new("temp", DataType::Float32, false),
+        Field::new("volume", DataType::Float32, false),
+    ]);
+    let columns: [ArrayRef; 4] = [
+        Arc::new(Float64Array::from_iter_values(
+            rows.iter().map(|row| row.seconds),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.node as u64),
+        )),
+        Arc::new(Float32Array::from_iter_values(
+            rows.iter().map(|row| row.temp),
+        )),
+        Arc::new(Float32Array::from_iter_values(
+            rows.iter().map(|row| row.volume),
+        )),
+    ];
+    // The columns always match the schema
+    arrow_array::RecordBatch::try_new(Arc::new(schema), columns.into()).unwrap()
+}
+
+/// Like [`SimulationRecorder`] but streams the rows to Parquet as they're
+/// recorded, so long runs don't have to fit in memory. Rows are written out
+/// a row group at a time, every [`Self::ROW_GROUP_SIZE`] rows by default.
+#[cfg(feature = "parquet")]
+pub struct ParquetRecorder<W: std::io::Write + Send> {
+    writer: parquet::arrow::ArrowWriter<W>,
+    rows: Vec<RecorderRow>,
+}
+
+#[cfg(feature = "parquet")]
+impl ParquetRecorder<std::fs::File> {
+    pub fn create(
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Self, parquet::errors::ParquetError> {
+        Self::new(std::fs::File::create(path)?)
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl<W: std::io::Write + Send> ParquetRecorder<W> {
+    pub const ROW_GROUP_SIZE: usize = 1024 * 1024;
+
+    pub fn new(out: W) -> Result<Self, parquet::errors::ParquetError> {
+        Self::with_row_group_size(out, Self::ROW_GROUP_SIZE)
+    }
+
+    /// Flushes a row group every `rows` rows instead.
+    pub fn with_row_group_size(out: W, rows: usize) -> Result<Self, parquet::errors::ParquetError> {
+        let properties = parquet::file::properties::WriterProperties::builder()
+            .set_max_row_group_size(rows)
+            .build();
+        let schema = record_batch(&[]).schema();
+        Ok(Self {
+            writer: parquet::arrow::ArrowWriter::try_new(out, schema, Some(properties))?,
+            rows: Vec::new(),
+        })
+    }
+
+    /// Writes a row for every node at the simulation's current time.
+    pub fn record(&mut self, simulation: &Simulation) -> Result<(), parquet::errors::ParquetError> {
+        let seconds = simulation.clock().seconds();
+        // Reused so recording doesn't allocate every tick
+        self.rows.clear();
+        self.rows.extend(
+            simulation
+                .nodes()
+                .iter_with_ids()
+                .map(|(node, state)| RecorderRow {
+                    seconds,
+                    node,
+                    temp: state.fluid.temp,
+                    volume: state.fluid.volume,
+                }),
+        );
+        self.writer.write(&record_batch(&self.rows))
+    }
+
+    /// How many row groups have been written out so far.
+    pub fn row_groups(&self) -> usize {
+        self.writer.flushed_row_groups().len()
+    }
+
+    /// Writes whatever's left and the file footer, and hands back `out`.
+    pub fn write(self) -> Result<W, parquet::errors::ParquetError> {
+        self.writer.into_inner()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::Environment;
+
+    #[test]
+    fn test_write_csv() {
+        let mut simulation = Simulation::new();
+        simulation.add_node(10.0, 80.0, 0.5, 100.0, glam::Vec3::ZERO);
+        simulation.add_node(20.0, 10.0, 0.5, 100.0, glam::Vec3::X);
+        let mut recorder = SimulationRecorder::new();
+        for _ in 0..5 {
+            simulation.tick(&Environment::default(), 0.5);
+            recorder.record(&simulation);
+        }
+        assert_eq!(recorder.rows().len(), 10);
+
+        let path = temp_path("csv");
+        recorder.write_csv(&path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut lines = csv.lines();
+        let header = lines.next().unwrap().split(',').collect::<Vec<_>>();
+        assert_eq!(header, ["t", "node_id", "temp", "volume"]);
+        let rows = lines
+            .map(|line| {
+                let fields = line.split(',').collect::<Vec<_>>();
+                assert_eq!(fields.len(), header.len());
+                (
+                    fields[0].parse::<f64>().unwrap(),
+                    fields[1].parse::<usize>().unwrap(),
+                    fields[2].parse::<f32>().unwrap(),
+                    fields[3].parse::<f32>().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 10);
+        assert_eq!(rows[0].0, 0.5);
+        assert_eq!(rows[9].0, 2.5);
+        assert_eq!(rows[9].1, 1);
+        // Round trips exactly
+        let last = recorder.rows()[9];
+        assert_eq!((rows[9].2, rows[9].3), (last.temp, last.volume));
+
+        recorder.clear();
+        assert_eq!(recorder.to_csv(), "t,node_id,temp,volume\n");
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_write_parquet() {
+        use arrow_array::cast::AsArray;
+        use arrow_array::types::{Float32Type, Float64Type, UInt64Type};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let mut simulation = Simulation::new();
+        simulation.add_node(10.0, 80.0, 0.5, 100.0, glam::Vec3::ZERO);
+        simulation.add_node(20.0, 10.0, 0.5, 100.0, glam::Vec3::X);
+        simulation.add_node(30.0, 10.0, 0.5, 100.0, glam::Vec3::Y);
+        let path = temp_path("parquet");
+        // Small row groups so a few ticks already span several
+        let file = std::fs::File::create(&path).unwrap();
+        let mut parquet = ParquetRecorder::with_row_group_size(file, 4).unwrap();
+        let mut csv = SimulationRecorder::new();
+        for _ in 0..5 {
+            simulation.tick(&Environment::default(), 0.5);
+            parquet.record(&simulation).unwrap();
+            csv.record(&simulation);
+        }
+        // Flushed while recording, not just at the end
+        assert_eq!(parquet.row_groups(), 3);
+        parquet.write().unwrap();
+
+        let file = std::fs::File::open(&path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 4);
+        let batches = reader
+            .build()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let names = batches[0]
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            SimulationRecorder::CSV_HEADER
+                .split(',')
+                .collect::<Vec<_>>()
+        );
+
+        let rows = batches
+            .iter()
+            .flat_map(|batch| {
+                let seconds = batch.column(0).as_primitive::<Float64Type>();
+                let nodes = batch.column(1).as_primitive::<UInt64Type>();
+                let temps = batch.column(2).as_primitive::<Float32Type>();
+                let volumes = batch.column(3).as_primitive::<Float32Type>();
+                (0..batch.num_rows())
+                    .map(|i| RecorderRow {
+                        seconds: seconds.value(i),
+                        node: nodes.value(i) as usize,
+                        temp: temps.value(i),
+                        volume: volumes.value(i),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 15);
+        assert_eq!(rows, csv.rows());
+    }
+
+    /// Unique to the process, and each test uses its own extension, so runs
+    /// at the same time don't clobber each other's files.
+    fn temp_path(extension: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "passive_logic_recorder_test_{}.{extension}",
+            std::process::id()
+        ))
+    }
+}
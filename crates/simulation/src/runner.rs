@@ -0,0 +1,175 @@
+//! Steps a simulation with nothing drawing it, e.g. in CI or on a server.
+
+use alloc::vec::Vec;
+
+use crate::{Environment, Fluid, Simulation};
+
+/// Every node's fluid at one point in a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunnerSample {
+    /// Simulated seconds, see [`crate::SimClock::seconds`].
+    pub seconds: f64,
+    /// Indexed by node id, with `None` for removed nodes.
+    pub fluids: Vec<Option<Fluid>>,
+}
+
+/// Owns a [`Simulation`] and its [`Environment`] and records the state of
+/// every node after each step.
+///
+/// ```
+/// use passive_logic_simulation::{Environment, SimulationRunner, Simulation, SolarPanel};
+///
+/// let mut simulation = Simulation::new();
+/// let panel = simulation.add_node(10.0, 20.0, 0.9, 100.0, glam::Vec3::ZERO);
+/// let tank = simulation.add_node(10.0, 20.0, 0.9, 100.0, glam::Vec3::X);
+/// simulation.connect_node(panel, tank, 1.0);
+/// simulation.connect_node(tank, panel, 1.0);
+/// simulation.attach_solar_panel(panel, SolarPanel { area: 1.0, efficiency: 0.9 });
+///
+/// let mut runner = SimulationRunner::new(simulation, Environment::default());
+/// assert_eq!(runner.run_for(60.0, 0.5), 120);
+///
+/// // The starting state plus one sample per step
+/// assert_eq!(runner.history().len(), 121);
+/// assert_eq!(runner.simulation().clock().seconds(), 60.0);
+/// let (_, first) = runner.node_history(tank).next().unwrap();
+/// let (_, last) = runner.node_history(tank).last().unwrap();
+/// assert!(last.temp > first.temp);
+/// ```
+#[derive(Debug)]
+pub struct SimulationRunner {
+    simulation: Simulation,
+    environment: Environment,
+    history: Vec<RunnerSample>,
+}
+
+impl SimulationRunner {
+    pub fn new(simulation: Simulation, environment: Environment) -> Self {
+        let mut runner = Self {
+            simulation,
+            environment,
+            history: Vec::new(),
+        };
+        runner.record();
+        runner
+    }
+
+    pub fn simulation(&self) -> &Simulation {
+        &self.simulation
+    }
+
+    /// Nodes added through this start being recorded on the next step.
+    pub fn simulation_mut(&mut self) -> &mut Simulation {
+        &mut self.simulation
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    pub fn environment_mut(&mut self) -> &mut Environment {
+        &mut self.environment
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        self.simulation.tick(&self.environment, dt);
+        self.record();
+    }
+
+    /// Steps by `dt` until `duration` seconds have passed, shortening the
+    /// last step to land on it exactly. Returns how many steps ran.
+    pub fn run_for(&mut self, duration: f32, dt: f32) -> usize {
+        if dt <= 0.0 || !dt.is_finite() || !duration.is_finite() {
+            return 0;
+        }
+
+        let end = self.simulation.clock().seconds() + f64::from(duration);
+        let mut steps = 0;
+        loop {
+            let remaining = (end - self.simulation.clock().seconds()) as f32;
+            // Don't take a sliver of a step just because of rounding
+            if remaining <= dt * 1e-3 {
+                break;
+            }
+            self.step(dt.min(remaining));
+            steps += 1;
+        }
+        steps
+    }
+
+    /// The starting state followed by the state after each step.
+    pub fn history(&self) -> &[RunnerSample] {
+        &self.history
+    }
+
+    /// When and what node `id` held in each sample it's in. Node ids don't
+    /// change, so this stays the same node's history after others are
+    /// removed.
+    pub fn node_history(&self, id: usize) -> impl Iterator<Item = (f64, Fluid)> + '_ {
+        self.history
+            .iter()
+            .filter_map(move |sample| Some((sample.seconds, (*sample.fluids.get(id)?)?)))
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    pub fn into_inner(self) -> (Simulation, Environment) {
+        (self.simulation, self.environment)
+    }
+
+    fn record(&mut self) {
+        self.history.push(RunnerSample {
+            seconds: self.simulation.clock().seconds(),
+            fluids: self
+                .simulation
+                .nodes()
+                .slots()
+                .iter()
+                .map(|slot| slot.as_ref().map(|node| node.fluid))
+                .collect(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_for() {
+        let mut simulation = Simulation::new();
+        simulation.add_node(10.0, 80.0, 0.5, 100.0, glam::Vec3::ZERO);
+        let mut runner = SimulationRunner::new(simulation, Environment::default());
+
+        // The last step is cut short to end on time
+        assert_eq!(runner.run_for(1.0, 0.3), 4);
+        assert!((runner.simulation().clock().seconds() - 1.0).abs() < 1e-6);
+        assert_eq!(runner.run_for(1.0, 0.0), 0);
+
+        let temps = runner
+            .node_history(0)
+            .map(|(_, fluid)| fluid.temp)
+            .collect::<Vec<_>>();
+        assert_eq!(temps.len(), 5);
+        assert!(temps.windows(2).all(|pair| pair[1] < pair[0]));
+
+        // Nodes added partway through show up from then on
+        runner
+            .simulation_mut()
+            .add_node(10.0, 20.0, 0.5, 100.0, glam::Vec3::X);
+        runner.step(0.5);
+        assert_eq!(runner.node_history(1).count(), 1);
+        assert_eq!(runner.history().len(), 6);
+
+        // Removing a node ends its history without touching the others'
+        runner.simulation_mut().remove_node(0);
+        runner.step(0.5);
+        assert_eq!(runner.node_history(0).count(), 6);
+        assert_eq!(runner.node_history(1).count(), 2);
+
+        runner.clear_history();
+        assert!(runner.history().is_empty());
+    }
+}
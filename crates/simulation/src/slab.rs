@@ -0,0 +1,152 @@
+//! Things looked up by ids that stay put, like a simulation's nodes and
+//! connections.
+
+use alloc::vec::Vec;
+use core::ops::{Index, IndexMut};
+
+/// Values indexed by id like a slice, see [`crate::Nodes`] and
+/// [`crate::Connections`].
+///
+/// Removing a value leaves a gap behind instead of moving the values after it
+/// down, and its id is never handed out again. Ids kept elsewhere, e.g. by
+/// hooks or a UI, keep pointing at the same value, and once it's gone
+/// [`Self::get`] returns `None` for them. Indexing with such an id panics,
+/// like indexing a slice out of bounds.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct Slab<T> {
+    /// `None` where a value was removed.
+    slots: Vec<Option<T>>,
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Self { slots: Vec::new() }
+    }
+}
+
+impl<T> Slab<T> {
+    /// How many values there are, not counting removed ones.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// One more than the highest id handed out so far, including removed
+    /// values' ids.
+    pub fn id_bound(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&T> {
+        self.slots.get(id)?.as_ref()
+    }
+
+    pub(crate) fn get_mut(&mut self, id: usize) -> Option<&mut T> {
+        self.slots.get_mut(id)?.as_mut()
+    }
+
+    /// In id order, skipping removed values.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> + '_ {
+        self.slots.iter().flatten()
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut T> + '_ {
+        self.slots.iter_mut().flatten()
+    }
+
+    /// Like [`Self::iter`], with each value's id.
+    pub fn iter_with_ids(&self) -> impl DoubleEndedIterator<Item = (usize, &T)> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(id, slot)| Some((id, slot.as_ref()?)))
+    }
+
+    pub(crate) fn iter_mut_with_ids(
+        &mut self,
+    ) -> impl DoubleEndedIterator<Item = (usize, &mut T)> + '_ {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(id, slot)| Some((id, slot.as_mut()?)))
+    }
+
+    /// The ids of the values, in order.
+    pub fn ids(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.iter_with_ids().map(|(id, _)| id)
+    }
+
+    /// Every id up to [`Self::id_bound`], with `None` for removed values.
+    pub(crate) fn slots(&self) -> &[Option<T>] {
+        &self.slots
+    }
+
+    pub(crate) fn slots_mut(&mut self) -> &mut [Option<T>] {
+        &mut self.slots
+    }
+
+    pub(crate) fn into_slots(self) -> Vec<Option<T>> {
+        self.slots
+    }
+
+    /// Returns the new value's id.
+    pub(crate) fn push(&mut self, value: T) -> usize {
+        self.slots.push(Some(value));
+        self.slots.len() - 1
+    }
+
+    pub(crate) fn extend_slots(&mut self, slots: impl IntoIterator<Item = Option<T>>) {
+        self.slots.extend(slots);
+    }
+
+    /// Leaves a gap at `id`, see [`Slab`].
+    pub(crate) fn remove(&mut self, id: usize) -> Option<T> {
+        self.slots.get_mut(id)?.take()
+    }
+
+    /// Removes the values `keep` returns `false` for, leaving gaps.
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        for slot in &mut self.slots {
+            if slot.as_ref().is_some_and(|value| !keep(value)) {
+                *slot = None;
+            }
+        }
+    }
+}
+
+impl<T> Index<usize> for Slab<T> {
+    type Output = T;
+
+    fn index(&self, id: usize) -> &T {
+        self.get(id)
+            .unwrap_or_else(|| panic!("nothing with id {id}"))
+    }
+}
+
+impl<T> IndexMut<usize> for Slab<T> {
+    fn index_mut(&mut self, id: usize) -> &mut T {
+        self.get_mut(id)
+            .unwrap_or_else(|| panic!("nothing with id {id}"))
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Slab<T> {
+    type Item = &'a T;
+    type IntoIter = core::iter::Flatten<core::slice::Iter<'a, Option<T>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.slots.iter().flatten()
+    }
+}
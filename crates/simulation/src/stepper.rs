@@ -0,0 +1,98 @@
+//! Runs the simulation at a fixed rate no matter how often it's stepped.
+
+use core::time::Duration;
+
+use crate::{Environment, Simulation};
+
+/// Accumulates elapsed time and runs whole ticks of `step` out of it.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedStepper {
+    step: Duration,
+    accumulator: Duration,
+    max_substeps: u32,
+}
+
+impl FixedStepper {
+    /// Enough to catch up on a few dropped frames without stalling.
+    pub const DEFAULT_MAX_SUBSTEPS: u32 = 10;
+
+    pub fn new(step: Duration) -> Self {
+        Self {
+            step,
+            accumulator: Duration::ZERO,
+            max_substeps: Self::DEFAULT_MAX_SUBSTEPS,
+        }
+    }
+
+    /// Caps how many ticks one call to [`Self::step`] can run, so a long
+    /// pause doesn't turn into thousands of ticks that freeze the app.
+    pub fn with_max_substeps(mut self, max_substeps: u32) -> Self {
+        self.max_substeps = max_substeps.max(1);
+        self
+    }
+
+    /// Adds `elapsed` and ticks `simulation` once per whole step. Time past
+    /// the substep cap is dropped. Returns how many ticks ran.
+    pub fn step(
+        &mut self,
+        simulation: &mut Simulation,
+        environment: &Environment,
+        elapsed: Duration,
+    ) -> u32 {
+        if self.step.is_zero() {
+            return 0;
+        }
+
+        self.accumulator += elapsed;
+        let dt = self.step.as_secs_f32();
+        let mut substeps = 0;
+        while self.accumulator >= self.step {
+            if substeps == self.max_substeps {
+                log::warn!(
+                    "Dropping {:?} of simulation time after {substeps} ticks",
+                    self.accumulator
+                );
+                self.accumulator = Duration::ZERO;
+                break;
+            }
+
+            simulation.tick(environment, dt);
+            self.accumulator -= self.step;
+            substeps += 1;
+        }
+        substeps
+    }
+
+    /// How far into the next step the accumulated time is, from 0 to 1.
+    /// Handy for [`Simulation::interpolate`].
+    pub fn alpha(&self) -> f32 {
+        if self.step.is_zero() {
+            return 0.0;
+        }
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_substeps() {
+        let mut sim = Simulation::new();
+        let environment = Environment::default();
+        let mut stepper = FixedStepper::new(Duration::from_millis(16)).with_max_substeps(5);
+
+        assert_eq!(
+            stepper.step(&mut sim, &environment, Duration::from_secs(10)),
+            5
+        );
+        assert_eq!(stepper.alpha(), 0.0);
+
+        assert_eq!(
+            stepper.step(&mut sim, &environment, Duration::from_millis(40)),
+            2
+        );
+        assert!((stepper.alpha() - 0.5).abs() < 1e-3);
+    }
+}
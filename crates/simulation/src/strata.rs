@@ -0,0 +1,183 @@
+//! Stratified nodes, which keep their fluid in layers stacked by temperature
+//! instead of fully mixed, like a real thermal store with hot water on top.
+
+use alloc::{vec, vec::Vec};
+
+use crate::{properties::FluidProperties, Fluid};
+
+/// How a node holds its fluid, see [`crate::Simulation::set_node_model`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NodeModel {
+    /// Everything in the node is one temperature.
+    #[default]
+    Mixed,
+    /// `layers` layers stacked bottom to top, coldest to hottest.
+    ///
+    /// Solar heat goes into the top layer and inflow joins the layer closest
+    /// to its temperature, so hot fluid ends up on top. Outflow is drawn
+    /// from the bottom, where the coldest fluid is. Stages that act on the
+    /// whole node, like heat loss, shift every layer by the same amount.
+    Stratified { layers: usize },
+}
+
+/// Splits `fluid` into `layers` equal layers.
+pub(crate) fn split(fluid: Fluid, layers: usize) -> Vec<Fluid> {
+    let layers = layers.max(1);
+    vec![
+        Fluid {
+            volume: fluid.volume / layers as f32,
+            temp: fluid.temp,
+        };
+        layers
+    ]
+}
+
+/// Everything in `layers` mixed together.
+pub(crate) fn total(layers: &[Fluid]) -> Fluid {
+    layers.iter().fold(
+        Fluid {
+            volume: 0.0,
+            temp: layers.first().map_or(0.0, |layer| layer.temp),
+        },
+        |total, &layer| total + layer,
+    )
+}
+
+/// Hot fluid rises, so layers are reordered coldest to hottest.
+pub(crate) fn settle(layers: &mut [Fluid]) {
+    layers.sort_by(|a, b| a.temp.total_cmp(&b.temp));
+}
+
+/// Mixes `incoming` into the layer closest to its temperature, or makes it
+/// a layer of its own if one has been drained. `properties` are the node's
+/// and `incoming_properties` the fluid's.
+pub(crate) fn add_inflow(
+    layers: &mut [Fluid],
+    properties: &FluidProperties,
+    incoming_properties: &FluidProperties,
+    incoming: Fluid,
+) {
+    let distance = |layer: &Fluid| (layer.temp - incoming.temp).abs();
+    let empty = layers.iter().position(|layer| layer.volume <= 0.0);
+    let closest = || {
+        layers
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))
+            .map(|(i, _)| i)
+    };
+    let Some(i) = empty.or_else(closest) else {
+        return;
+    };
+    let layer = &mut layers[i];
+    *layer = properties.mix(*layer, incoming_properties, incoming);
+    settle(layers);
+}
+
+/// Takes `volume` from the bottom up, returning what was taken.
+pub(crate) fn draw(layers: &mut [Fluid], volume: f32) -> Fluid {
+    let mut remaining = volume;
+    let mut drawn = Fluid {
+        volume: 0.0,
+        temp: layers.first().map_or(0.0, |layer| layer.temp),
+    };
+    for layer in layers.iter_mut() {
+        if remaining <= 0.0 {
+            break;
+        }
+        let taken = remaining.min(layer.volume);
+        layer.volume -= taken;
+        remaining -= taken;
+        drawn += Fluid {
+            volume: taken,
+            temp: layer.temp,
+        };
+    }
+    drawn
+}
+
+/// Brings `layers` in line with `fluid` after something changed the node as
+/// a whole. Volume is scaled and temperature shifted evenly across them.
+pub(crate) fn sync(layers: &mut [Fluid], fluid: Fluid) {
+    let current = total(layers);
+    if current.volume <= 0.0 {
+        layers.fill(Fluid {
+            volume: fluid.volume / layers.len().max(1) as f32,
+            temp: fluid.temp,
+        });
+        return;
+    }
+
+    let scale = fluid.volume / current.volume;
+    let shift = fluid.temp - current.temp;
+    for layer in layers.iter_mut() {
+        layer.volume *= scale;
+        layer.temp += shift;
+    }
+}
+
+/// Where solar heat goes, the highest layer that isn't empty.
+pub(crate) fn top(layers: &mut [Fluid]) -> Option<&mut Fluid> {
+    layers.iter_mut().rev().find(|layer| layer.volume > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layers() {
+        let water = FluidProperties::water();
+        let mut layers = split(
+            Fluid {
+                volume: 30.0,
+                temp: 20.0,
+            },
+            3,
+        );
+        assert_eq!(layers.len(), 3);
+        assert_eq!(total(&layers).volume, 30.0);
+
+        // Hot inflow joins the top layer and colder inflow one further down
+        let hot = Fluid {
+            volume: 10.0,
+            temp: 80.0,
+        };
+        add_inflow(&mut layers, &water, &water, hot);
+        assert_eq!(layers[2].temp, 50.0);
+        let warm = Fluid {
+            volume: 10.0,
+            temp: 30.0,
+        };
+        add_inflow(&mut layers, &water, &water, warm);
+        assert_eq!(
+            layers.iter().map(|layer| layer.temp).collect::<Vec<_>>(),
+            [20.0, 25.0, 50.0]
+        );
+
+        // The coldest fluid leaves first
+        let drawn = draw(&mut layers, 15.0);
+        assert_eq!(drawn.volume, 15.0);
+        assert!((drawn.temp - (10.0 * 20.0 + 5.0 * 25.0) / 15.0).abs() < 1e-5);
+        assert_eq!(layers[0].volume, 0.0);
+        assert_eq!(total(&layers).volume, 35.0);
+
+        let cooled = Fluid {
+            volume: 70.0,
+            temp: total(&layers).temp - 5.0,
+        };
+        sync(&mut layers, cooled);
+        assert_eq!(layers[1].volume, 30.0);
+        assert_eq!(layers[2].temp, 45.0);
+
+        // Refills the drained layer, which sinks back to the bottom
+        let cold = Fluid {
+            volume: 10.0,
+            temp: 10.0,
+        };
+        add_inflow(&mut layers, &water, &water, cold);
+        assert_eq!(layers[0], cold);
+        assert_eq!(top(&mut layers).unwrap().temp, 45.0);
+    }
+}
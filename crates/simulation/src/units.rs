@@ -0,0 +1,118 @@
+use alloc::{format, string::String};
+
+use crate::Fluid;
+
+/// Internally volumes are stored in mL and temperatures in degrees Celsius.
+pub const ML_PER_LITER: f32 = 1000.0;
+pub const ML_PER_GALLON: f32 = 3785.4118;
+
+pub fn celsius_to_fahrenheit(celsius: f32) -> f32 {
+    celsius * 9.0 / 5.0 + 32.0
+}
+
+pub fn fahrenheit_to_celsius(fahrenheit: f32) -> f32 {
+    (fahrenheit - 32.0) * 5.0 / 9.0
+}
+
+impl Fluid {
+    pub fn from_liters_celsius(liters: f32, celsius: f32) -> Self {
+        Self {
+            volume: liters * ML_PER_LITER,
+            temp: celsius,
+        }
+    }
+
+    pub fn from_gallons_fahrenheit(gallons: f32, fahrenheit: f32) -> Self {
+        Self {
+            volume: gallons * ML_PER_GALLON,
+            temp: fahrenheit_to_celsius(fahrenheit),
+        }
+    }
+
+    pub fn volume_liters(&self) -> f32 {
+        self.volume / ML_PER_LITER
+    }
+
+    pub fn volume_gallons(&self) -> f32 {
+        self.volume / ML_PER_GALLON
+    }
+
+    pub fn temp_fahrenheit(&self) -> f32 {
+        celsius_to_fahrenheit(self.temp)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UnitSystem {
+    #[default]
+    Metric,
+    Imperial,
+}
+
+impl UnitSystem {
+    /// Formats a volume given in mL.
+    pub fn format_volume(&self, volume: f32) -> String {
+        match self {
+            UnitSystem::Metric => format!("{volume:.2} mL"),
+            UnitSystem::Imperial => format!("{:.2} gal", volume / ML_PER_GALLON),
+        }
+    }
+
+    /// Formats a temperature given in degrees Celsius.
+    pub fn format_temp(&self, temp: f32) -> String {
+        match self {
+            UnitSystem::Metric => format!("{temp:.2} C"),
+            UnitSystem::Imperial => format!("{:.2} F", celsius_to_fahrenheit(temp)),
+        }
+    }
+
+    pub fn format_fluid(&self, fluid: &Fluid) -> String {
+        format!(
+            "{} @ {}",
+            self.format_volume(fluid.volume),
+            self.format_temp(fluid.temp)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_round_trip() {
+        for celsius in [-40.0, 0.0, 20.0, 37.5, 100.0] {
+            let fahrenheit = celsius_to_fahrenheit(celsius);
+            assert!((fahrenheit_to_celsius(fahrenheit) - celsius).abs() < 1e-4);
+        }
+
+        assert_eq!(celsius_to_fahrenheit(-40.0), -40.0);
+        assert_eq!(celsius_to_fahrenheit(100.0), 212.0);
+    }
+
+    #[test]
+    fn test_fluid_round_trip() {
+        let fluid = Fluid::from_liters_celsius(2.5, 60.0);
+        assert_eq!(fluid.volume, 2500.0);
+        assert_eq!(fluid.volume_liters(), 2.5);
+        assert!((fluid.temp_fahrenheit() - 140.0).abs() < 1e-4);
+
+        let fluid = Fluid::from_gallons_fahrenheit(1.0, 212.0);
+        assert!((fluid.volume - ML_PER_GALLON).abs() < 1e-3);
+        assert!((fluid.volume_gallons() - 1.0).abs() < 1e-6);
+        assert!((fluid.temp - 100.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_format() {
+        let fluid = Fluid::from_liters_celsius(1.0, 100.0);
+        assert_eq!(
+            UnitSystem::Metric.format_fluid(&fluid),
+            "1000.00 mL @ 100.00 C"
+        );
+        assert_eq!(
+            UnitSystem::Imperial.format_fluid(&fluid),
+            "0.26 gal @ 212.00 F"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! Renders a connection running into a node with and without a depth bias
+//! and saves both, so the z-fighting where they meet can be compared.
+//!
+//! Run with `cargo run --example depth_bias`. Writes `depth_bias_off.png` and
+//! `depth_bias_on.png` to the working directory.
+
+use passive_logic_challenge::{
+    resources::{
+        buffer::BackedBuffer,
+        camera::{CameraBinder, PerspectiveCamera},
+        light::LightBinder,
+        model::{MaterialBinder, ModelPipeline},
+        texture::Texture,
+        vertex::ColoredInstance,
+        FsResources,
+    },
+    simulation::visualization::{VisualizationPipeline, CONNECTION_DEPTH_BIAS},
+};
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let instance = wgpu::Instance::new(&Default::default());
+    let adapter = pollster::block_on(instance.request_adapter(&Default::default()))?;
+    let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+        required_limits: wgpu::Limits::downlevel_defaults(),
+        ..Default::default()
+    }))?;
+
+    let res = FsResources::new(concat!(env!("CARGO_MANIFEST_DIR"), "/res"));
+    let camera_binder = CameraBinder::new(&device);
+    let material_binder = MaterialBinder::new(&device);
+    let light_binder = LightBinder::new(&device);
+    let mut model_pipeline = ModelPipeline::new(
+        &device,
+        COLOR_FORMAT,
+        DEPTH_FORMAT,
+        &camera_binder,
+        &material_binder,
+        &light_binder,
+        1,
+    );
+    let node_model = model_pipeline.load_obj(
+        &device,
+        &queue,
+        &material_binder,
+        &res,
+        "models/spherical-cube.obj",
+    )?;
+    let connection_model = model_pipeline.load_obj(
+        &device,
+        &queue,
+        &material_binder,
+        &res,
+        "models/connection.obj",
+    )?;
+
+    // Close up on where a connection enters a node
+    let camera = PerspectiveCamera::new(
+        glam::vec3(-0.1, 0.05, 0.3),
+        -std::f32::consts::FRAC_PI_2,
+        0.0,
+        WIDTH,
+        HEIGHT,
+        std::f32::consts::FRAC_PI_4,
+        0.1,
+        100.0,
+    );
+    let camera_binding = camera_binder.bind(&device, &camera);
+
+    let node = glam::vec3(0.0, 0.0, 0.0);
+    let node_instances = BackedBuffer::with_data(
+        &device,
+        vec![ColoredInstance::with_position_scale(
+            glam::vec3(1.0, 0.0, 0.0),
+            node,
+            0.1,
+        )],
+        wgpu::BufferUsages::VERTEX,
+    );
+    let connection_instances = BackedBuffer::with_data(
+        &device,
+        vec![ColoredInstance::extend_between(
+            glam::vec3(0.0, 0.0, 1.0),
+            node,
+            glam::vec3(-1.0, 0.0, 0.0),
+            0.02,
+        )],
+        wgpu::BufferUsages::VERTEX,
+    );
+
+    let node_pipeline = VisualizationPipeline::new(
+        &device,
+        COLOR_FORMAT,
+        DEPTH_FORMAT,
+        &camera_binder,
+        Default::default(),
+        1,
+    );
+
+    let size = wgpu::Extent3d {
+        width: WIDTH,
+        height: HEIGHT,
+        depth_or_array_layers: 1,
+    };
+    let color_texture = Texture::from_descriptor(
+        &device,
+        &wgpu::TextureDescriptor {
+            label: Some("color"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: COLOR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        },
+        Default::default(),
+    );
+    let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("depth"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    let depth_view = depth_texture.create_view(&Default::default());
+
+    for (path, bias) in [
+        ("depth_bias_off.png", wgpu::DepthBiasState::default()),
+        ("depth_bias_on.png", CONNECTION_DEPTH_BIAS),
+    ] {
+        let connection_pipeline = VisualizationPipeline::new(
+            &device,
+            COLOR_FORMAT,
+            DEPTH_FORMAT,
+            &camera_binder,
+            bias,
+            1,
+        );
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &color_texture.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                ..Default::default()
+            });
+
+            node_pipeline.draw(
+                &mut pass,
+                node_model,
+                &model_pipeline,
+                &camera_binding,
+                &node_instances,
+            );
+            connection_pipeline.draw(
+                &mut pass,
+                connection_model,
+                &model_pipeline,
+                &camera_binding,
+                &connection_instances,
+            );
+        }
+        queue.submit([encoder.finish()]);
+
+        let image = color_texture.read_to_image(&device, &queue)?;
+        let connection_pixels = image.pixels().filter(|p| p[2] > p[0]).count();
+        image.save(path)?;
+        println!("{path}: {connection_pixels} connection pixels");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,189 @@
+//! Renders a grid of nodes with the heatmap overlay off and on. In the app
+//! the overlay is toggled with H.
+//!
+//! Run with `cargo run --example heatmap`. Writes `heatmap_off.png` and
+//! `heatmap_on.png` to the working directory.
+
+use passive_logic_challenge::{
+    resources::{
+        buffer::BackedBuffer,
+        camera::{CameraBinder, PerspectiveCamera},
+        light::LightBinder,
+        model::{MaterialBinder, ModelPipeline},
+        texture::Texture,
+        vertex::ColoredInstance,
+        FsResources,
+    },
+    simulation::{heatmap::HeatmapPipeline, visualization::VisualizationPipeline, Simulation},
+};
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let instance = wgpu::Instance::new(&Default::default());
+    let adapter = pollster::block_on(instance.request_adapter(&Default::default()))?;
+    let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+        required_limits: wgpu::Limits::downlevel_defaults(),
+        ..Default::default()
+    }))?;
+
+    // Hot in one corner, cold in the opposite one
+    let mut simulation = Simulation::new();
+    for y in 0..5 {
+        for x in 0..5 {
+            let position = glam::vec3(x as f32 - 2.0, y as f32 - 2.0, 0.0) * 0.4;
+            simulation.add_node(10.0, (x + y) as f32 * 12.5, 0.9, 100.0, position);
+        }
+    }
+
+    let res = FsResources::new(concat!(env!("CARGO_MANIFEST_DIR"), "/res"));
+    let camera_binder = CameraBinder::new(&device);
+    let material_binder = MaterialBinder::new(&device);
+    let light_binder = LightBinder::new(&device);
+    let mut model_pipeline = ModelPipeline::new(
+        &device,
+        COLOR_FORMAT,
+        DEPTH_FORMAT,
+        &camera_binder,
+        &material_binder,
+        &light_binder,
+        1,
+    );
+    let node_model = model_pipeline.load_obj(
+        &device,
+        &queue,
+        &material_binder,
+        &res,
+        "models/spherical-cube.obj",
+    )?;
+
+    let camera = PerspectiveCamera::new(
+        glam::vec3(0.0, 0.0, 3.0),
+        -std::f32::consts::FRAC_PI_2,
+        0.0,
+        WIDTH,
+        HEIGHT,
+        std::f32::consts::FRAC_PI_4,
+        0.1,
+        100.0,
+    );
+    let camera_binding = camera_binder.bind(&device, &camera);
+
+    let node_instances = BackedBuffer::with_data(
+        &device,
+        simulation
+            .nodes()
+            .iter()
+            .map(|node| {
+                ColoredInstance::with_position_scale(glam::Vec3::splat(0.8), node.position, 0.05)
+            })
+            .collect(),
+        wgpu::BufferUsages::VERTEX,
+    );
+    let node_pipeline = VisualizationPipeline::new(
+        &device,
+        COLOR_FORMAT,
+        DEPTH_FORMAT,
+        &camera_binder,
+        Default::default(),
+        1,
+    );
+    let mut heatmap_pipeline = HeatmapPipeline::new(&device, COLOR_FORMAT, 1);
+    heatmap_pipeline.update(
+        &device,
+        &queue,
+        &simulation,
+        &camera,
+        WIDTH as f32 / HEIGHT as f32,
+    );
+
+    let size = wgpu::Extent3d {
+        width: WIDTH,
+        height: HEIGHT,
+        depth_or_array_layers: 1,
+    };
+    let color_texture = Texture::from_descriptor(
+        &device,
+        &wgpu::TextureDescriptor {
+            label: Some("color"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: COLOR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        },
+        Default::default(),
+    );
+    let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("depth"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    let depth_view = depth_texture.create_view(&Default::default());
+
+    for (path, show_heatmap) in [("heatmap_off.png", false), ("heatmap_on.png", true)] {
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &color_texture.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                ..Default::default()
+            });
+            node_pipeline.draw(
+                &mut pass,
+                node_model,
+                &model_pipeline,
+                &camera_binding,
+                &node_instances,
+            );
+        }
+
+        if show_heatmap {
+            // The overlay goes over everything, so it gets a pass without depth
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &color_texture.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            heatmap_pipeline.draw(&mut pass);
+        }
+        queue.submit([encoder.finish()]);
+
+        color_texture.read_to_image(&device, &queue)?.save(path)?;
+        println!("Saved {path}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,13 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use passive_logic_challenge::resources::model::parse_obj;
+
+fuzz_target!(|data: &[u8]| {
+    // The loader should reject malformed input with an error, never a panic.
+    let _ = parse_obj(&mut Cursor::new(data), |_| {
+        Err(tobj::LoadError::OpenFileFailed)
+    });
+});
@@ -0,0 +1,39 @@
+//! Builds the simulation core without `std`. Run `cargo test` from this
+//! directory.
+
+#![no_std]
+
+use passive_logic_simulation::{Environment, Simulation, SolarPanel};
+
+/// Heats a tank with a solar panel and pumps it into a second tank, returning
+/// the temperature of the second tank after `ticks` seconds.
+pub fn run(ticks: usize) -> f32 {
+    let mut simulation = Simulation::new();
+    let panel = simulation.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::ZERO);
+    let tank = simulation.add_node(10.0, 20.0, 1.0, 100.0, glam::Vec3::X);
+    simulation.attach_solar_panel(
+        panel,
+        SolarPanel {
+            area: 1.0,
+            efficiency: 0.9,
+        },
+    );
+    simulation.connect_node(panel, tank, 1.0);
+
+    let environment = Environment::default();
+    for _ in 0..ticks {
+        simulation.tick(&environment, 1.0);
+    }
+
+    simulation.nodes()[tank].fluid.temp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        assert!(run(10) > 20.0);
+    }
+}
@@ -1,34 +1,137 @@
 use core::f32;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use anyhow::Context;
-use glam::FloatExt;
-use winit::{event::MouseButton, event_loop::ActiveEventLoop, keyboard::KeyCode, window::Window};
+use winit::{
+    event::{MouseButton, MouseScrollDelta},
+    event_loop::ActiveEventLoop,
+    keyboard::KeyCode,
+    window::Window,
+};
 
 use crate::{
     resources::{
         self,
         buffer::{self, BackedBuffer},
-        camera::{CameraBinder, CameraController, OrthoCamera, PerspectiveCamera},
-        font::{Font, TextPipeline},
-        light::{LightBinder, LightUniform},
-        model::{MaterialBinder, ModelPipeline},
-        texture::TextureBinder,
+        camera::{
+            world_to_screen, CameraBinder, CameraControl, CameraController, OrbitController,
+            OrthoCamera, PerspectiveCamera,
+        },
+        font::{Font, Label, LabelBatch, TextLayout, TextPipeline},
+        grid::GridPipeline,
+        light::{sun_direction, DirectionalLight, LightBinder, LightUniform},
+        model::{MaterialBinder, MeshData, ModelPipeline},
+        shadow::ShadowPipeline,
+        texture::{supported_sample_count, Texture, TextureBinder},
         vertex::{ColoredInstance, InstanceVertex},
-        FsResources,
     },
-    simulation::{visualization::VisualizationPipeline, Environment, Extractor, Simulation, SolarPanel},
-    utils::{rev_lerp, RenderPipelineBuilder},
+    scene::SceneState,
+    simulation::{
+        heatmap::HeatmapPipeline,
+        units::UnitSystem,
+        visualization::{VisualizationPipeline, CONNECTION_DEPTH_BIAS},
+        Environment, Extractor, FixedStepper, Fluid, Node, NodeDesc, Simulation, SolarPanel,
+    },
+    utils::{ray_sphere_intersection, request_adapter, rev_lerp, Ema, RenderPipelineBuilder},
 };
 
 const SIMULATION_TICK_RATE: web_time::Duration = web_time::Duration::from_millis(16);
-const SIMULATION_TICK_RATE_F32: f32 = SIMULATION_TICK_RATE.as_secs_f32();
+/// How much of [`Simulation::warm_start`] runs each frame, so a scene that
+/// takes long to settle doesn't hold up the first one.
+const WARM_START_TICKS_PER_FRAME: u32 = 1_000;
+
+/// How far from the cursor, in pixels, the hover tooltip starts.
+const TOOLTIP_OFFSET: glam::Vec2 = glam::vec2(16.0, 16.0);
+/// How far from the middle of its node, in pixels, a node's label starts, so
+/// it sits beside the node instead of over it.
+const NODE_LABEL_OFFSET: glam::Vec2 = glam::vec2(24.0, -8.0);
+const NODE_LABEL_SCALE: f32 = 0.5;
+
+/// How far the corners of the node model reach at a scale of one.
+const NODE_MODEL_RADIUS: f32 = 0.87;
+
+/// Where F5 saves the scene and F9 loads it from.
+const SCENE_PATH: &str = "scene.json";
+/// Where F12 saves a screenshot.
+#[cfg(not(target_arch = "wasm32"))]
+const SCREENSHOT_PATH: &str = "screenshot.png";
+/// Every file in `res` that [`Canvas::new`] loads.
+#[cfg(target_arch = "wasm32")]
+const WEB_RESOURCES: &[&str] = &[
+    "fonts/OpenSans MSDF.zip",
+    "models/spherical-cube.obj",
+    "models/spherical-cube.mtl",
+    "models/connection.obj",
+    "models/connection.mtl",
+];
+
+/// How much each new frame contributes to the displayed frame time.
+const FRAME_TIME_SMOOTHING: f32 = 0.05;
+
+pub(crate) const HOT_TEMP: f32 = 100.0;
+pub(crate) const HOT_COLOR: glam::Vec3 = glam::vec3(1.0, 0.0, 0.0);
+
+pub(crate) const COLD_TEMP: f32 = 0.0;
+pub(crate) const COLD_COLOR: glam::Vec3 = glam::vec3(0.0, 0.0, 1.0);
+
+/// Halfway between cold and hot.
+pub(crate) const WARM_COLOR: glam::Vec3 = glam::vec3(0.0, 1.0, 0.0);
+
+/// Sunlight on a clear day.
+const SUN_COLOR: glam::Vec3 = glam::vec3(1.0, 0.95, 0.85);
+/// Sine of the elevation the sun fades out below, about 6 degrees, so it
+/// doesn't switch off all at once at sunset.
+const SUN_FADE: f32 = 0.1;
+
+/// The ground the sun casts node shadows onto, below where nodes start out.
+const GROUND_HEIGHT: f32 = -1.0;
+const GROUND_SIZE: f32 = 4.0;
+/// How far around the nodes the sun's shadow map reaches.
+const SHADOW_RADIUS: f32 = 2.0;
+
+/// MSAA samples per pixel, lowered to what the adapter supports.
+const MSAA_SAMPLE_COUNT: u32 = 4;
+
+/// How thick connections are drawn per mL/s they carry, within
+/// `MIN_CONNECTION_RADIUS..=MAX_CONNECTION_RADIUS`.
+const CONNECTION_RADIUS_PER_FLOW: f32 = 0.002;
+const MIN_CONNECTION_RADIUS: f32 = 0.005;
+const MAX_CONNECTION_RADIUS: f32 = 0.02;
+
+/// Set to render to a HDR (`Rgba16Float`) surface when the display supports it.
+pub const PREFER_HDR_ENV: &str = "PLC_PREFER_HDR";
+
+/// Surfaces and textures can't be empty, so a minimized or not yet laid out
+/// window still gets a 1x1 surface.
+fn surface_size(width: u32, height: u32) -> (u32, u32) {
+    (width.max(1), height.max(1))
+}
+
+/// Picks the surface format and the format to view it as. HDR output is used
+/// if preferred and available, otherwise an sRGB format.
+fn select_surface_format(
+    formats: &[wgpu::TextureFormat],
+    prefer_hdr: bool,
+) -> Option<(wgpu::TextureFormat, wgpu::TextureFormat)> {
+    const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    if prefer_hdr && formats.contains(&HDR_FORMAT) {
+        return Some((HDR_FORMAT, HDR_FORMAT));
+    }
 
-const HOT_TEMP: f32 = 100.0;
-const HOT_COLOR: glam::Vec3 = glam::vec3(1.0, 0.0, 0.0);
+    // Surfaces can be viewed as the sRGB version of their format, so a non
+    // sRGB format that has one is as good as an sRGB one
+    let format = formats
+        .iter()
+        .copied()
+        .find(|format| format.add_srgb_suffix().is_srgb())
+        .or_else(|| formats.first().copied())?;
 
-const COLD_TEMP: f32 = 0.0;
-const COLD_COLOR: glam::Vec3 = glam::vec3(0.0, 0.0, 1.0);
+    Some((format, format.add_srgb_suffix()))
+}
+
+/// Maps a node (and its index) to the instance drawn for it.
+pub type NodeStyle = dyn Fn(usize, &Node) -> ColoredInstance;
 
 pub struct Canvas {
     surface: wgpu::Surface<'static>,
@@ -42,28 +145,43 @@ pub struct Canvas {
     ortho_camera_binding: resources::camera::CameraBinding,
     font: Font,
     text_pipeline: TextPipeline,
-    mspt_text: resources::font::TextBuffer,
-    frame_timer: web_time::Instant,
-    num_ticks: u32,
+    hud_text: resources::font::TextBuffer,
+    /// Shows the hovered node's fluid next to the cursor.
+    tooltip_text: resources::font::TextBuffer,
+    /// Each node's index and temperature, following it around the screen.
+    node_labels: LabelBatch,
     depth_texture: wgpu::Texture,
+    /// Where everything is drawn before being resolved into the frame, if
+    /// multisampling.
+    msaa_texture: Option<wgpu::Texture>,
+    /// Set while the window has no area, e.g. when minimized.
+    zero_sized: bool,
     model_pipeline: ModelPipeline,
     visualization_pipeline: VisualizationPipeline,
-    perspective_camera: PerspectiveCamera,
+    /// Like `visualization_pipeline` but with [`CONNECTION_DEPTH_BIAS`].
+    connection_pipeline: VisualizationPipeline,
+    heatmap_pipeline: HeatmapPipeline,
+    shadow_pipeline: ShadowPipeline,
+    /// Lines on the ground, shown while `state.show_grid` is set.
+    grid_pipeline: GridPipeline,
     perspective_camera_binding: resources::camera::CameraBinding,
-    camera_controller: CameraController,
-    light_buffer: BackedBuffer<LightUniform>,
+    lights: Vec<LightUniform>,
+    light_binder: LightBinder,
     light_binding: resources::light::LightBinding,
-    lmb_down: bool,
     gameplay_timer: web_time::Instant,
-    simulation_accumulator: web_time::Duration,
-    simulation: Simulation,
-    environment: Environment,
+    state: CanvasState,
     solar_panel: usize,
     extractor: usize,
+    /// Used for nodes without an entry in `node_models`.
     node_model: resources::model::ModelId,
+    node_models: HashMap<usize, resources::model::ModelId>,
     connection_model: resources::model::ModelId,
-    node_instances: buffer::BackedBuffer<ColoredInstance>,
+    /// One buffer per model so nodes sharing a model are drawn together.
+    node_instances: Vec<(resources::model::ModelId, BackedBuffer<ColoredInstance>)>,
+    node_style: Box<NodeStyle>,
     connection_instances: BackedBuffer<ColoredInstance>,
+    ground_model: resources::model::ModelId,
+    ground_instances: BackedBuffer<InstanceVertex>,
 }
 
 impl Canvas {
@@ -94,15 +212,12 @@ impl Canvas {
         log::info!("Creating surface");
         let surface = instance.create_surface(window.clone())?;
         log::info!("Requesting adapter");
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                compatible_surface: Some(&surface),
-                ..Default::default()
-            })
-            .await
-            .with_context(|| "No compatible adapter")?;
+        let adapter = request_adapter(&instance, Some(&surface)).await?;
+        log::info!("Adapter: {:?}", adapter.get_info());
         let device_request = adapter
             .request_device(&wgpu::DeviceDescriptor {
+                // For wireframes, which are left off where it's missing
+                required_features: adapter.features() & wgpu::Features::POLYGON_MODE_LINE,
                 required_limits: wgpu::Limits::downlevel_defaults(),
                 ..Default::default()
             })
@@ -113,18 +228,35 @@ impl Canvas {
         #[cfg(target_arch = "wasm32")]
         let (device, queue) = device_request.unwrap_throw();
 
+        let size = window.inner_size();
+        let (width, height) = surface_size(size.width, size.height);
         let mut config = surface
-            .get_default_config(
-                &adapter,
-                window.inner_size().width.max(1),
-                window.inner_size().height.max(1),
-            )
+            .get_default_config(&adapter, width, height)
             .with_context(|| "Surface is invalid")?;
-        config.view_formats.push(config.format.add_srgb_suffix());
+        let prefer_hdr = std::env::var_os(PREFER_HDR_ENV).is_some();
+        let (format, view_format) =
+            select_surface_format(&surface.get_capabilities(&adapter).formats, prefer_hdr)
+                .with_context(|| "Surface has no supported formats")?;
+        log::info!("Surface format: {format:?} (viewed as {view_format:?})");
+        config.format = format;
+        config.view_formats.push(view_format);
+        let color_format = view_format;
 
         #[cfg(not(target_arch = "wasm32"))]
         surface.configure(&device, &config);
 
+        let depth_format = wgpu::TextureFormat::Depth32Float;
+        let sample_count = supported_sample_count(
+            &adapter,
+            &device,
+            color_format,
+            depth_format,
+            MSAA_SAMPLE_COUNT,
+        );
+        if sample_count != MSAA_SAMPLE_COUNT {
+            log::warn!("{MSAA_SAMPLE_COUNT}x MSAA is unsupported, using {sample_count}x");
+        }
+
         log::info!("Creating canvas pipeline");
         let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
         let fullscreen_quad = RenderPipelineBuilder::new()
@@ -139,74 +271,78 @@ impl Canvas {
                 entry_point: Some("canvas"),
                 compilation_options: Default::default(),
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: config.view_formats[0],
+                    format: color_format,
                     blend: None,
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             })
+            .sample_count(sample_count)
             .build(&device)?;
 
-        let ortho_camera = OrthoCamera::new(
-            0.0,
-            window.inner_size().width as f32,
-            window.inner_size().height as f32,
-            0.0,
-        );
+        let ortho_camera = OrthoCamera::new(0.0, config.width as f32, config.height as f32, 0.0);
         let camera_binder = CameraBinder::new(&device);
         let ortho_camera_binding = camera_binder.bind(&device, &ortho_camera);
 
         let texture_binder = TextureBinder::new(&device);
 
-        let res = FsResources::new("res");
+        #[cfg(not(target_arch = "wasm32"))]
+        let res = resources::CachingResources::new(
+            resources::FsResources::new("res"),
+            resources::CachingResources::<resources::FsResources>::DEFAULT_BUDGET,
+        );
+        // Browsers can only fetch in the background, so everything the
+        // loaders below need is fetched first
+        #[cfg(target_arch = "wasm32")]
+        let res = resources::MemoryResources::preload(
+            &resources::FetchResources::new("res"),
+            WEB_RESOURCES,
+        )
+        .await?;
 
         let font = Font::load(&res, "fonts/OpenSans MSDF.zip", '�', &device, &queue)?;
 
         let text_pipeline = TextPipeline::new(
             &font,
             &camera_binder,
-            config.view_formats[0],
+            color_format,
             &texture_binder,
             &shader,
             &device,
+            sample_count,
         )?;
 
-        let mspt_text = text_pipeline.buffer_text(&font, &device, "Tick Rate: ----")?;
+        let layout = TextLayout::default();
+        let hud_text = text_pipeline.buffer_text(&font, &device, "Frame Time: ----", &layout)?;
+        let tooltip_text = text_pipeline.buffer_text(&font, &device, "----", &layout)?;
+        let node_labels = text_pipeline.buffer_labels(&font, &device, &[]);
 
-        let depth_format = wgpu::TextureFormat::Depth32Float;
-        let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("depth_texture"),
-            size: wgpu::Extent3d {
-                width: config.width,
-                height: config.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: depth_format,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[],
-        });
-
-        let light_buffer = BackedBuffer::with_data(
+        let depth_texture = create_target_texture(
             &device,
-            vec![LightUniform {
-                position: glam::vec4(2.0, 2.0, 2.0, 1.0),
-                color: glam::vec4(1.0, 1.0, 1.0, 1.0),
-            }],
-            wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            "depth_texture",
+            depth_format,
+            &config,
+            sample_count,
         );
+        let msaa_texture = (sample_count > 1).then(|| {
+            create_target_texture(&device, "msaa_texture", color_format, &config, sample_count)
+        });
+
+        // A dim fill so the sides facing away from the sun, and the whole
+        // scene at night, aren't lit by ambient alone
+        let lights =
+            vec![LightUniform::new(glam::vec3(2.0, 2.0, 2.0), glam::Vec3::ONE).with_intensity(4.0)];
         let light_binder = LightBinder::new(&device);
-        let light_binding = light_binder.bind(&device, &light_buffer);
+        let light_binding = light_binder.bind(&device, &lights);
 
         let material_binder = MaterialBinder::new(&device);
         let mut model_pipeline = ModelPipeline::new(
             &device,
-            config.format,
+            color_format,
             depth_format,
             &camera_binder,
             &material_binder,
             &light_binder,
+            sample_count,
         );
 
         let node_model = model_pipeline.load_obj(
@@ -225,6 +361,21 @@ impl Canvas {
             "models/connection.obj",
         )?;
 
+        let ground_model = model_pipeline.add_mesh_data(
+            &device,
+            &queue,
+            &material_binder,
+            &[MeshData::plane(GROUND_SIZE, 1)],
+        );
+        let ground_instances = buffer::BackedBuffer::with_data(
+            &device,
+            vec![InstanceVertex::with_position_scale(
+                glam::vec3(0.0, GROUND_HEIGHT, 0.0),
+                1.0,
+            )],
+            wgpu::BufferUsages::VERTEX,
+        );
+
         let perspective_camera = PerspectiveCamera::new(
             glam::vec3(0.0, 0.0, 3.0),
             -f32::consts::FRAC_PI_2,
@@ -236,60 +387,53 @@ impl Canvas {
             100.0,
         );
         let perspective_camera_binding = camera_binder.bind(&device, &perspective_camera);
-        let camera_controller = CameraController::new(1.0, 1.0);
 
         let environment = Environment::default();
-        let mut simulation = Simulation::new();
-
-        let solar_panel = simulation.add_node(10.0, 50.0, 0.9, 100.0, glam::vec3(-0.5, -0.5, 0.0));
-        let extractor = simulation.add_node(10.0, 20.0, 0.9, 100.0, glam::vec3(0.5, -0.5, 0.0));
-        let pump = simulation.add_node(10.0, 20.0, 0.9, 100.0, glam::vec3(0.0, 0.5, 0.0));
+        let (mut simulation, solar_panel, extractor) = demo_simulation();
+        simulation.set_interpolation(true);
 
-        simulation.connect_node(solar_panel, extractor, 10.0);
-        simulation.connect_node(extractor, pump, 10.0);
-        simulation.connect_node(pump, solar_panel, 10.0);
+        let node_instances = vec![(
+            node_model,
+            buffer::BackedBuffer::with_data(
+                &device,
+                build_node_instances(&simulation, 1.0, &default_node_style),
+                wgpu::BufferUsages::VERTEX,
+            ),
+        )];
 
-        simulation.attach_solar_panel(
-            solar_panel,
-            SolarPanel {
-                area: 1.0,
-                efficiency: 0.9,
-            },
+        let connection_instances = buffer::BackedBuffer::with_data(
+            &device,
+            build_connection_instances(&simulation),
+            wgpu::BufferUsages::VERTEX,
         );
 
-        simulation.attach_extractor(
-            extractor,
-            Extractor {
-                power_draw: 100.0,
-                efficiency: 0.9,
-            }
+        let visualization_pipeline = VisualizationPipeline::new(
+            &device,
+            color_format,
+            depth_format,
+            &camera_binder,
+            Default::default(),
+            sample_count,
         );
-
-        let node_instances = buffer::BackedBuffer::with_data(
+        let connection_pipeline = VisualizationPipeline::new(
             &device,
-            simulation
-                .nodes()
-                .iter()
-                .map(|node| instance_from_node(node))
-                .collect(),
-            wgpu::BufferUsages::VERTEX,
+            color_format,
+            depth_format,
+            &camera_binder,
+            CONNECTION_DEPTH_BIAS,
+            sample_count,
         );
-
-        let connection_instances = buffer::BackedBuffer::with_data(
+        let heatmap_pipeline = HeatmapPipeline::new(&device, color_format, sample_count);
+        let shadow_pipeline = ShadowPipeline::new(&device, &light_binder);
+        let mut grid_pipeline = GridPipeline::new(
             &device,
-            simulation
-                .connected_nodes()
-                .map(|(flow_rate, input, output)| {
-                    instance_from_connection(flow_rate, input, output)
-                })
-                .collect(),
-            wgpu::BufferUsages::VERTEX,
+            color_format,
+            depth_format,
+            &camera_binder,
+            sample_count,
         );
-
-        let visualization_pipeline =
-            VisualizationPipeline::new(&device, config.format, depth_format, &camera_binder);
-
-        let last_time = web_time::Instant::now();
+        grid_pipeline.set_height(GROUND_HEIGHT);
+        grid_pipeline.update(&queue);
 
         Ok(Self {
             config,
@@ -298,39 +442,100 @@ impl Canvas {
             queue,
             window,
             depth_texture,
+            msaa_texture,
+            zero_sized: size.width == 0 || size.height == 0,
             fullscreen_quad,
-            mspt_text,
+            hud_text,
+            tooltip_text,
+            node_labels,
             font,
             ortho_camera,
             ortho_camera_binding,
             text_pipeline,
             model_pipeline,
             visualization_pipeline,
+            connection_pipeline,
+            heatmap_pipeline,
+            shadow_pipeline,
+            grid_pipeline,
             node_model,
+            node_models: HashMap::new(),
             node_instances,
+            node_style: Box::new(default_node_style),
             connection_model,
             connection_instances,
-            perspective_camera,
+            ground_model,
+            ground_instances,
             perspective_camera_binding,
-            camera_controller,
-            light_buffer,
+            lights,
+            light_binder,
             light_binding,
-            frame_timer: last_time,
-            num_ticks: 0,
-            lmb_down: false,
-            environment,
-            simulation,
             solar_panel,
             extractor,
             gameplay_timer: web_time::Instant::now(),
-            simulation_accumulator: web_time::Duration::ZERO,
+            state: CanvasState {
+                warming_up: true,
+                ..CanvasState::new(simulation, environment, perspective_camera)
+            },
         })
     }
 
+    /// Adds a light to the scene and returns its index.
+    pub fn add_light(&mut self, light: LightUniform) -> usize {
+        self.lights.push(light);
+        self.upload_lights();
+        self.lights.len() - 1
+    }
+
+    /// Replaces every light in the scene.
+    pub fn set_lights(&mut self, lights: Vec<LightUniform>) {
+        self.lights = lights;
+        self.upload_lights();
+    }
+
+    pub fn lights(&self) -> &[LightUniform] {
+        &self.lights
+    }
+
+    fn upload_lights(&mut self) {
+        self.light_binder.update(
+            &self.device,
+            &self.queue,
+            &mut self.light_binding,
+            &self.lights,
+        );
+    }
+
+    pub fn set_node_style(&mut self, style: Box<NodeStyle>) {
+        self.node_style = style;
+    }
+
+    /// Draws `node` with `model` instead of the default node model.
+    #[allow(unused)]
+    pub fn set_node_model(&mut self, node: usize, model: resources::model::ModelId) {
+        self.node_models.insert(node, model);
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
-        self.config.width = width.max(1);
-        self.config.height = height.max(1);
+        self.zero_sized = width == 0 || height == 0;
+        (self.config.width, self.config.height) = surface_size(width, height);
         self.surface.configure(&self.device, &self.config);
+        let recreate = |texture: &wgpu::Texture, label| {
+            create_target_texture(
+                &self.device,
+                label,
+                texture.format(),
+                &self.config,
+                texture.sample_count(),
+            )
+        };
+        self.depth_texture = recreate(&self.depth_texture, "depth_texture");
+        self.msaa_texture = self
+            .msaa_texture
+            .as_ref()
+            .map(|texture| recreate(texture, "msaa_texture"));
+        self.state.perspective_camera
+            .resize(self.config.width, self.config.height);
         self.ortho_camera
             .resize(self.config.width, self.config.height);
         self.ortho_camera_binding
@@ -339,6 +544,9 @@ impl Canvas {
 
     pub fn render(&mut self, event_loop: &ActiveEventLoop) {
         self.window.request_redraw();
+        if self.zero_sized {
+            return;
+        }
         let frame = match self.surface.get_current_texture() {
             Ok(frame) => frame,
             Err(wgpu::SurfaceError::Outdated) => {
@@ -351,76 +559,182 @@ impl Canvas {
             }
         };
 
-        if self.num_ticks == 100 {
-            let mut text = String::new();
-
-            self.node_instances.clear();
-            let mut batch = self.node_instances.batch(&self.device, &self.queue);
-
-            for (i, node) in self.simulation.nodes().iter().enumerate() {
-                if i > 0 {
-                    text += "\n";
-                }
-
-                text += &format!("{i}: {:.2} mL @ {:.2} C", node.fluid.volume, node.fluid.temp);
-
-                batch.push(instance_from_node(node));
-            }
-
-            drop(batch);
+        if self.state.num_frames == 100 {
+            let text = self.state.hud_text();
 
             self.text_pipeline
                 .update_text(
                     &self.font,
                     &text,
-                    &mut self.mspt_text,
+                    &TextLayout::default(),
+                    &mut self.hud_text,
                     &self.device,
                     &self.queue,
                 )
                 .unwrap();
 
-            {
-                self.connection_instances.clear();
-                let mut batch = self.connection_instances.batch(&self.device, &self.queue);
-
-                for (flow_rate, input, output) in self.simulation.connected_nodes() {
-                    batch.push(instance_from_connection(flow_rate, input, output));
-                }
-            }
-
-            self.frame_timer = web_time::Instant::now();
-            self.num_ticks = 0;
+            self.state.num_frames = 0;
         }
-        self.num_ticks += 1;
+        self.state.num_frames += 1;
 
         let dt = self.gameplay_timer.elapsed();
         self.gameplay_timer = web_time::Instant::now();
+        self.state.update(dt);
+
+        {
+            // Blend between the last two ticks so the nodes don't step when
+            // rendering faster than the tick rate
+            let alpha = self.state.stepper.alpha();
+            let simulation = &self.state.simulation;
+            let instances = build_node_instances(simulation, alpha, &self.node_style);
+            let groups = group_by_model(simulation.nodes().ids().zip(instances), |id| {
+                self.node_models
+                    .get(&id)
+                    .copied()
+                    .unwrap_or(self.node_model)
+            });
+
+            for (_, buffer) in &mut self.node_instances {
+                buffer.clear();
+            }
+            for (model, instances) in groups {
+                match self.node_instances.iter_mut().find(|(m, _)| *m == model) {
+                    Some((_, buffer)) => {
+                        let mut batch = buffer.batch(&self.device, &self.queue);
+                        for instance in instances {
+                            batch.push(instance);
+                        }
+                    }
+                    None => self.node_instances.push((
+                        model,
+                        BackedBuffer::with_data(
+                            &self.device,
+                            instances,
+                            wgpu::BufferUsages::VERTEX,
+                        ),
+                    )),
+                }
+            }
+        }
+        {
+            // Every frame too, so pipe colors and thickness keep up with the flow
+            self.connection_instances.clear();
+            let mut batch = self.connection_instances.batch(&self.device, &self.queue);
+            for instance in build_connection_instances(&self.state.simulation) {
+                batch.push(instance);
+            }
+        }
 
-        self.simulation_accumulator += dt;
-        while self.simulation_accumulator >= SIMULATION_TICK_RATE {
-            self.simulation
-                .tick(&self.environment, SIMULATION_TICK_RATE_F32);
-            self.simulation_accumulator -= SIMULATION_TICK_RATE;
+        // Nodes can move under a still cursor, so this is checked every frame
+        self.state.hovered_node = self
+            .state
+            .cursor
+            .and_then(|cursor| self.pick_node(cursor.x, cursor.y));
+        let hovered = self
+            .state
+            .hovered_node
+            .and_then(|id| self.state.simulation.get_node(id));
+        if let (Some(node), Some(cursor)) = (hovered, self.state.cursor) {
+            let text = tooltip_text(self.state.units, &node.fluid);
+            self.text_pipeline
+                .update_text(
+                    &self.font,
+                    &text,
+                    &TextLayout::at(cursor + TOOLTIP_OFFSET),
+                    &mut self.tooltip_text,
+                    &self.device,
+                    &self.queue,
+                )
+                .unwrap();
         }
 
-        self.camera_controller
-            .update_camera(&mut self.perspective_camera, dt);
         self.perspective_camera_binding
-            .update(&self.perspective_camera, &self.queue);
+            .update(&self.state.perspective_camera, &self.queue);
+        self.update_node_labels();
+        let sun = sun_light(&self.state.environment);
+        // A dark sun casts nothing, which also skips the shadow pass
+        let shadow_view_proj = if sun.color.truncate() == glam::Vec3::ZERO {
+            glam::Mat4::ZERO
+        } else {
+            sun.view_proj(simulation_center(&self.state.simulation), SHADOW_RADIUS)
+        };
+        self.light_binding.set_sun(&self.queue, sun);
+        self.light_binding
+            .set_shadow_view_proj(&self.queue, shadow_view_proj);
+        if self.state.show_heatmap {
+            self.heatmap_pipeline.update(
+                &self.device,
+                &self.queue,
+                &self.state.simulation,
+                &self.state.perspective_camera,
+                self.config.width as f32 / self.config.height as f32,
+            );
+        }
 
         let view = frame.texture.create_view(&wgpu::TextureViewDescriptor {
             format: self.config.view_formats.get(0).copied(),
             ..Default::default()
         });
+        self.draw_frame(&view);
+        frame.present();
+    }
+
+    /// Renders what the window would show next into an image, e.g. for
+    /// screenshots. Fails if the surface is viewed as an HDR format. Not on
+    /// the web, where waiting for the GPU to finish would block the page.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn capture_frame(&self) -> anyhow::Result<image::RgbaImage> {
+        let texture = Texture::from_descriptor(
+            &self.device,
+            &wgpu::TextureDescriptor {
+                label: Some("Canvas::capture_frame"),
+                size: wgpu::Extent3d {
+                    width: self.config.width,
+                    height: self.config.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: self.color_format(),
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            },
+            Default::default(),
+        );
+        self.draw_frame(&texture.view);
+        texture.read_to_image(&self.device, &self.queue)
+    }
+
+    /// The format everything is drawn in.
+    fn color_format(&self) -> wgpu::TextureFormat {
+        self.config
+            .view_formats
+            .first()
+            .copied()
+            .unwrap_or(self.config.format)
+    }
+
+    fn draw_frame(&self, view: &wgpu::TextureView) {
         let depth_view = self.depth_texture.create_view(&Default::default());
+        // When multisampling, every pass draws into the samples and resolves
+        // them into `view`
+        let msaa_view = self
+            .msaa_texture
+            .as_ref()
+            .map(|texture| texture.create_view(&Default::default()));
+        let (view, resolve_target) = match &msaa_view {
+            Some(msaa_view) => (msaa_view, Some(view)),
+            None => (view, None),
+        };
 
         let mut encoder = self.device.create_command_encoder(&Default::default());
 
         {
             let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view,
+                    resolve_target,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
                         store: wgpu::StoreOp::Store,
@@ -433,14 +747,40 @@ impl Canvas {
             pass.draw(0..3, 0..1);
 
             self.text_pipeline
-                .draw_text(&mut pass, &self.mspt_text, &self.ortho_camera_binding);
+                .draw_text(&mut pass, &self.hud_text, &self.ortho_camera_binding);
+            if self.state.hovered_node.is_some() {
+                self.text_pipeline.draw_text(
+                    &mut pass,
+                    &self.tooltip_text,
+                    &self.ortho_camera_binding,
+                );
+            }
+        }
+
+        let batch = self
+            .node_instances
+            .iter()
+            .map(|(model, instances)| (*model, instances))
+            .collect::<Vec<_>>();
+        if self.light_binding.shadow_view_proj() != glam::Mat4::ZERO {
+            let casters = [
+                batch.as_slice(),
+                &[(self.connection_model, &self.connection_instances)],
+            ]
+            .concat();
+            self.shadow_pipeline.render(
+                &mut encoder,
+                &self.light_binding,
+                &casters,
+                &self.model_pipeline,
+            );
         }
 
         {
             let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view,
+                    resolve_target,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Load,
                         store: wgpu::StoreOp::Store,
@@ -457,25 +797,105 @@ impl Canvas {
                 ..Default::default()
             });
 
-            self.visualization_pipeline.draw(
+            self.model_pipeline.draw(
                 &mut pass,
-                self.node_model,
+                self.ground_model,
+                &self.perspective_camera_binding,
+                &self.light_binding,
+                &self.ground_instances,
+            );
+            self.visualization_pipeline.draw_batch(
+                &mut pass,
+                &batch,
                 &self.model_pipeline,
                 &self.perspective_camera_binding,
-                &self.node_instances,
             );
-
-            self.visualization_pipeline.draw(
+            self.connection_pipeline.draw(
                 &mut pass,
                 self.connection_model,
                 &self.model_pipeline,
                 &self.perspective_camera_binding,
                 &self.connection_instances,
             );
+            if self.state.show_grid {
+                self.grid_pipeline
+                    .draw(&mut pass, &self.perspective_camera_binding);
+            }
+        }
+
+        if self.state.show_heatmap {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            self.heatmap_pipeline.draw(&mut pass);
+        }
+
+        // Over everything else, including the heatmap
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            self.draw_node_labels(&mut pass);
         }
 
         self.queue.submit([encoder.finish()]);
-        frame.present();
+    }
+
+    /// Places each node's label next to where the node is on screen. Nodes
+    /// behind the camera get no label.
+    fn update_node_labels(&mut self) {
+        let (width, height) = (self.config.width, self.config.height);
+        let units = self.state.units;
+        let placed = self
+            .state
+            .simulation
+            .nodes()
+            .iter_with_ids()
+            .filter_map(|(i, node)| {
+                let screen =
+                    world_to_screen(&self.state.perspective_camera, node.position, width, height)?;
+                let text = format!("{i}: {}", units.format_temp(node.fluid.temp));
+                Some((text, screen + NODE_LABEL_OFFSET))
+            })
+            .collect::<Vec<_>>();
+        let labels = placed
+            .iter()
+            .map(|(text, screen)| Label {
+                text,
+                position: screen.extend(0.0),
+                scale: NODE_LABEL_SCALE,
+            })
+            .collect::<Vec<_>>();
+        self.text_pipeline.update_labels(
+            &self.font,
+            &labels,
+            &mut self.node_labels,
+            &self.device,
+            &self.queue,
+        );
+    }
+
+    /// Draws every node's label, always facing the screen, in one draw.
+    /// Returns how many glyphs were drawn.
+    pub fn draw_node_labels(&self, pass: &mut wgpu::RenderPass<'_>) -> u32 {
+        self.text_pipeline
+            .draw_labels(pass, &self.node_labels, &self.ortho_camera_binding)
     }
 
     pub fn project_point(&self, x: f32, y: f32) -> glam::Vec2 {
@@ -486,40 +906,933 @@ impl Canvas {
         )
     }
 
+    /// The node under `screen_x`, `screen_y` in pixels, nearest first.
+    pub fn pick_node(&self, screen_x: f32, screen_y: f32) -> Option<usize> {
+        let ndc = glam::vec2(
+            screen_x / self.config.width.max(1) as f32 * 2.0 - 1.0,
+            1.0 - screen_y / self.config.height.max(1) as f32 * 2.0,
+        );
+        let (origin, dir) = self.state.perspective_camera.ray_through(ndc);
+        let instances = build_node_instances(
+            &self.state.simulation,
+            self.state.stepper.alpha(),
+            &self.node_style,
+        );
+        let i = pick_instance(&instances, origin, dir)?;
+        self.state.simulation.nodes().ids().nth(i)
+    }
+
+    pub(crate) fn handle_mouse_move(&mut self, dx: f64, dy: f64) {
+        self.state.handle_mouse_move(dx, dy);
+    }
+
+    pub(crate) fn handle_scroll(&mut self, delta: &MouseScrollDelta) {
+        self.state.handle_scroll(delta);
+    }
+
+    /// `x` and `y` are in pixels from the top left of the window.
+    pub(crate) fn handle_cursor_moved(&mut self, x: f32, y: f32) {
+        self.state.cursor = Some(glam::vec2(x, y));
+    }
+
+    pub(crate) fn handle_cursor_left(&mut self) {
+        self.state.cursor = None;
+        self.state.hovered_node = None;
+    }
+
+    pub(crate) fn handle_mouse_button(&mut self, button: MouseButton, pressed: bool) {
+        self.state.handle_mouse_button(button, pressed);
+        self.window
+            .set_cursor_visible(!(self.state.lmb_down || self.state.mmb_down));
+    }
+
+    pub(crate) fn handle_key(&mut self, key: KeyCode, pressed: bool) {
+        match key {
+            KeyCode::F5 if pressed => {
+                if let Err(e) = self.save_scene(SCENE_PATH) {
+                    log::error!("{e:?}");
+                }
+            }
+            KeyCode::F9 if pressed => {
+                if let Err(e) = self.load_scene(SCENE_PATH) {
+                    log::error!("{e:?}");
+                }
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            KeyCode::F12 if pressed => {
+                let result = self
+                    .capture_frame()
+                    .and_then(|image| Ok(image.save(SCREENSHOT_PATH)?));
+                if let Err(e) = result {
+                    log::error!("{e:?}");
+                }
+            }
+            KeyCode::KeyL if pressed => self.toggle_wireframe(),
+            _ => self.state.handle_key(key, pressed),
+        }
+    }
+
+    /// Switches the nodes and connections between filled and wireframe.
+    /// Stays filled if the device can't draw lines.
+    fn toggle_wireframe(&mut self) {
+        let wireframe = !self.visualization_pipeline.wireframe();
+        if self.visualization_pipeline.set_wireframe(wireframe) {
+            self.connection_pipeline.set_wireframe(wireframe);
+            self.model_pipeline.set_wireframe(wireframe);
+        }
+    }
+
+    pub fn save_scene(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        self.state.scene().save(path)
+    }
+
+    pub fn load_scene(&mut self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        self.state.apply_scene(SceneState::load(path)?);
+        Ok(())
+    }
+}
+
+/// Everything [`Canvas`] tracks that doesn't need a GPU, so input handling
+/// and updates can be tested on their own.
+pub(crate) struct CanvasState {
+    pub(crate) simulation: Simulation,
+    pub(crate) environment: Environment,
+    pub(crate) units: UnitSystem,
+    pub(crate) stepper: FixedStepper,
+    /// Whether [`Self::update`] is still warm starting the simulation, a
+    /// little each frame.
+    pub(crate) warming_up: bool,
+    pub(crate) frame_time: Ema,
+    pub(crate) num_frames: u32,
+    pub(crate) lmb_down: bool,
+    pub(crate) mmb_down: bool,
+    /// Toggled with H.
+    pub(crate) show_heatmap: bool,
+    /// Toggled with G.
+    pub(crate) show_grid: bool,
+    pub(crate) camera_controller: Box<dyn CameraControl>,
+    /// Whether `camera_controller` orbits the simulation. Toggled with O.
+    pub(crate) orbiting: bool,
+    pub(crate) perspective_camera: PerspectiveCamera,
+    /// In pixels, `None` while the cursor is outside the window.
+    pub(crate) cursor: Option<glam::Vec2>,
+    /// The id of the node under the cursor as of the last frame. Ids stay
+    /// with their node, so this never points at a different one.
+    pub(crate) hovered_node: Option<usize>,
+}
+
+impl CanvasState {
+    pub(crate) fn new(
+        simulation: Simulation,
+        environment: Environment,
+        perspective_camera: PerspectiveCamera,
+    ) -> Self {
+        Self {
+            simulation,
+            environment,
+            units: UnitSystem::default(),
+            stepper: FixedStepper::new(SIMULATION_TICK_RATE),
+            warming_up: false,
+            frame_time: Ema::new(FRAME_TIME_SMOOTHING),
+            num_frames: 0,
+            lmb_down: false,
+            mmb_down: false,
+            show_heatmap: false,
+            show_grid: true,
+            camera_controller: Box::new(CameraController::new(1.0, 1.0)),
+            orbiting: false,
+            perspective_camera,
+            cursor: None,
+            hovered_node: None,
+        }
+    }
+
+    /// Mouse movement only moves the camera while a button is held.
     pub(crate) fn handle_mouse_move(&mut self, dx: f64, dy: f64) {
         if self.lmb_down {
-            self.camera_controller.process_mouse(dx, dy);
+            self.camera_controller
+                .process_drag(MouseButton::Left, dx, dy);
+        } else if self.mmb_down {
+            self.camera_controller
+                .process_drag(MouseButton::Middle, dx, dy);
         }
     }
 
+    /// Moves the camera toward or away from where it's looking, or what it's
+    /// orbiting.
+    pub(crate) fn handle_scroll(&mut self, delta: &MouseScrollDelta) {
+        self.camera_controller.process_mouse_scroll(delta);
+    }
+
     pub(crate) fn handle_mouse_button(&mut self, button: MouseButton, pressed: bool) {
         match button {
-            MouseButton::Left => {
-                self.lmb_down = pressed;
-                self.window.set_cursor_visible(!pressed);
-            }
+            MouseButton::Left => self.lmb_down = pressed,
+            MouseButton::Middle => self.mmb_down = pressed,
             _ => {}
         }
     }
 
+    /// Switches between flying freely and orbiting the middle of the nodes.
+    pub(crate) fn toggle_orbit(&mut self) {
+        self.orbiting = !self.orbiting;
+        self.camera_controller = if self.orbiting {
+            let center = simulation_center(&self.simulation);
+            Box::new(OrbitController::new(center, 1.0))
+        } else {
+            Box::new(CameraController::new(1.0, 1.0))
+        };
+    }
+
     pub(crate) fn handle_key(&mut self, key: KeyCode, pressed: bool) {
-        self.camera_controller.process_keyboard(key, pressed);
+        match key {
+            KeyCode::KeyH if pressed => self.show_heatmap = !self.show_heatmap,
+            KeyCode::KeyG if pressed => self.show_grid = !self.show_grid,
+            KeyCode::KeyO if pressed => self.toggle_orbit(),
+            _ => {
+                self.camera_controller.process_keyboard(key, pressed);
+            }
+        }
+    }
+
+    /// Advances the simulation and camera by `dt`. Returns how many ticks ran.
+    pub(crate) fn update(&mut self, dt: web_time::Duration) -> u32 {
+        self.frame_time.update(dt.as_secs_f32());
+        if self.warming_up {
+            self.warming_up = !self
+                .simulation
+                .warm_start_for(&self.environment, WARM_START_TICKS_PER_FRAME);
+        }
+        let ticks = self
+            .stepper
+            .step(&mut self.simulation, &self.environment, dt);
+        self.camera_controller
+            .update_camera(&mut self.perspective_camera, dt);
+        ticks
+    }
+
+    pub(crate) fn hud_text(&self) -> String {
+        let frame_time = self.frame_time.value().unwrap_or(0.0);
+        let mut text = format!(
+            "{:.0} fps ({:.2} ms)\n",
+            1.0 / frame_time.max(f32::EPSILON),
+            frame_time * 1000.0,
+        );
+        text += &format!("sim time: {}\n", self.simulation.clock());
+        text += &self.environment.to_string();
+        text += "\n";
+        text += &self.simulation.metrics(&self.environment).to_string();
+
+        for (i, node) in self.simulation.nodes().iter_with_ids() {
+            text += "\n";
+            text += &format!("{i}: {}", self.units.format_fluid(&node.fluid));
+            match self.simulation.node_energy_delta(i) {
+                Some(delta) if delta > 0.0 => text += " heating",
+                Some(delta) if delta < 0.0 => text += " cooling",
+                _ => {}
+            }
+        }
+        text
+    }
+
+    pub(crate) fn scene(&self) -> SceneState {
+        SceneState {
+            simulation: self.simulation.clone(),
+            environment: self.environment.clone(),
+            camera: self.perspective_camera.state(),
+        }
     }
+
+    pub(crate) fn apply_scene(&mut self, scene: SceneState) {
+        self.simulation = scene.simulation;
+        self.environment = scene.environment;
+        self.perspective_camera.set_state(scene.camera);
+        // Loaded temperatures are kept as they were
+        self.warming_up = false;
+    }
+}
+
+/// A panel heating a loop that an extractor draws from, along with the ids
+/// of the panel and extractor nodes.
+fn demo_simulation() -> (Simulation, usize, usize) {
+    let mut simulation = Simulation::new();
+
+    let solar_panel = simulation.add_node_desc(NodeDesc {
+        temp: 50.0,
+        position: glam::vec3(-0.5, -0.5, 0.0),
+        ..Default::default()
+    });
+    let extractor = simulation.add_node_desc(NodeDesc {
+        position: glam::vec3(0.5, -0.5, 0.0),
+        ..Default::default()
+    });
+    let pump = simulation.add_node_desc(NodeDesc {
+        position: glam::vec3(0.0, 0.5, 0.0),
+        ..Default::default()
+    });
+
+    simulation.connect_node(solar_panel, extractor, 10.0);
+    simulation.connect_node(extractor, pump, 10.0);
+    simulation.connect_node(pump, solar_panel, 10.0);
+
+    simulation.attach_solar_panel(
+        solar_panel,
+        SolarPanel {
+            area: 1.0,
+            efficiency: 0.9,
+        },
+    );
+
+    simulation.attach_extractor(
+        extractor,
+        Extractor {
+            power_draw: 100.0,
+            efficiency: 0.9,
+        },
+    );
+
+    (simulation, solar_panel, extractor)
+}
+
+/// A texture to render into the size of the surface, for depth or MSAA.
+fn create_target_texture(
+    device: &wgpu::Device,
+    label: &str,
+    format: wgpu::TextureFormat,
+    config: &wgpu::SurfaceConfiguration,
+    sample_count: u32,
+) -> wgpu::Texture {
+    let (width, height) = surface_size(config.width, config.height);
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    })
+}
+
+/// [`temperature_to_color_in`] between [`COLD_TEMP`] and [`HOT_TEMP`].
+pub(crate) fn temperature_to_color(temp: f32) -> glam::Vec3 {
+    temperature_to_color_in(temp, COLD_TEMP, HOT_TEMP)
+}
+
+/// Blue at `cold_temp` and below, through green halfway, to red at
+/// `hot_temp` and above.
+pub(crate) fn temperature_to_color_in(temp: f32, cold_temp: f32, hot_temp: f32) -> glam::Vec3 {
+    let s = rev_lerp(cold_temp, hot_temp, temp);
+    // An empty range has no halfway point, so it's just hot or cold
+    let s = if s.is_nan() { 0.0 } else { s.clamp(0.0, 1.0) };
+    if s < 0.5 {
+        COLD_COLOR.lerp(WARM_COLOR, s * 2.0)
+    } else {
+        WARM_COLOR.lerp(HOT_COLOR, s * 2.0 - 1.0)
+    }
+}
+
+/// The temperature color tinted by the node's fluid.
+pub(crate) fn node_color(node: &Node) -> glam::Vec3 {
+    temperature_to_color(node.fluid.temp) * node.tint
 }
 
-fn instance_from_node(node: &crate::simulation::Node) -> ColoredInstance {
-    let s = rev_lerp(COLD_TEMP, HOT_TEMP, node.fluid.temp);
-    let color = COLD_COLOR.lerp(HOT_COLOR, s);
-    ColoredInstance::with_position_scale(color, node.position, 0.1)
+pub(crate) fn default_node_style(_: usize, node: &Node) -> ColoredInstance {
+    ColoredInstance::with_position_scale(node_color(node), node.position, 0.1)
+}
+
+/// Lights the scene from where the simulation's sun is, dimmed by clouds
+/// like the panels are. Off below the horizon or with the sun disabled.
+pub(crate) fn sun_light(environment: &Environment) -> DirectionalLight {
+    let direction = sun_direction(environment.sun_angle());
+    if !environment.sun_enabled() {
+        return DirectionalLight::OFF;
+    }
+    let strength = (direction.y / SUN_FADE).clamp(0.0, 1.0) * (1.0 - environment.cloud_cover());
+    DirectionalLight::new(direction, SUN_COLOR * strength)
+}
+
+/// The average position of the nodes, or the origin if there aren't any.
+fn simulation_center(simulation: &Simulation) -> glam::Vec3 {
+    let nodes = simulation.nodes();
+    if nodes.is_empty() {
+        return glam::Vec3::ZERO;
+    }
+    nodes.iter().map(|node| node.position).sum::<glam::Vec3>() / nodes.len() as f32
+}
+
+/// What the hover tooltip says about a node's fluid. The font has no degree
+/// sign, so temperatures are written like the HUD's.
+pub(crate) fn tooltip_text(units: UnitSystem, fluid: &Fluid) -> String {
+    match units {
+        UnitSystem::Metric => format!("{:.1} C / {:.1} mL", fluid.temp, fluid.volume),
+        UnitSystem::Imperial => format!(
+            "{:.1} F / {:.2} gal",
+            fluid.temp_fahrenheit(),
+            fluid.volume_gallons()
+        ),
+    }
+}
+
+/// The nearest of `instances` the ray hits, treating each as the sphere
+/// around its node model.
+pub(crate) fn pick_instance(
+    instances: &[ColoredInstance],
+    origin: glam::Vec3,
+    dir: glam::Vec3,
+) -> Option<usize> {
+    instances
+        .iter()
+        .enumerate()
+        .filter_map(|(i, instance)| {
+            let model_matrix = instance.model_matrix();
+            let scale = model_matrix
+                .x_axis
+                .length()
+                .max(model_matrix.y_axis.length())
+                .max(model_matrix.z_axis.length());
+            let center = model_matrix.w_axis.truncate();
+            let t = ray_sphere_intersection(origin, dir, center, scale * NODE_MODEL_RADIUS)?;
+            Some((i, t))
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(i, _)| i)
+}
+
+/// Styles each node as it was `alpha` of the way through the last tick.
+pub(crate) fn build_node_instances(
+    simulation: &Simulation,
+    alpha: f32,
+    style: &dyn Fn(usize, &Node) -> ColoredInstance,
+) -> Vec<ColoredInstance> {
+    simulation
+        .nodes()
+        .iter_with_ids()
+        .zip(simulation.interpolate(alpha))
+        .map(|((i, node), snapshot)| {
+            let node = Node {
+                fluid: Fluid {
+                    volume: snapshot.volume,
+                    temp: snapshot.temp,
+                },
+                position: snapshot.position,
+                ..node.clone()
+            };
+            style(i, &node)
+        })
+        .collect()
+}
+
+/// Groups per node items, each with its node's id, by the model returned by
+/// `model_for` for the id, keeping the order models are first seen in.
+fn group_by_model<M: PartialEq, T>(
+    items: impl IntoIterator<Item = (usize, T)>,
+    model_for: impl Fn(usize) -> M,
+) -> Vec<(M, Vec<T>)> {
+    let mut groups: Vec<(M, Vec<T>)> = Vec::new();
+    for (id, item) in items {
+        let model = model_for(id);
+        match groups.iter_mut().find(|(m, _)| *m == model) {
+            Some((_, group)) => group.push(item),
+            None => groups.push((model, vec![item])),
+        }
+    }
+    groups
+}
+
+/// Pipes thin out as the flow they actually achieved drops below their
+/// configured rate, e.g. when the input runs dry.
+pub(crate) fn build_connection_instances(simulation: &Simulation) -> Vec<ColoredInstance> {
+    simulation
+        .connections()
+        .iter_with_ids()
+        .filter_map(|(id, connection)| {
+            let input = simulation.get_node(connection.input)?;
+            let output = simulation.get_node(connection.output)?;
+            Some(instance_from_connection(
+                connection.flow_rate,
+                simulation.realized_flows()[id],
+                input,
+                output,
+            ))
+        })
+        .collect()
+}
+
+/// Pipes carry fluid at the temperature of the node feeding them, so a hot
+/// front can be seen moving around the loop.
+pub(crate) fn connection_color(source: &Node) -> glam::Vec3 {
+    node_color(source)
 }
 
 fn instance_from_connection(
-    _flow_rate: f32,
-    input: &crate::simulation::Node,
-    output: &crate::simulation::Node,
+    flow_rate: f32,
+    realized_flow: f32,
+    input: &Node,
+    output: &Node,
 ) -> ColoredInstance {
-    let avg_temp = (input.fluid.temp + output.fluid.temp) * 0.5;
-    let s = rev_lerp(COLD_TEMP, HOT_TEMP, avg_temp);
-    let color = COLD_COLOR.lerp(HOT_COLOR, s);
-    ColoredInstance::extend_between(color, input.position, output.position, 0.02)
+    // Negative flow runs from the output to the input
+    let color = connection_color(if flow_rate < 0.0 { output } else { input });
+    let radius = connection_radius(realized_flow);
+    ColoredInstance::extend_between(color, input.position, output.position, radius)
+}
+
+/// Thicker the more a connection actually carries, whichever way.
+fn connection_radius(realized_flow: f32) -> f32 {
+    (realized_flow.abs() * CONNECTION_RADIUS_PER_FLOW)
+        .clamp(MIN_CONNECTION_RADIUS, MAX_CONNECTION_RADIUS)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::{
+        resources::camera::{Camera, MIN_ZOOM_DISTANCE},
+        simulation::{TickStage, TickStages},
+    };
+
+    #[test]
+    fn test_node_style_called_per_node() {
+        let mut simulation = Simulation::new();
+        for i in 0..3 {
+            simulation.add_node(10.0, i as f32 * 10.0, 1.0, 100.0, glam::Vec3::ZERO);
+        }
+
+        let calls = RefCell::new(Vec::new());
+        let style = |i: usize, node: &Node| {
+            calls.borrow_mut().push((i, node.fluid.temp));
+            ColoredInstance::with_position_scale(glam::Vec3::ONE, node.position, i as f32)
+        };
+        let instances = build_node_instances(&simulation, 1.0, &style);
+
+        assert_eq!(instances.len(), 3);
+        assert_eq!(calls.take(), vec![(0, 0.0), (1, 10.0), (2, 20.0)]);
+
+        // Removed nodes are skipped and the rest keep their ids
+        simulation.remove_node(0);
+        let instances = build_node_instances(&simulation, 1.0, &style);
+        assert_eq!(instances.len(), 2);
+        assert_eq!(calls.into_inner(), vec![(1, 10.0), (2, 20.0)]);
+    }
+
+    #[test]
+    fn test_group_by_model() {
+        let node_models = HashMap::from([(1, "pump"), (3, "pump"), (4, "panel")]);
+        let groups = group_by_model((0..5).map(|i| (i, i)), |i| {
+            node_models.get(&i).copied().unwrap_or("tank")
+        });
+
+        assert_eq!(
+            groups,
+            vec![
+                ("tank", vec![0, 2]),
+                ("pump", vec![1, 3]),
+                ("panel", vec![4]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_surface_size() {
+        assert_eq!(surface_size(0, 0), (1, 1));
+        assert_eq!(surface_size(0, 600), (1, 600));
+        assert_eq!(surface_size(800, 600), (800, 600));
+    }
+
+    #[test]
+    fn test_canvas_state_input() {
+        let camera = PerspectiveCamera::new(
+            glam::Vec3::ZERO,
+            0.0,
+            0.0,
+            800,
+            600,
+            f32::consts::FRAC_PI_4,
+            0.1,
+            100.0,
+        );
+        let mut state = CanvasState::new(Simulation::new(), Environment::default(), camera);
+        let dt = web_time::Duration::from_millis(100);
+
+        // Dragging without the left button held doesn't look around
+        state.handle_mouse_move(10.0, 0.0);
+        state.update(dt);
+        assert_eq!(state.perspective_camera.state().yaw, 0.0);
+
+        state.handle_mouse_button(MouseButton::Left, true);
+        assert!(state.lmb_down);
+        state.handle_mouse_move(10.0, 0.0);
+        state.update(dt);
+        assert!(state.perspective_camera.state().yaw > 0.0);
+        state.handle_mouse_button(MouseButton::Left, false);
+        assert!(!state.lmb_down);
+
+        let start = state.perspective_camera.state().position;
+        state.handle_key(KeyCode::KeyW, true);
+        state.update(dt);
+        assert_ne!(state.perspective_camera.state().position, start);
+
+        // Held keys keep moving the camera until released
+        state.handle_key(KeyCode::KeyW, false);
+        let stopped = state.perspective_camera.state().position;
+        state.update(dt);
+        assert_eq!(state.perspective_camera.state().position, stopped);
+
+        assert!(!state.show_heatmap);
+        state.handle_key(KeyCode::KeyH, true);
+        state.handle_key(KeyCode::KeyH, false);
+        assert!(state.show_heatmap);
+
+        assert!(state.show_grid);
+        state.handle_key(KeyCode::KeyG, true);
+        state.handle_key(KeyCode::KeyG, false);
+        assert!(!state.show_grid);
+    }
+
+    #[test]
+    fn test_canvas_state_orbit() {
+        let camera = PerspectiveCamera::new(
+            glam::vec3(0.0, 0.0, 5.0),
+            0.0,
+            0.0,
+            800,
+            600,
+            f32::consts::FRAC_PI_4,
+            0.1,
+            100.0,
+        );
+        let mut sim = Simulation::new();
+        sim.add_node(10.0, 20.0, 0.5, 100.0, glam::vec3(-1.0, 0.0, 0.0));
+        sim.add_node(10.0, 20.0, 0.5, 100.0, glam::vec3(3.0, 0.0, 0.0));
+        let mut state = CanvasState::new(sim, Environment::default(), camera);
+        let dt = web_time::Duration::from_millis(100);
+
+        state.handle_key(KeyCode::KeyO, true);
+        state.handle_key(KeyCode::KeyO, false);
+        assert!(state.orbiting);
+
+        // Orbits the middle of the nodes rather than the origin
+        let center = glam::vec3(1.0, 0.0, 0.0);
+        let distance = state.perspective_camera.position.distance(center);
+        state.handle_mouse_button(MouseButton::Left, true);
+        state.handle_mouse_move(100.0, 0.0);
+        state.update(dt);
+        state.handle_mouse_button(MouseButton::Left, false);
+        let position = state.perspective_camera.position;
+        assert!((position.distance(center) - distance).abs() < 1e-4);
+
+        // Orbiting ignores the movement keys
+        state.handle_key(KeyCode::KeyW, true);
+        state.update(dt);
+        assert_eq!(state.perspective_camera.position, position);
+
+        state.handle_key(KeyCode::KeyO, true);
+        assert!(!state.orbiting);
+    }
+
+    #[test]
+    fn test_canvas_state_warm_start() {
+        let camera = PerspectiveCamera::new(
+            glam::vec3(0.0, 0.0, 3.0),
+            0.0,
+            0.0,
+            800,
+            600,
+            f32::consts::FRAC_PI_4,
+            0.1,
+            100.0,
+        );
+        let (sim, _, extractor) = demo_simulation();
+        let cold = sim.nodes()[extractor].fluid.temp;
+        let mut state = CanvasState {
+            warming_up: true,
+            ..CanvasState::new(sim, Environment::default(), camera)
+        };
+        let dt = web_time::Duration::from_millis(16);
+
+        let mut frames = 0;
+        while state.warming_up && frames < 100 {
+            state.update(dt);
+            frames += 1;
+        }
+        assert!(!state.warming_up);
+        assert!(state.simulation.nodes()[extractor].fluid.temp > cold);
+
+        // A loaded scene isn't warmed up over
+        state.warming_up = true;
+        state.apply_scene(state.scene());
+        assert!(!state.warming_up);
+    }
+
+    #[test]
+    fn test_canvas_state_scroll() {
+        let camera = PerspectiveCamera::new(
+            glam::vec3(-2.0, 0.0, 0.0),
+            0.0,
+            0.0,
+            800,
+            600,
+            f32::consts::FRAC_PI_4,
+            0.1,
+            100.0,
+        );
+        let mut state = CanvasState::new(Simulation::new(), Environment::default(), camera);
+        let dt = web_time::Duration::from_millis(100);
+        let x = |state: &CanvasState| state.perspective_camera.state().position.x;
+
+        // A wheel notch and a notch's worth of touchpad pixels move the same
+        state.handle_scroll(&MouseScrollDelta::LineDelta(0.0, -1.0));
+        state.update(dt);
+        let line = x(&state) + 2.0;
+        assert!(line > 0.0);
+        state.handle_scroll(&MouseScrollDelta::PixelDelta(
+            winit::dpi::PhysicalPosition::new(0.0, -100.0),
+        ));
+        state.update(dt);
+        assert!((x(&state) + 2.0 - line * 2.0).abs() < 1e-5);
+
+        // Scrolling hard toward the origin stops just short of it
+        state.handle_scroll(&MouseScrollDelta::LineDelta(0.0, -1000.0));
+        state.update(dt);
+        assert!((x(&state) + MIN_ZOOM_DISTANCE).abs() < 1e-5);
+        state.handle_scroll(&MouseScrollDelta::LineDelta(0.0, -1000.0));
+        state.update(dt);
+        assert!((x(&state) + MIN_ZOOM_DISTANCE).abs() < 1e-5);
+
+        // Backing away isn't limited
+        state.handle_scroll(&MouseScrollDelta::LineDelta(0.0, 10.0));
+        state.update(dt);
+        assert!(x(&state) < -MIN_ZOOM_DISTANCE);
+    }
+
+    #[test]
+    fn test_tooltip_text() {
+        let fluid = Fluid {
+            volume: 8.14,
+            temp: 42.27,
+        };
+        assert_eq!(tooltip_text(UnitSystem::Metric, &fluid), "42.3 C / 8.1 mL");
+
+        let fluid = Fluid::from_gallons_fahrenheit(1.5, 100.0);
+        assert_eq!(
+            tooltip_text(UnitSystem::Imperial, &fluid),
+            "100.0 F / 1.50 gal"
+        );
+    }
+
+    #[test]
+    fn test_sun_light() {
+        let mut environment = Environment::default();
+        let noon = sun_light(&environment);
+        assert!(noon.direction.abs_diff_eq(glam::Vec4::Y, 1e-6));
+        assert_eq!(noon.color.truncate(), SUN_COLOR);
+
+        environment.set_cloud_cover(0.5);
+        assert_eq!(sun_light(&environment).color.truncate(), SUN_COLOR * 0.5);
+
+        // Dims towards the horizon, then goes out
+        environment.set_cloud_cover(0.0);
+        environment.set_sun_angle(0.05);
+        let dawn = sun_light(&environment);
+        assert!(dawn.color.x > 0.0 && dawn.color.x < SUN_COLOR.x);
+        environment.set_sun_angle(-0.1);
+        assert_eq!(sun_light(&environment).color.truncate(), glam::Vec3::ZERO);
+
+        environment.set_sun_angle(f32::consts::FRAC_PI_2);
+        environment.set_sun_enabled(false);
+        assert_eq!(sun_light(&environment), DirectionalLight::OFF);
+    }
+
+    #[test]
+    fn test_pick_instance() {
+        // Looking down +Z at a node on the origin
+        let camera = PerspectiveCamera::new(
+            glam::vec3(0.0, 0.0, -5.0),
+            f32::consts::FRAC_PI_2,
+            0.0,
+            800,
+            600,
+            f32::consts::FRAC_PI_4,
+            0.1,
+            100.0,
+        );
+        let instances = [
+            ColoredInstance::with_position_scale(glam::Vec3::ONE, glam::vec3(0.0, 0.0, 2.0), 0.1),
+            ColoredInstance::with_position_scale(glam::Vec3::ONE, glam::Vec3::ZERO, 0.1),
+            ColoredInstance::with_position_scale(glam::Vec3::ONE, glam::vec3(1.0, 0.0, 0.0), 0.1),
+        ];
+
+        let (origin, dir) = camera.ray_through(glam::Vec2::ZERO);
+        assert!(dir.abs_diff_eq(glam::Vec3::Z, 1e-5));
+        // Both nodes down the middle are hit but the nearer one wins
+        assert_eq!(pick_instance(&instances, origin, dir), Some(1));
+
+        // Off to the side, +X is on the left when looking down +Z
+        let (origin, dir) = camera.ray_through(glam::vec2(-0.1, 0.0));
+        assert_eq!(pick_instance(&instances, origin, dir), None);
+        let target = camera.view_proj().project_point3(glam::vec3(1.0, 0.0, 0.0));
+        let (origin, dir) = camera.ray_through(target.truncate());
+        assert_eq!(pick_instance(&instances, origin, dir), Some(2));
+    }
+
+    #[test]
+    fn test_canvas_state_update_ticks() {
+        let camera = PerspectiveCamera::new(
+            glam::Vec3::ZERO,
+            0.0,
+            0.0,
+            800,
+            600,
+            f32::consts::FRAC_PI_4,
+            0.1,
+            100.0,
+        );
+        let (simulation, _, _) = demo_simulation();
+        let mut state = CanvasState::new(simulation, Environment::default(), camera);
+        let before = build_node_instances(&state.simulation, 1.0, &default_node_style);
+        let connections_before = build_connection_instances(&state.simulation);
+        let temps = |state: &CanvasState| {
+            state
+                .simulation
+                .nodes()
+                .iter()
+                .map(|node| node.fluid.temp)
+                .collect::<Vec<_>>()
+        };
+        let start = temps(&state);
+
+        assert!(state.update(SIMULATION_TICK_RATE * 10) >= 10);
+        assert_ne!(temps(&state), start);
+        // What gets written to the instance buffers follows
+        let after = build_node_instances(&state.simulation, 1.0, &default_node_style);
+        assert!(before
+            .iter()
+            .zip(&after)
+            .any(|(before, after)| before.color() != after.color()));
+        // Connections too, both how hot they are and how much they carry
+        let connections_after = build_connection_instances(&state.simulation);
+        assert_eq!(connections_before.len(), connections_after.len());
+        let pairs = connections_before
+            .iter()
+            .zip(&connections_after)
+            .collect::<Vec<_>>();
+        assert!(pairs
+            .iter()
+            .any(|(before, after)| before.color() != after.color()));
+        assert!(pairs
+            .iter()
+            .any(|(before, after)| before.model_matrix() != after.model_matrix()));
+    }
+
+    #[test]
+    fn test_select_surface_format() {
+        use wgpu::TextureFormat::*;
+
+        let formats = [Bgra8Unorm, Rgba16Float, Bgra8UnormSrgb];
+        assert_eq!(
+            select_surface_format(&formats, true),
+            Some((Rgba16Float, Rgba16Float))
+        );
+        assert_eq!(
+            select_surface_format(&formats, false),
+            Some((Bgra8Unorm, Bgra8UnormSrgb))
+        );
+
+        // No HDR support falls back to sRGB
+        assert_eq!(
+            select_surface_format(&[Rgb10a2Unorm, Rgba8UnormSrgb], true),
+            Some((Rgba8UnormSrgb, Rgba8UnormSrgb))
+        );
+        assert_eq!(
+            select_surface_format(&[Rgb10a2Unorm], true),
+            Some((Rgb10a2Unorm, Rgb10a2Unorm))
+        );
+        assert_eq!(select_surface_format(&[], true), None);
+    }
+
+    #[test]
+    fn test_connection_color_follows_source() {
+        let mut simulation = Simulation::new();
+        let hot = simulation.add_node(10.0, HOT_TEMP, 1.0, 100.0, glam::Vec3::ZERO);
+        let cold = simulation.add_node(10.0, COLD_TEMP, 1.0, 100.0, glam::Vec3::X);
+        let hot = simulation.get_node(hot).unwrap();
+        let cold = simulation.get_node(cold).unwrap();
+
+        assert_eq!(connection_color(hot), HOT_COLOR);
+        assert_eq!(connection_color(cold), COLD_COLOR);
+
+        // Reversed flow carries the output's fluid and is just as wide
+        let forward = instance_from_connection(1.0, 1.0, hot, cold);
+        let reversed = instance_from_connection(-1.0, -1.0, hot, cold);
+        assert_eq!(forward.color().truncate(), HOT_COLOR);
+        assert_eq!(reversed.color().truncate(), COLD_COLOR);
+        assert_eq!(forward.model_matrix(), reversed.model_matrix());
+    }
+
+    #[test]
+    fn test_connection_radius_follows_flow() {
+        // A full small pipe is thinner than a full large one
+        assert!(connection_radius(1.0) < connection_radius(5.0));
+        assert_eq!(connection_radius(-5.0), connection_radius(5.0));
+        assert_eq!(connection_radius(0.0), MIN_CONNECTION_RADIUS);
+        assert_eq!(connection_radius(1_000.0), MAX_CONNECTION_RADIUS);
+    }
+
+    #[test]
+    fn test_demo_simulation_heats() {
+        let (mut simulation, solar_panel, _) = demo_simulation();
+        let panels = simulation
+            .solar_panels()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(panels, [solar_panel]);
+
+        simulation.set_tick_stages(TickStages::new([TickStage::SolarPanels]));
+        simulation.tick(&Environment::default(), 1.0);
+        assert!(simulation.node_energy_delta(solar_panel).unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_temperature_to_color() {
+        assert_eq!(temperature_to_color(COLD_TEMP), COLD_COLOR);
+        assert_eq!(
+            temperature_to_color((COLD_TEMP + HOT_TEMP) / 2.0),
+            WARM_COLOR
+        );
+        assert_eq!(temperature_to_color(HOT_TEMP), HOT_COLOR);
+        assert_eq!(temperature_to_color(HOT_TEMP + 50.0), HOT_COLOR);
+        assert_eq!(temperature_to_color(COLD_TEMP - 50.0), COLD_COLOR);
+
+        assert_eq!(
+            temperature_to_color_in(15.0, 10.0, 30.0),
+            glam::vec3(0.0, 0.5, 0.5)
+        );
+        assert_eq!(
+            temperature_to_color_in(25.0, 10.0, 30.0),
+            glam::vec3(0.5, 0.5, 0.0)
+        );
+        assert_eq!(temperature_to_color_in(30.0, 20.0, 20.0), HOT_COLOR);
+        assert_eq!(temperature_to_color_in(20.0, 20.0, 20.0), COLD_COLOR);
+    }
+
+    #[test]
+    fn test_node_tint() {
+        let mut simulation = Simulation::new();
+        let node = simulation.add_node(10.0, 25.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let untinted = node_color(simulation.get_node(node).unwrap());
+        assert_eq!(untinted, temperature_to_color(25.0));
+
+        let tint = glam::vec3(0.5, 1.0, 0.25);
+        assert!(simulation.set_node_tint(node, tint));
+        assert!(!simulation.set_node_tint(node + 1, tint));
+        let tinted = simulation.get_node(node).unwrap();
+        assert_eq!(node_color(tinted), untinted * tint);
+        assert_eq!(connection_color(tinted), untinted * tint);
+    }
 }
@@ -0,0 +1,173 @@
+//! C ABI for driving the simulation from a non-Rust host.
+//!
+//! The simulation is handed out as an opaque pointer that must be released
+//! with [`plc_simulation_free`]. All functions are null-safe and none of them
+//! unwind across the FFI boundary.
+
+use crate::simulation::{Environment, Simulation};
+
+/// Returned by [`plc_simulation_add_node`] when the node couldn't be added.
+pub const PLC_INVALID_NODE: usize = usize::MAX;
+
+pub struct PlcSimulation {
+    simulation: Simulation,
+    environment: Environment,
+}
+
+#[no_mangle]
+pub extern "C" fn plc_simulation_new() -> *mut PlcSimulation {
+    Box::into_raw(Box::new(PlcSimulation {
+        simulation: Simulation::new(),
+        environment: Environment::default(),
+    }))
+}
+
+/// # Safety
+///
+/// `sim` must be null or a pointer returned by [`plc_simulation_new`] that
+/// hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn plc_simulation_free(sim: *mut PlcSimulation) {
+    if !sim.is_null() {
+        drop(Box::from_raw(sim));
+    }
+}
+
+/// Returns the id of the new node or [`PLC_INVALID_NODE`] if `sim` is null.
+///
+/// # Safety
+///
+/// `sim` must be null or a live pointer returned by [`plc_simulation_new`].
+#[no_mangle]
+pub unsafe extern "C" fn plc_simulation_add_node(
+    sim: *mut PlcSimulation,
+    volume: f32,
+    temp: f32,
+    insulation: f32,
+    capacity: f32,
+    x: f32,
+    y: f32,
+    z: f32,
+) -> usize {
+    match sim.as_mut() {
+        Some(sim) => {
+            sim.simulation
+                .add_node(volume, temp, insulation, capacity, glam::vec3(x, y, z))
+        }
+        None => PLC_INVALID_NODE,
+    }
+}
+
+/// Returns `false` if `sim` is null or either node doesn't exist.
+///
+/// # Safety
+///
+/// `sim` must be null or a live pointer returned by [`plc_simulation_new`].
+#[no_mangle]
+pub unsafe extern "C" fn plc_simulation_connect_nodes(
+    sim: *mut PlcSimulation,
+    input: usize,
+    output: usize,
+    flow_rate: f32,
+) -> bool {
+    match sim.as_mut() {
+        Some(sim)
+            if sim.simulation.contains_node(input) && sim.simulation.contains_node(output) =>
+        {
+            sim.simulation.connect_node(input, output, flow_rate);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be null or a live pointer returned by [`plc_simulation_new`].
+#[no_mangle]
+pub unsafe extern "C" fn plc_simulation_tick(sim: *mut PlcSimulation, dt: f32) {
+    if let Some(sim) = sim.as_mut() {
+        sim.simulation.tick(&sim.environment, dt);
+    }
+}
+
+/// # Safety
+///
+/// `sim` must be null or a live pointer returned by [`plc_simulation_new`].
+#[no_mangle]
+pub unsafe extern "C" fn plc_simulation_node_count(sim: *const PlcSimulation) -> usize {
+    sim.as_ref().map_or(0, |sim| sim.simulation.nodes().len())
+}
+
+/// Copies up to `len` node temperatures into `out`, in id order without
+/// removed nodes, and returns how many were written.
+///
+/// # Safety
+///
+/// `sim` must be null or a live pointer returned by [`plc_simulation_new`].
+/// `out` must be null or valid for writes of `len` floats.
+#[no_mangle]
+pub unsafe extern "C" fn plc_simulation_read_temps(
+    sim: *const PlcSimulation,
+    out: *mut f32,
+    len: usize,
+) -> usize {
+    let sim = match sim.as_ref() {
+        Some(sim) if !out.is_null() => sim,
+        _ => return 0,
+    };
+
+    let out = std::slice::from_raw_parts_mut(out, len);
+    let mut count = 0;
+    for (temp, node) in out.iter_mut().zip(sim.simulation.nodes()) {
+        *temp = node.fluid.temp;
+        count += 1;
+    }
+
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi_round_trip() {
+        unsafe {
+            let sim = plc_simulation_new();
+
+            let a = plc_simulation_add_node(sim, 10.0, 100.0, 1.0, 100.0, 0.0, 0.0, 0.0);
+            let b = plc_simulation_add_node(sim, 10.0, 0.0, 1.0, 100.0, 1.0, 0.0, 0.0);
+            assert_eq!(plc_simulation_node_count(sim), 2);
+
+            assert!(plc_simulation_connect_nodes(sim, a, b, 1.0));
+            assert!(!plc_simulation_connect_nodes(sim, a, 42, 1.0));
+
+            plc_simulation_tick(sim, 1.0);
+
+            let mut temps = [0.0f32; 4];
+            let written = plc_simulation_read_temps(sim, temps.as_mut_ptr(), temps.len());
+            assert_eq!(written, 2);
+            assert_eq!(temps[0], 100.0);
+            assert!(temps[1] > 0.0);
+
+            plc_simulation_free(sim);
+        }
+    }
+
+    #[test]
+    fn test_ffi_null_safety() {
+        unsafe {
+            let null = std::ptr::null_mut();
+            assert_eq!(
+                plc_simulation_add_node(null, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0),
+                PLC_INVALID_NODE
+            );
+            assert!(!plc_simulation_connect_nodes(null, 0, 1, 1.0));
+            plc_simulation_tick(null, 1.0);
+            assert_eq!(plc_simulation_node_count(null), 0);
+            assert_eq!(plc_simulation_read_temps(null, std::ptr::null_mut(), 0), 0);
+            plc_simulation_free(null);
+        }
+    }
+}
@@ -1,5 +1,10 @@
-mod resources;
-mod simulation;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "python")]
+mod python;
+pub mod resources;
+pub mod scene;
+pub mod simulation;
 mod utils;
 
 use std::sync::Arc;
@@ -23,6 +28,7 @@ pub struct App {
     #[cfg(target_arch = "wasm32")]
     proxy: Option<winit::event_loop::EventLoopProxy<canvas::Canvas>>,
     canvas: Option<canvas::Canvas>,
+    node_style: Option<Box<NodeStyle>>,
 }
 
 impl App {
@@ -32,10 +38,27 @@ impl App {
 
         Self {
             canvas: None,
+            node_style: None,
             #[cfg(target_arch = "wasm32")]
             proxy,
         }
     }
+
+    /// Overrides how nodes are drawn, see [`NodeStyle`].
+    pub fn with_node_style(
+        mut self,
+        style: impl Fn(usize, &simulation::Node) -> resources::vertex::ColoredInstance + 'static,
+    ) -> Self {
+        self.node_style = Some(Box::new(style));
+        self
+    }
+
+    fn set_canvas(&mut self, mut canvas: canvas::Canvas) {
+        if let Some(style) = self.node_style.take() {
+            canvas.set_node_style(style);
+        }
+        self.canvas = Some(canvas);
+    }
 }
 
 impl ApplicationHandler<canvas::Canvas> for App {
@@ -59,7 +82,7 @@ impl ApplicationHandler<canvas::Canvas> for App {
 
         #[cfg(not(target_arch = "wasm32"))]
         {
-            self.canvas = Some(pollster::block_on(canvas::Canvas::new(window)).unwrap());
+            self.set_canvas(pollster::block_on(canvas::Canvas::new(window)).unwrap());
         }
 
         #[cfg(target_arch = "wasm32")]
@@ -88,7 +111,7 @@ impl ApplicationHandler<canvas::Canvas> for App {
                 event.window.inner_size().height,
             );
         }
-        self.canvas = Some(event);
+        self.set_canvas(event);
     }
 
     fn device_event(
@@ -128,10 +151,14 @@ impl ApplicationHandler<canvas::Canvas> for App {
                 canvas.render(event_loop);
             }
             // WindowEvent::ModifiersChanged(mods) => {}
-            // WindowEvent::CursorMoved { position, .. } => {}
+            WindowEvent::CursorMoved { position, .. } => {
+                canvas.handle_cursor_moved(position.x as f32, position.y as f32)
+            }
+            WindowEvent::CursorLeft { .. } => canvas.handle_cursor_left(),
             WindowEvent::MouseInput { state, button, .. } => {
                 canvas.handle_mouse_button(button, state.is_pressed())
             }
+            WindowEvent::MouseWheel { delta, .. } => canvas.handle_scroll(&delta),
             WindowEvent::KeyboardInput {
                 event:
                     KeyEvent {
@@ -151,6 +178,8 @@ impl ApplicationHandler<canvas::Canvas> for App {
 
 mod canvas;
 
+pub use canvas::NodeStyle;
+
 pub fn run() -> anyhow::Result<()> {
     #[cfg(not(target_arch = "wasm32"))]
     {
@@ -0,0 +1,105 @@
+//! Python bindings for the simulation core.
+//!
+//! Build the extension module with `maturin` using
+//! `--features python,pyo3/extension-module`.
+
+use numpy::PyArray1;
+use pyo3::prelude::*;
+
+use crate::simulation::{Environment, Simulation, SolarPanel};
+
+#[pyclass(name = "Environment")]
+#[derive(Default)]
+pub struct PyEnvironment {
+    inner: Environment,
+}
+
+#[pymethods]
+impl PyEnvironment {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[pyclass(name = "Simulation")]
+#[derive(Default)]
+pub struct PySimulation {
+    inner: Simulation,
+}
+
+#[pymethods]
+impl PySimulation {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    #[pyo3(signature = (volume, temp, insulation, capacity, position = (0.0, 0.0, 0.0)))]
+    fn add_node(
+        &mut self,
+        volume: f32,
+        temp: f32,
+        insulation: f32,
+        capacity: f32,
+        position: (f32, f32, f32),
+    ) -> usize {
+        self.inner
+            .add_node(volume, temp, insulation, capacity, position.into())
+    }
+
+    fn connect_node(&mut self, input: usize, output: usize, flow_rate: f32) {
+        self.inner.connect_node(input, output, flow_rate);
+    }
+
+    fn attach_solar_panel(&mut self, id: usize, area: f32, efficiency: f32) {
+        self.inner
+            .attach_solar_panel(id, SolarPanel { area, efficiency });
+    }
+
+    fn tick(&mut self, environment: &PyEnvironment, dt: f32) {
+        self.inner.tick(&environment.inner, dt);
+    }
+
+    /// Node temperatures in degrees Celsius as a NumPy array.
+    fn temperatures<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f32>> {
+        PyArray1::from_vec(py, self.temps())
+    }
+}
+
+impl PySimulation {
+    fn temps(&self) -> Vec<f32> {
+        self.inner
+            .nodes()
+            .iter()
+            .map(|node| node.fluid.temp)
+            .collect()
+    }
+}
+
+#[pymodule]
+fn passive_logic_challenge(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyEnvironment>()?;
+    m.add_class::<PySimulation>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_updates_temperatures() {
+        let environment = PyEnvironment::new();
+        let mut sim = PySimulation::new();
+
+        let panel = sim.add_node(10.0, 20.0, 1.0, 100.0, (0.0, 0.0, 0.0));
+        sim.attach_solar_panel(panel, 1.0, 0.9);
+
+        let before = sim.temps();
+        sim.tick(&environment, 1.0);
+        let after = sim.temps();
+
+        assert!(after[panel] > before[panel]);
+    }
+}
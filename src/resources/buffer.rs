@@ -45,6 +45,10 @@ impl<T: bytemuck::Pod + bytemuck::Zeroable> BackedBuffer<T> {
         self.data.len() as _
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
     pub fn version(&self) -> u32 {
         self.version
     }
@@ -67,9 +71,64 @@ impl<T: bytemuck::Pod + bytemuck::Zeroable> BackedBuffer<T> {
         IndexedBatch::new(device, queue, self, indices)
     }
 
-    #[allow(unused)]
+    /// The part of the buffer holding data. Like any [`wgpu::BufferSlice`]
+    /// this panics if the buffer has no room at all.
     pub fn slice(&self) -> wgpu::BufferSlice<'_> {
-        self.buffer.slice(..)
+        if self.data.is_empty() {
+            return self.buffer.slice(..);
+        }
+        self.buffer
+            .slice(..(self.data.len() * size_of::<T>()) as wgpu::BufferAddress)
+    }
+
+    /// How many elements fit before the buffer has to be reallocated.
+    pub fn capacity(&self) -> usize {
+        (self.buffer.size() / size_of::<T>() as wgpu::BufferAddress) as _
+    }
+
+    /// Makes room for at least `additional` more elements. The capacity at
+    /// least doubles when it grows, so pushing one at a time stays cheap.
+    pub fn reserve(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, additional: usize) {
+        let required = self.data.len() + additional;
+        if required > self.capacity() {
+            self.reallocate(device, queue, required.max(self.capacity() * 2));
+        }
+    }
+
+    pub fn push(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, value: T) {
+        self.extend(device, queue, [value]);
+    }
+
+    /// Appends `values`, uploading only the new ones unless the buffer has to
+    /// grow.
+    pub fn extend(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        values: impl IntoIterator<Item = T>,
+    ) {
+        let start = self.data.len();
+        self.data.extend(values);
+        self.upload_from(device, queue, start);
+    }
+
+    /// Uploads the data from `start` on, which was added since the last
+    /// upload, growing the buffer if it no longer fits.
+    fn upload_from(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, start: usize) {
+        if self.data.len() > self.capacity() {
+            self.reallocate(device, queue, self.data.len().max(self.capacity() * 2));
+        } else if start < self.data.len() {
+            queue.write_buffer(
+                &self.buffer,
+                (start * size_of::<T>()) as wgpu::BufferAddress,
+                bytemuck::cast_slice(&self.data[start..]),
+            );
+        }
+    }
+
+    /// Keeps the first `len` elements. The buffer keeps its capacity.
+    pub fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
     }
 
     pub fn update(&mut self, queue: &wgpu::Queue, mut f: impl FnMut(&mut [T])) {
@@ -84,6 +143,22 @@ impl<T: bytemuck::Pod + bytemuck::Zeroable> BackedBuffer<T> {
     pub fn clear(&mut self) {
         self.data.clear();
     }
+
+    /// Replaces the buffer with one fitting `capacity` elements and uploads
+    /// the data to it.
+    fn reallocate(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, capacity: usize) {
+        self.data.reserve(capacity - self.data.len());
+        self.buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (capacity * size_of::<T>()) as wgpu::BufferAddress,
+            usage: self.usage,
+            mapped_at_creation: false,
+        });
+        if !self.data.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.data));
+        }
+        self.version += 1;
+    }
 }
 
 pub struct Batch<'a, T: bytemuck::Pod + bytemuck::Zeroable> {
@@ -115,30 +190,8 @@ impl<'a, T: bytemuck::Pod + bytemuck::Zeroable> Batch<'a, T> {
 
 impl<'a, T: bytemuck::Pod + bytemuck::Zeroable> Drop for Batch<'a, T> {
     fn drop(&mut self) {
-        if self.start_vertex < self.vertices.data.len() {
-            let size = (self.vertices.data.capacity() * size_of::<T>()) as wgpu::BufferAddress;
-            if size > self.vertices.buffer.size() {
-                self.vertices.buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
-                    label: None,
-                    size,
-                    usage: self.vertices.usage,
-                    mapped_at_creation: false,
-                });
-                self.queue.write_buffer(
-                    &self.vertices.buffer,
-                    0,
-                    bytemuck::cast_slice(&self.vertices.data),
-                );
-                self.vertices.version += 1;
-            } else {
-                let offset = (self.start_vertex * size_of::<T>()) as wgpu::BufferAddress;
-                self.queue.write_buffer(
-                    &self.vertices.buffer,
-                    offset,
-                    bytemuck::cast_slice(&self.vertices.data[self.start_vertex..]),
-                );
-            }
-        }
+        self.vertices
+            .upload_from(self.device, self.queue, self.start_vertex);
     }
 }
 
@@ -179,30 +232,8 @@ impl<'a, T: bytemuck::Pod + bytemuck::Zeroable> IndexedBatch<'a, T> {
 
 impl<'a, T: bytemuck::Pod + bytemuck::Zeroable> Drop for IndexedBatch<'a, T> {
     fn drop(&mut self) {
-        if self.start_index < self.indices.data.len() {
-            let size = (self.indices.data.capacity() * size_of::<T>()) as wgpu::BufferAddress;
-            if size > self.indices.buffer.size() {
-                self.indices.buffer = self.batch.device.create_buffer(&wgpu::BufferDescriptor {
-                    label: None,
-                    size,
-                    usage: self.indices.usage,
-                    mapped_at_creation: false,
-                });
-                self.batch.queue.write_buffer(
-                    &self.indices.buffer,
-                    0,
-                    bytemuck::cast_slice(&self.indices.data),
-                );
-                self.indices.version += 1;
-            } else {
-                let offset = (self.start_index * size_of::<T>()) as wgpu::BufferAddress;
-                self.batch.queue.write_buffer(
-                    &self.indices.buffer,
-                    offset,
-                    bytemuck::cast_slice(&self.indices.data[self.start_index..]),
-                );
-            }
-        }
+        self.indices
+            .upload_from(self.batch.device, self.batch.queue, self.start_index);
     }
 }
 
@@ -1,13 +1,37 @@
 use core::f32;
 
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
-use winit::{dpi::PhysicalPosition, event::MouseScrollDelta, keyboard::KeyCode};
+use winit::{
+    dpi::PhysicalPosition,
+    event::{MouseButton, MouseScrollDelta},
+    keyboard::KeyCode,
+};
 
 pub trait Camera {
     fn view_position(&self) -> glam::Vec4;
     fn view_proj(&self) -> glam::Mat4;
 }
 
+/// Where `position` shows up through `camera` on a `width` by `height`
+/// screen, in pixels from the top left like an [`OrthoCamera`] covering the
+/// screen. `None` if it's behind the camera.
+pub fn world_to_screen(
+    camera: &impl Camera,
+    position: glam::Vec3,
+    width: u32,
+    height: u32,
+) -> Option<glam::Vec2> {
+    let clip = camera.view_proj() * position.extend(1.0);
+    if clip.w <= 0.0 {
+        return None;
+    }
+    let ndc = clip.truncate().truncate() / clip.w;
+    Some(glam::vec2(
+        (ndc.x + 1.0) * 0.5 * width as f32,
+        (1.0 - ndc.y) * 0.5 * height as f32,
+    ))
+}
+
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 #[repr(C)]
 pub struct CameraUniform {
@@ -125,6 +149,10 @@ impl Camera for OrthoCamera {
 }
 
 const SAFE_FRAC_PI_2: f32 = f32::consts::FRAC_PI_2 - 0.0001;
+/// Roughly how many pixels a touchpad scrolls for one notch of a wheel.
+const PIXELS_PER_LINE: f32 = 100.0;
+/// How far scrolling toward the origin can take the camera.
+pub const MIN_ZOOM_DISTANCE: f32 = 0.1;
 
 #[derive(Debug)]
 pub struct PerspectiveCamera {
@@ -177,6 +205,49 @@ impl PerspectiveCamera {
     pub fn calc_proj(&self) -> glam::Mat4 {
         glam::Mat4::perspective_rh(self.fovy, self.aspect, self.znear, self.zfar)
     }
+
+    /// The ray from the near plane through `ndc` on screen, as a start and a
+    /// unit direction.
+    pub fn ray_through(&self, ndc: glam::Vec2) -> (glam::Vec3, glam::Vec3) {
+        let inv_view_proj = self.view_proj().inverse();
+        let near = inv_view_proj.project_point3(ndc.extend(0.0));
+        let far = inv_view_proj.project_point3(ndc.extend(1.0));
+        (near, (far - near).normalize())
+    }
+}
+
+/// The parts of a [`PerspectiveCamera`] worth saving. The aspect ratio comes
+/// from the window so it's left out.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CameraState {
+    pub position: glam::Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl PerspectiveCamera {
+    pub fn state(&self) -> CameraState {
+        CameraState {
+            position: self.position,
+            yaw: self.yaw,
+            pitch: self.pitch,
+            fovy: self.fovy,
+            znear: self.znear,
+            zfar: self.zfar,
+        }
+    }
+
+    pub fn set_state(&mut self, state: CameraState) {
+        self.position = state.position;
+        self.yaw = state.yaw;
+        self.pitch = state.pitch.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2);
+        self.fovy = state.fovy;
+        self.znear = state.znear;
+        self.zfar = state.zfar;
+    }
 }
 
 impl Camera for PerspectiveCamera {
@@ -194,6 +265,31 @@ impl Camera for PerspectiveCamera {
     }
 }
 
+/// Turns input into camera movement. [`crate::canvas`] holds one of these
+/// behind a `Box` so the way the camera moves can be swapped at runtime.
+pub trait CameraControl {
+    /// Returns whether the key did anything.
+    fn process_keyboard(&mut self, key: KeyCode, pressed: bool) -> bool;
+    /// Mouse movement in pixels while `button` is held.
+    fn process_drag(&mut self, button: MouseButton, dx: f64, dy: f64);
+    fn process_mouse_scroll(&mut self, delta: &MouseScrollDelta);
+    /// Applies everything processed since the last call to `camera`.
+    fn update_camera(&mut self, camera: &mut PerspectiveCamera, dt: web_time::Duration);
+}
+
+/// Wheels report lines and touchpads report pixels, so pixels are converted
+/// to lines to scroll the same amount either way.
+fn scroll_lines(delta: &MouseScrollDelta) -> f32 {
+    match delta {
+        MouseScrollDelta::LineDelta(_, scroll) => *scroll,
+        MouseScrollDelta::PixelDelta(PhysicalPosition { y: scroll, .. }) => {
+            *scroll as f32 / PIXELS_PER_LINE
+        }
+    }
+}
+
+/// A free-flying camera. WASD moves, space and left shift go up and down,
+/// and dragging with the left button looks around.
 #[derive(Debug)]
 pub struct CameraController {
     amount_left: f32,
@@ -226,7 +322,14 @@ impl CameraController {
         }
     }
 
-    pub fn process_keyboard(&mut self, key: KeyCode, pressed: bool) -> bool {
+    pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        self.rotate_horizontal = mouse_dx as f32;
+        self.rotate_vertical = mouse_dy as f32;
+    }
+}
+
+impl CameraControl for CameraController {
+    fn process_keyboard(&mut self, key: KeyCode, pressed: bool) -> bool {
         let amount = if pressed { 1.0 } else { 0.0 };
         match key {
             KeyCode::KeyW | KeyCode::ArrowUp => {
@@ -257,21 +360,18 @@ impl CameraController {
         }
     }
 
-    pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
-        self.rotate_horizontal = mouse_dx as f32;
-        self.rotate_vertical = mouse_dy as f32;
+    fn process_drag(&mut self, button: MouseButton, dx: f64, dy: f64) {
+        if button == MouseButton::Left {
+            self.process_mouse(dx, dy);
+        }
     }
 
-    #[allow(unused)]
-    pub fn process_mouse_scroll(&mut self, delta: &MouseScrollDelta) {
-        self.scroll = match delta {
-            // I'm assuming a line is about 100 pixels
-            MouseScrollDelta::LineDelta(_, scroll) => -scroll * 0.5,
-            MouseScrollDelta::PixelDelta(PhysicalPosition { y: scroll, .. }) => -*scroll as f32,
-        };
+    /// Events add up until the next [`Self::update_camera`].
+    fn process_mouse_scroll(&mut self, delta: &MouseScrollDelta) {
+        self.scroll += -scroll_lines(delta) * 0.5;
     }
 
-    pub fn update_camera(&mut self, camera: &mut PerspectiveCamera, dt: web_time::Duration) {
+    fn update_camera(&mut self, camera: &mut PerspectiveCamera, dt: web_time::Duration) {
         let dt = dt.as_secs_f32();
 
         // Move forward/backward and left/right
@@ -288,7 +388,13 @@ impl CameraController {
         let (pitch_sin, pitch_cos) = camera.pitch.sin_cos();
         let scrollward =
             glam::Vec3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
-        camera.position += scrollward * self.scroll * self.speed * self.sensitivity * dt;
+        let mut amount = self.scroll * self.speed * self.sensitivity * dt;
+        // Stop short of the origin rather than flying through it
+        let to_origin = -camera.position.dot(scrollward);
+        if amount > 0.0 && to_origin > 0.0 {
+            amount = amount.min((to_origin - MIN_ZOOM_DISTANCE).max(0.0));
+        }
+        camera.position += scrollward * amount;
         self.scroll = 0.0;
 
         // Move up/down. Since we don't use roll, we can just
@@ -313,3 +419,183 @@ impl CameraController {
         }
     }
 }
+
+/// Circles the camera around a focus point, always looking at it. Dragging
+/// with the left button orbits, with the middle button pans the focus, and
+/// scrolling changes the distance to it.
+#[derive(Debug)]
+pub struct OrbitController {
+    pub focus: glam::Vec3,
+    rotate: glam::Vec2,
+    pan: glam::Vec2,
+    scroll: f32,
+    sensitivity: f32,
+}
+
+impl OrbitController {
+    /// Radians turned per pixel dragged at a sensitivity of one.
+    const RADIANS_PER_PIXEL: f32 = 0.005;
+    /// How much of the distance to the focus one pixel of panning moves.
+    const PAN_PER_PIXEL: f32 = 0.002;
+    /// How much of the distance to the focus one line of scrolling moves.
+    const ZOOM_PER_LINE: f32 = 0.1;
+
+    pub fn new(focus: glam::Vec3, sensitivity: f32) -> Self {
+        Self {
+            focus,
+            rotate: glam::Vec2::ZERO,
+            pan: glam::Vec2::ZERO,
+            scroll: 0.0,
+            sensitivity,
+        }
+    }
+}
+
+impl CameraControl for OrbitController {
+    fn process_keyboard(&mut self, _key: KeyCode, _pressed: bool) -> bool {
+        false
+    }
+
+    fn process_drag(&mut self, button: MouseButton, dx: f64, dy: f64) {
+        let delta = glam::vec2(dx as f32, dy as f32);
+        match button {
+            MouseButton::Left => self.rotate += delta,
+            MouseButton::Middle => self.pan += delta,
+            _ => {}
+        }
+    }
+
+    fn process_mouse_scroll(&mut self, delta: &MouseScrollDelta) {
+        self.scroll += scroll_lines(delta);
+    }
+
+    /// Drags and scrolls are in pixels and lines rather than per second, so
+    /// `dt` isn't needed. The camera's current position is kept as the
+    /// starting point, so switching to this from another controller doesn't
+    /// make it jump.
+    fn update_camera(&mut self, camera: &mut PerspectiveCamera, _dt: web_time::Duration) {
+        let offset = camera.position - self.focus;
+        let distance = offset.length().max(MIN_ZOOM_DISTANCE);
+        let mut yaw = offset.z.atan2(offset.x);
+        let mut pitch = (offset.y / distance).clamp(-1.0, 1.0).asin();
+
+        // Dragging right swings the camera left, so the scene turns with the
+        // cursor
+        let rotate = self.rotate * Self::RADIANS_PER_PIXEL * self.sensitivity;
+        yaw += rotate.x;
+        pitch = (pitch + rotate.y).clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2);
+        let distance =
+            (distance * (1.0 - self.scroll * Self::ZOOM_PER_LINE)).max(MIN_ZOOM_DISTANCE);
+
+        let (sin_pitch, cos_pitch) = pitch.sin_cos();
+        let (sin_yaw, cos_yaw) = yaw.sin_cos();
+        let direction = glam::vec3(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw);
+
+        // Pan in the plane facing the camera
+        let right = glam::Vec3::Y.cross(direction).normalize();
+        let up = direction.cross(right);
+        let pan = self.pan * Self::PAN_PER_PIXEL * distance;
+        self.focus += -right * pan.x + up * pan.y;
+
+        camera.position = self.focus + direction * distance;
+        camera.yaw = yaw + f32::consts::PI;
+        camera.pitch = -pitch;
+
+        self.rotate = glam::Vec2::ZERO;
+        self.pan = glam::Vec2::ZERO;
+        self.scroll = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera_at(position: glam::Vec3) -> PerspectiveCamera {
+        PerspectiveCamera::new(
+            position,
+            0.0,
+            0.0,
+            800,
+            600,
+            f32::consts::FRAC_PI_4,
+            0.1,
+            100.0,
+        )
+    }
+
+    #[test]
+    fn test_world_to_screen() {
+        // Looking down -z at the origin
+        let camera = PerspectiveCamera::new(
+            glam::vec3(0.0, 0.0, 5.0),
+            -f32::consts::FRAC_PI_2,
+            0.0,
+            800,
+            600,
+            f32::consts::FRAC_PI_4,
+            0.1,
+            100.0,
+        );
+        let center = world_to_screen(&camera, glam::Vec3::ZERO, 800, 600).unwrap();
+        assert!(center.abs_diff_eq(glam::vec2(400.0, 300.0), 1e-3));
+
+        // Screen y grows downwards
+        let above = world_to_screen(&camera, glam::Vec3::Y, 800, 600).unwrap();
+        assert!(above.y < center.y);
+        let right = world_to_screen(&camera, glam::Vec3::X, 800, 600).unwrap();
+        assert!(right.x > center.x);
+
+        assert_eq!(
+            world_to_screen(&camera, glam::vec3(0.0, 0.0, 10.0), 800, 600),
+            None
+        );
+    }
+
+    #[test]
+    fn test_orbit_drag_keeps_distance() {
+        let focus = glam::vec3(1.0, 2.0, 3.0);
+        let mut camera = camera_at(focus + glam::vec3(0.0, 1.0, 4.0));
+        let mut controller = OrbitController::new(focus, 1.0);
+        let dt = web_time::Duration::from_millis(16);
+        let distance = camera.position.distance(focus);
+
+        for _ in 0..10 {
+            let before = camera.position;
+            controller.process_drag(MouseButton::Left, 50.0, 0.0);
+            controller.update_camera(&mut camera, dt);
+            assert_ne!(camera.position, before);
+            assert!((camera.position.distance(focus) - distance).abs() < 1e-4);
+            // Horizontal drags don't change the height
+            assert!((camera.position.y - before.y).abs() < 1e-4);
+
+            // The camera keeps looking at the focus
+            let (origin, dir) = camera.ray_through(glam::Vec2::ZERO);
+            let to_focus = (focus - origin).normalize();
+            assert!(dir.abs_diff_eq(to_focus, 1e-4));
+        }
+    }
+
+    #[test]
+    fn test_orbit_pitch_and_zoom_limits() {
+        let mut camera = camera_at(glam::vec3(0.0, 0.0, 5.0));
+        let mut controller = OrbitController::new(glam::Vec3::ZERO, 1.0);
+        let dt = web_time::Duration::from_millis(16);
+
+        controller.process_drag(MouseButton::Left, 0.0, 100_000.0);
+        controller.update_camera(&mut camera, dt);
+        assert_eq!(camera.state().pitch, -SAFE_FRAC_PI_2);
+        assert!((camera.position.length() - 5.0).abs() < 1e-4);
+
+        controller.process_mouse_scroll(&MouseScrollDelta::LineDelta(0.0, 1000.0));
+        controller.update_camera(&mut camera, dt);
+        assert!((camera.position.length() - MIN_ZOOM_DISTANCE).abs() < 1e-4);
+
+        // Panning moves the focus and the camera with it
+        let before = camera.position;
+        controller.process_drag(MouseButton::Middle, 100.0, 0.0);
+        controller.update_camera(&mut camera, dt);
+        assert_ne!(controller.focus, glam::Vec3::ZERO);
+        assert!((camera.position - before).abs_diff_eq(controller.focus, 1e-5));
+    }
+}
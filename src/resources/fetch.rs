@@ -0,0 +1,61 @@
+//! Loading assets over HTTP in the browser.
+
+use std::path::Path;
+
+use anyhow::Context;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use super::AsyncResources;
+
+/// Fetches assets relative to a base URL, e.g. `"res"` for a `res` folder
+/// next to the page.
+pub struct FetchResources {
+    base_url: String,
+}
+
+impl FetchResources {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+        }
+    }
+
+    async fn fetch(&self, path: &Path) -> anyhow::Result<web_sys::Response> {
+        let url = super::join_url(&self.base_url, path);
+        log::info!("fetch({url})");
+        let window = web_sys::window().context("There's no window to fetch with")?;
+        let response = JsFuture::from(window.fetch_with_str(&url))
+            .await
+            .map_err(js_error)?
+            .dyn_into::<web_sys::Response>()
+            .map_err(js_error)?;
+        if !response.ok() {
+            anyhow::bail!("Fetching {url} failed with status {}", response.status());
+        }
+        Ok(response)
+    }
+}
+
+impl AsyncResources for FetchResources {
+    async fn load_string(&self, path: impl AsRef<Path>) -> anyhow::Result<String> {
+        let response = self.fetch(path.as_ref()).await?;
+        let text = JsFuture::from(response.text().map_err(js_error)?)
+            .await
+            .map_err(js_error)?;
+        text.as_string().context("Response text wasn't a string")
+    }
+
+    async fn load_binary(&self, path: impl AsRef<Path>) -> anyhow::Result<Vec<u8>> {
+        let response = self.fetch(path.as_ref()).await?;
+        let buffer = JsFuture::from(response.array_buffer().map_err(js_error)?)
+            .await
+            .map_err(js_error)?;
+        Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+    }
+}
+
+/// JS errors aren't `Send`, so they can't go in an [`anyhow::Error`] as is.
+fn js_error(value: wasm_bindgen::JsValue) -> anyhow::Error {
+    anyhow::anyhow!("{value:?}")
+}
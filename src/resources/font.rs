@@ -10,8 +10,9 @@ use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use crate::{resources::texture, utils::RenderPipelineBuilder};
 
 use super::{
+    buffer::BackedBuffer,
     camera::{CameraBinder, CameraBinding},
-    vertex::TexturedVertex,
+    vertex::{GlyphInstance, TexturedVertex},
     Resources,
 };
 
@@ -31,8 +32,12 @@ pub struct TextPipeline {
     font_uniforms: FontUniforms,
     font_uniform_buffer: wgpu::Buffer,
     text_pipeline: wgpu::RenderPipeline,
+    label_pipeline: wgpu::RenderPipeline,
     font_uniform_bg: wgpu::BindGroup,
     font_atlas: wgpu::BindGroup,
+    color_layout: wgpu::BindGroupLayout,
+    /// Labels are always drawn in [`TEXT_COLOR`].
+    label_color_bg: wgpu::BindGroup,
 }
 
 impl TextPipeline {
@@ -43,6 +48,7 @@ impl TextPipeline {
         texture_binder: &texture::TextureBinder,
         shader: &wgpu::ShaderModule,
         device: &wgpu::Device,
+        sample_count: u32,
     ) -> anyhow::Result<Self> {
         let font_uniforms = FontUniforms {
             unit_range: vec2(
@@ -87,12 +93,28 @@ impl TextPipeline {
             }],
         });
 
+        let color_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TextPipeline::color_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let (_, label_color_bg) = bind_color(device, &color_layout, TEXT_COLOR);
+
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("pipeline_layout"),
             bind_group_layouts: &[
                 texture_binder.layout(),
                 camera_binder.layout(),
                 &font_uniform_bg_layout,
+                &color_layout,
             ],
             push_constant_ranges: &[],
         });
@@ -115,8 +137,30 @@ impl TextPipeline {
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             })
+            .sample_count(sample_count)
             .build(&device)?;
 
+        let label_pipeline = RenderPipelineBuilder::new()
+            .layout(&pipeline_layout)
+            .vertex(wgpu::VertexState {
+                module: shader,
+                entry_point: Some("glyph_instance"),
+                compilation_options: Default::default(),
+                buffers: &[GlyphInstance::VB_DESC],
+            })
+            .fragment(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("msdf_text"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            })
+            .sample_count(sample_count)
+            .build(device)?;
+
         let font_atlas = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("font_atlas"),
             layout: &text_pipeline.get_bind_group_layout(0),
@@ -145,7 +189,10 @@ impl TextPipeline {
             font_uniform_buffer,
             font_uniform_bg,
             text_pipeline,
+            label_pipeline,
             font_atlas,
+            color_layout,
+            label_color_bg,
         })
     }
 
@@ -154,8 +201,9 @@ impl TextPipeline {
         font: &Font,
         device: &wgpu::Device,
         text: &str,
+        layout: &TextLayout,
     ) -> anyhow::Result<TextBuffer> {
-        let (verts, indices) = generate_text_data(font, text);
+        let (verts, indices) = generate_text_data(font, text, layout);
 
         let vb = device.create_buffer_init(&BufferInitDescriptor {
             label: Some(text),
@@ -168,10 +216,15 @@ impl TextPipeline {
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::INDEX,
         });
 
+        let (color_buffer, color_bg) = bind_color(device, &self.color_layout, TEXT_COLOR);
+
         Ok(TextBuffer {
             num_indices: indices.len() as _,
             indices: ib,
             vertices: vb,
+            color: TEXT_COLOR,
+            color_buffer,
+            color_bg,
         })
     }
 
@@ -179,11 +232,12 @@ impl TextPipeline {
         &self,
         font: &Font,
         text: &str,
+        layout: &TextLayout,
         buffer: &mut TextBuffer,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
     ) -> anyhow::Result<()> {
-        let (verts, indices) = generate_text_data(font, text);
+        let (verts, indices) = generate_text_data(font, text, layout);
 
         if verts.len() * size_of::<TexturedVertex>() > buffer.vertices.size() as usize {
             buffer.vertices = device.create_buffer_init(&BufferInitDescriptor {
@@ -219,82 +273,321 @@ impl TextPipeline {
         pass.set_bind_group(0, &self.font_atlas, &[]);
         pass.set_bind_group(1, camera_binding.bind_group(), &[]);
         pass.set_bind_group(2, &self.font_uniform_bg, &[]);
+        pass.set_bind_group(3, &text.color_bg, &[]);
         pass.set_vertex_buffer(0, text.vertices.slice(..));
         pass.set_index_buffer(text.indices.slice(..), wgpu::IndexFormat::Uint32);
         pass.set_pipeline(&self.text_pipeline);
         pass.draw_indexed(0..text.num_indices as u32, 0, 0..1);
     }
+
+    /// Lays out every label into one buffer so they can all be drawn at once
+    /// with [`Self::draw_labels`].
+    pub fn buffer_labels(
+        &self,
+        font: &Font,
+        device: &wgpu::Device,
+        labels: &[Label],
+    ) -> LabelBatch {
+        LabelBatch {
+            glyphs: BackedBuffer::with_data(
+                device,
+                generate_label_data(font, labels),
+                wgpu::BufferUsages::VERTEX,
+            ),
+            num_labels: labels.len(),
+        }
+    }
+
+    pub fn update_labels(
+        &self,
+        font: &Font,
+        labels: &[Label],
+        batch: &mut LabelBatch,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) {
+        batch.glyphs.clear();
+        let mut glyphs = batch.glyphs.batch(device, queue);
+        for glyph in generate_label_data(font, labels) {
+            glyphs.push(glyph);
+        }
+        batch.num_labels = labels.len();
+    }
+
+    /// Draws every label in `batch` with one instanced draw. Returns how many
+    /// glyphs were drawn.
+    pub fn draw_labels(
+        &self,
+        pass: &mut wgpu::RenderPass<'_>,
+        batch: &LabelBatch,
+        camera_binding: &CameraBinding,
+    ) -> u32 {
+        if batch.glyphs.is_empty() {
+            return 0;
+        }
+
+        pass.set_bind_group(0, &self.font_atlas, &[]);
+        pass.set_bind_group(1, camera_binding.bind_group(), &[]);
+        pass.set_bind_group(2, &self.font_uniform_bg, &[]);
+        pass.set_bind_group(3, &self.label_color_bg, &[]);
+        pass.set_vertex_buffer(0, batch.glyphs.slice());
+        pass.set_pipeline(&self.label_pipeline);
+        pass.draw(0..6, 0..batch.glyphs.len());
+        batch.glyphs.len()
+    }
 }
 
-fn generate_text_data(font: &Font, text: &str) -> (Vec<TexturedVertex>, Vec<u32>) {
-    let tex_width = font.texture.width() as f32;
-    let tex_height = font.texture.height() as f32;
+/// Text placed at `position`, with font units scaled by `scale`.
+#[derive(Debug, Clone, Copy)]
+pub struct Label<'a> {
+    pub text: &'a str,
+    pub position: glam::Vec3,
+    pub scale: f32,
+}
 
-    let mut cursor_x = 0.0;
-    let mut cursor_y = 0.0;
-    let mut i = 0u32;
+/// Many labels buffered together, see [`TextPipeline::buffer_labels`].
+pub struct LabelBatch {
+    glyphs: BackedBuffer<GlyphInstance>,
+    num_labels: usize,
+}
 
-    let mut verts = Vec::new();
-    let mut indices = Vec::new();
-    for c in text.chars() {
-        if c == '\n' {
-            cursor_x = 0.0;
-            cursor_y += font.info.common.line_height as f32;
-            continue;
+impl LabelBatch {
+    pub fn num_labels(&self) -> usize {
+        self.num_labels
+    }
+
+    pub fn num_glyphs(&self) -> u32 {
+        self.glyphs.len()
+    }
+}
+
+/// How each line of text sits relative to [`TextLayout::origin`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Alignment {
+    /// Lines start at the origin.
+    #[default]
+    Left,
+    /// Lines are centered on the origin.
+    Center,
+    /// Lines end at the origin.
+    Right,
+}
+
+/// Where and how [`TextPipeline`] lays out text. Lines are split on `\n`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextLayout {
+    /// Where the first line is, in pixels from the top left.
+    pub origin: Vec2,
+    pub alignment: Alignment,
+    /// How far each line is below the last, or the font's line height if
+    /// `None`.
+    pub line_height: Option<f32>,
+}
+
+impl TextLayout {
+    /// Left aligned text starting at `origin`.
+    pub fn at(origin: Vec2) -> Self {
+        Self {
+            origin,
+            ..Default::default()
         }
+    }
 
-        let glyph = font.glyph(c).unwrap_or_else(|| font.unknown_glyph());
+    pub fn with_alignment(mut self, alignment: Alignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
 
-        if glyph.width == 0 || glyph.height == 0 {
-            cursor_x += glyph.xadvance as f32;
-            continue;
+    pub fn with_line_height(mut self, line_height: f32) -> Self {
+        self.line_height = Some(line_height);
+        self
+    }
+
+    fn line_height_for(&self, font: &Font) -> f32 {
+        self.line_height
+            .unwrap_or(font.info.common.line_height as f32)
+    }
+}
+
+impl Default for TextLayout {
+    /// Left aligned in the top left corner.
+    fn default() -> Self {
+        Self {
+            origin: TEXT_ORIGIN,
+            alignment: Alignment::default(),
+            line_height: None,
         }
+    }
+}
 
-        let min_uv = glam::vec2(glyph.x as f32 / tex_width, glyph.y as f32 / tex_height);
-        let max_uv = min_uv
-            + glam::vec2(
-                glyph.width as f32 / tex_width,
-                glyph.height as f32 / tex_height,
+/// Calls `f` with the position and uv rects of each visible glyph in `text`,
+/// laid out from the origin in font units. Each line is `line_height` below
+/// the last, aligned on x = 0.
+fn layout_glyphs(
+    font: &Font,
+    text: &str,
+    alignment: Alignment,
+    line_height: f32,
+    mut f: impl FnMut(Vec2, Vec2, Vec2, Vec2),
+) {
+    let tex_width = font.texture.width() as f32;
+    let tex_height = font.texture.height() as f32;
+
+    // A trailing newline leaves an empty last line, which draws nothing
+    for (row, line) in text.split('\n').enumerate() {
+        let mut cursor_x = match alignment {
+            Alignment::Left => 0.0,
+            Alignment::Center => -font.line_width(line) / 2.0,
+            Alignment::Right => -font.line_width(line),
+        };
+        let cursor_y = row as f32 * line_height;
+
+        for c in line.chars() {
+            let glyph = font.glyph_or_unknown(c);
+
+            if glyph.width == 0 || glyph.height == 0 {
+                cursor_x += glyph.xadvance as f32;
+                continue;
+            }
+
+            let min_uv = glam::vec2(glyph.x as f32 / tex_width, glyph.y as f32 / tex_height);
+            let max_uv = min_uv
+                + glam::vec2(
+                    glyph.width as f32 / tex_width,
+                    glyph.height as f32 / tex_height,
+                );
+
+            let p1 = glam::vec2(
+                cursor_x + glyph.xoffset as f32,
+                cursor_y + glyph.yoffset as f32,
             );
+            let p2 = p1 + glam::vec2(glyph.width as f32, glyph.height as f32);
 
-        let p1 = glam::vec2(
-            cursor_x + glyph.xoffset as f32 + 20.0,
-            cursor_y + glyph.yoffset as f32 + 20.0,
-        );
-        let p2 = p1 + glam::vec2(glyph.width as f32, glyph.height as f32);
+            f(p1, p2, min_uv, max_uv);
 
-        verts.extend_from_slice(&[
-            TexturedVertex {
-                position: glam::vec2(p1.x, p1.y),
-                uv: glam::vec2(min_uv.x, min_uv.y),
-            },
-            TexturedVertex {
-                position: glam::vec2(p2.x, p1.y),
-                uv: glam::vec2(max_uv.x, min_uv.y),
-            },
-            TexturedVertex {
-                position: glam::vec2(p2.x, p2.y),
-                uv: glam::vec2(max_uv.x, max_uv.y),
-            },
-            TexturedVertex {
-                position: glam::vec2(p1.x, p2.y),
-                uv: glam::vec2(min_uv.x, max_uv.y),
-            },
-        ]);
+            cursor_x += glyph.xadvance as f32;
+        }
+    }
+}
 
-        indices.extend_from_slice(&[i, i + 1, i + 2, i, i + 2, i + 3]);
+/// Where text starts unless told otherwise.
+const TEXT_ORIGIN: glam::Vec2 = glam::Vec2::splat(20.0);
 
-        cursor_x += glyph.xadvance as f32;
-        i += 4;
-    }
+fn generate_text_data(
+    font: &Font,
+    text: &str,
+    layout: &TextLayout,
+) -> (Vec<TexturedVertex>, Vec<u32>) {
+    let mut i = 0u32;
+    let mut verts = Vec::new();
+    let mut indices = Vec::new();
+    let line_height = layout.line_height_for(font);
+    layout_glyphs(
+        font,
+        text,
+        layout.alignment,
+        line_height,
+        |p1, p2, min_uv, max_uv| {
+            let p1 = p1 + layout.origin;
+            let p2 = p2 + layout.origin;
+            verts.extend_from_slice(&[
+                TexturedVertex {
+                    position: glam::vec2(p1.x, p1.y),
+                    uv: glam::vec2(min_uv.x, min_uv.y),
+                },
+                TexturedVertex {
+                    position: glam::vec2(p2.x, p1.y),
+                    uv: glam::vec2(max_uv.x, min_uv.y),
+                },
+                TexturedVertex {
+                    position: glam::vec2(p2.x, p2.y),
+                    uv: glam::vec2(max_uv.x, max_uv.y),
+                },
+                TexturedVertex {
+                    position: glam::vec2(p1.x, p2.y),
+                    uv: glam::vec2(min_uv.x, max_uv.y),
+                },
+            ]);
+
+            indices.extend_from_slice(&[i, i + 1, i + 2, i, i + 2, i + 3]);
+            i += 4;
+        },
+    );
     (verts, indices)
 }
 
+fn generate_label_data(font: &Font, labels: &[Label]) -> Vec<GlyphInstance> {
+    let mut glyphs = Vec::new();
+    for label in labels {
+        let line_height = font.info.common.line_height as f32;
+        layout_glyphs(
+            font,
+            label.text,
+            Alignment::Left,
+            line_height,
+            |p1, p2, min_uv, max_uv| {
+                glyphs.push(GlyphInstance {
+                    rect: glam::vec4(p1.x, p1.y, p2.x, p2.y),
+                    uv_rect: glam::vec4(min_uv.x, min_uv.y, max_uv.x, max_uv.y),
+                    origin: label.position,
+                    scale: label.scale,
+                });
+            },
+        );
+    }
+    glyphs
+}
+
+/// What text is drawn in unless set otherwise.
+pub const TEXT_COLOR: glam::Vec4 = glam::Vec4::ONE;
+
+/// A uniform buffer holding `color` and a bind group for it.
+fn bind_color(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    color: glam::Vec4,
+) -> (wgpu::Buffer, wgpu::BindGroup) {
+    let buffer = device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("TextPipeline::color_buffer"),
+        contents: bytemuck::bytes_of(&color),
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("TextPipeline::color_bg"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+    });
+    (buffer, bind_group)
+}
+
 pub struct TextBuffer {
     // todo: font: FontId,
     num_indices: u32,
     indices: wgpu::Buffer,
     vertices: wgpu::Buffer,
+    color: glam::Vec4,
+    color_buffer: wgpu::Buffer,
+    color_bg: wgpu::BindGroup,
+}
+
+impl TextBuffer {
+    pub fn color(&self) -> glam::Vec4 {
+        self.color
+    }
+
+    /// Tints the text, e.g. red for a warning. The glyphs' coverage is
+    /// multiplied by `color`, so its alpha fades the whole text. White by
+    /// default.
+    pub fn set_color(&mut self, queue: &wgpu::Queue, color: glam::Vec4) {
+        if color == self.color {
+            return;
+        }
+        queue.write_buffer(&self.color_buffer, 0, bytemuck::bytes_of(&color));
+        self.color = color;
+    }
 }
 
 pub struct Font {
@@ -391,6 +684,29 @@ impl Font {
     pub fn unknown_glyph(&self) -> &Glyph {
         self.glyph(self.unknown_char).unwrap()
     }
+
+    fn glyph_or_unknown(&self, c: char) -> &Glyph {
+        self.glyph(c).unwrap_or_else(|| self.unknown_glyph())
+    }
+
+    /// How far the pen moves drawing `line`, in font units. Newlines aren't
+    /// treated specially, so pass a single line.
+    pub fn line_width(&self, line: &str) -> f32 {
+        line.chars()
+            .map(|c| self.glyph_or_unknown(c).xadvance as f32)
+            .sum()
+    }
+
+    /// The top left and bottom right corners of each visible glyph in `text`,
+    /// where [`TextPipeline`] would draw them with `layout`.
+    pub fn glyph_rects(&self, text: &str, layout: &TextLayout) -> Vec<(Vec2, Vec2)> {
+        let mut rects = Vec::new();
+        let line_height = layout.line_height_for(self);
+        layout_glyphs(self, text, layout.alignment, line_height, |p1, p2, _, _| {
+            rects.push((p1 + layout.origin, p2 + layout.origin));
+        });
+        rects
+    }
 }
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
@@ -0,0 +1,184 @@
+//! A reference grid on a horizontal plane, drawn procedurally.
+
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use crate::resources::camera::{CameraBinder, CameraBinding};
+
+/// Pulls the grid towards the camera so it shows on top of a surface at the
+/// same height, like the ground.
+const GRID_DEPTH_BIAS: wgpu::DepthBiasState = wgpu::DepthBiasState {
+    constant: -2,
+    slope_scale: -1.0,
+    clamp: 0.0,
+};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GridParams {
+    color: glam::Vec4,
+    spacing: f32,
+    fade_distance: f32,
+    height: f32,
+    _padding: f32,
+}
+
+/// Lines every [`Self::spacing`] units on the horizontal plane at
+/// [`Self::height`], fading out [`Self::fade_distance`] from the camera.
+/// Only a square under the camera reaching that far is drawn, so the grid
+/// seems to go on forever. It's blended over whatever is already drawn and
+/// tested against depth without writing it, so draw it after the scene in
+/// the same pass.
+pub struct GridPipeline {
+    pipeline: wgpu::RenderPipeline,
+    params_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    params: GridParams,
+}
+
+impl GridPipeline {
+    pub const DEFAULT_SPACING: f32 = 0.25;
+    pub const DEFAULT_FADE_DISTANCE: f32 = 10.0;
+    pub const DEFAULT_COLOR: glam::Vec4 = glam::vec4(0.5, 0.5, 0.5, 0.5);
+
+    pub fn new(
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        camera_binder: &CameraBinder,
+        sample_count: u32,
+    ) -> Self {
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GridPipeline::params_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GridPipeline"),
+            bind_group_layouts: &[camera_binder.layout(), &params_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("grid.wgsl"));
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("GridPipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            primitive: Default::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: Default::default(),
+                bias: GRID_DEPTH_BIAS,
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: Default::default(),
+            cache: None,
+        });
+
+        let params = GridParams {
+            color: Self::DEFAULT_COLOR,
+            spacing: Self::DEFAULT_SPACING,
+            fade_distance: Self::DEFAULT_FADE_DISTANCE,
+            height: 0.0,
+            _padding: 0.0,
+        };
+        let params_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("GridPipeline::params_buffer"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("GridPipeline::bind_group"),
+            layout: &params_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params_buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            pipeline,
+            params_buffer,
+            bind_group,
+            params,
+        }
+    }
+
+    pub fn spacing(&self) -> f32 {
+        self.params.spacing
+    }
+
+    /// Sets how far apart lines are, which has to be more than zero.
+    pub fn set_spacing(&mut self, spacing: f32) {
+        self.params.spacing = spacing.max(f32::EPSILON);
+    }
+
+    pub fn fade_distance(&self) -> f32 {
+        self.params.fade_distance
+    }
+
+    /// Sets how far from the camera the grid fades out, which is also as far
+    /// as it's drawn.
+    pub fn set_fade_distance(&mut self, fade_distance: f32) {
+        self.params.fade_distance = fade_distance.max(0.0);
+    }
+
+    pub fn height(&self) -> f32 {
+        self.params.height
+    }
+
+    /// Moves the grid up or down from y = 0, where it starts.
+    pub fn set_height(&mut self, height: f32) {
+        self.params.height = height;
+    }
+
+    pub fn color(&self) -> glam::Vec4 {
+        self.params.color
+    }
+
+    /// The color of the lines, with `a` as their opacity.
+    pub fn set_color(&mut self, color: glam::Vec4) {
+        self.params.color = color;
+    }
+
+    /// Uploads anything changed with the setters.
+    pub fn update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&self.params));
+    }
+
+    pub fn draw(&self, pass: &mut wgpu::RenderPass<'_>, camera: &CameraBinding) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, camera.bind_group(), &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.draw(0..6, 0..1);
+    }
+}
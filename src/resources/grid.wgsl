@@ -0,0 +1,56 @@
+// Procedural grid on a horizontal plane, see `GridPipeline`
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// Mirrors `GridParams` in grid.rs
+struct GridParams {
+    color: vec4<f32>,
+    spacing: f32,
+    fade_distance: f32,
+    height: f32,
+}
+@group(1) @binding(0)
+var<uniform> params: GridParams;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) camera_position: vec3<f32>,
+}
+
+// Draw with 6 vertices, a square under the camera reaching as far as the
+// grid fades out
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> VertexOutput {
+    var corners = array(vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0));
+    var order = array(0u, 1u, 2u, 0u, 2u, 3u);
+    let corner = corners[order[i]] * params.fade_distance;
+    let world_position = vec3(
+        camera.view_pos.x + corner.x,
+        params.height,
+        camera.view_pos.z + corner.y,
+    );
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4(world_position, 1.0);
+    out.world_position = world_position;
+    out.camera_position = camera.view_pos.xyz;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coord = in.world_position.xz / params.spacing;
+    // Distance to the closest line in pixels, so lines stay about a pixel
+    // wide however far away they are
+    let to_line = abs(fract(coord - 0.5) - 0.5) / fwidth(coord);
+    let line = 1.0 - min(min(to_line.x, to_line.y), 1.0);
+    let distance = length(in.world_position - in.camera_position);
+    let fade = saturate(1.0 - distance / params.fade_distance);
+    return vec4(params.color.rgb, params.color.a * line * fade);
+}
@@ -1,24 +1,233 @@
-use crate::resources::buffer::BackedBuffer;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
 
+use super::texture::Texture;
+
+/// How many lights fit in the uniform array used when storage buffers
+/// aren't available. Must match the array size in the fallback shader.
+pub const MAX_UNIFORM_LIGHTS: usize = 16;
+
+/// Width and height of a [`LightBinding`]'s shadow map in texels.
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+pub const SHADOW_MAP_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// A point light as read by normal_mapped.wgsl.
+///
+/// The layout has to match the shader's `Light` exactly, or every light
+/// after the first reads garbage without any validation error:
+///
+/// | offset | field                  |
+/// |--------|------------------------|
+/// | 0      | `position` (xyz used)  |
+/// | 16     | `color` (rgb used)     |
+/// | 32     | `ambient` (rgb used)   |
+/// | 48     | `range`                |
+/// | 52     | `intensity`            |
+/// | 56     | padding to 64 bytes    |
+///
+/// 64 bytes keeps the array stride a multiple of 16, which uniform arrays
+/// require.
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 #[repr(C)]
 pub struct LightUniform {
     pub position: glam::Vec4,
     pub color: glam::Vec4,
+    /// Added regardless of distance or facing, so unlit sides aren't black.
+    pub ambient: glam::Vec4,
+    /// Distance the light fades out to nothing by. Zero or less means it
+    /// reaches forever.
+    pub range: f32,
+    /// Brightness at a distance of one. Falls off with `1 / d^2`.
+    pub intensity: f32,
+    _padding: [f32; 2],
+}
+
+impl LightUniform {
+    /// How much of its color a light adds as ambient by default.
+    pub const DEFAULT_AMBIENT: f32 = 0.1;
+
+    /// A light reaching forever with an intensity of one.
+    pub fn new(position: glam::Vec3, color: glam::Vec3) -> Self {
+        Self {
+            position: position.extend(1.0),
+            color: color.extend(1.0),
+            ambient: (color * Self::DEFAULT_AMBIENT).extend(1.0),
+            range: 0.0,
+            intensity: 1.0,
+            _padding: [0.0; 2],
+        }
+    }
+
+    pub fn with_ambient(mut self, ambient: glam::Vec3) -> Self {
+        self.ambient = ambient.extend(1.0);
+        self
+    }
+
+    pub fn with_range(mut self, range: f32) -> Self {
+        self.range = range;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+}
+
+/// A light so far away it shines the same way everywhere, like the sun, as
+/// read by normal_mapped.wgsl. There's one per [`LightBinding`], shining
+/// alongside its point lights.
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct DirectionalLight {
+    /// Unit vector pointing towards the light. `w` is unused.
+    pub direction: glam::Vec4,
+    /// Black turns the light off. `a` is unused.
+    pub color: glam::Vec4,
 }
 
+impl DirectionalLight {
+    pub const OFF: Self = Self {
+        direction: glam::Vec4::Y,
+        color: glam::Vec4::ZERO,
+    };
+
+    /// A light shining from `direction`, which doesn't need to be normalized.
+    /// Straight down if it's zero.
+    pub fn new(direction: glam::Vec3, color: glam::Vec3) -> Self {
+        Self {
+            direction: direction
+                .try_normalize()
+                .unwrap_or(glam::Vec3::Y)
+                .extend(0.0),
+            color: color.extend(1.0),
+        }
+    }
+
+    /// An orthographic projection looking along the light that covers the
+    /// sphere of `radius` around `center`, for rendering shadow maps.
+    pub fn view_proj(&self, center: glam::Vec3, radius: f32) -> glam::Mat4 {
+        let direction = self.direction.truncate();
+        // Any up works as long as it isn't parallel to the light
+        let up = if direction.y.abs() > 0.99 {
+            glam::Vec3::Z
+        } else {
+            glam::Vec3::Y
+        };
+        let view = glam::Mat4::look_at_rh(center + direction * radius * 2.0, center, up);
+        let proj = glam::Mat4::orthographic_rh(-radius, radius, -radius, radius, 0.0, radius * 4.0);
+        proj * view
+    }
+}
+
+/// Points from the ground towards the sun at `sun_angle` radians above the
+/// horizon, as in [`crate::simulation::Environment::sun_angle`]. The sun
+/// rises towards +x, is straight up at a quarter turn and sets towards -x.
+pub fn sun_direction(sun_angle: f32) -> glam::Vec3 {
+    let (sin, cos) = sun_angle.sin_cos();
+    glam::vec3(cos, sin, 0.0)
+}
+
+/// The light count, padded out to the 16 bytes uniforms need.
+fn count_uniform(count: usize) -> [u32; 4] {
+    [count as u32, 0, 0, 0]
+}
+
+/// Binds a list of [`LightUniform`]s and how many of them are in use.
+///
+/// Lights are read from a storage buffer when the device supports them.
+/// WebGL-class backends have no storage buffers, so there they go in a
+/// uniform array of [`MAX_UNIFORM_LIGHTS`] instead. Lights are only read in
+/// the fragment stage either way. The [`DirectionalLight`] is always a
+/// uniform, and casts shadows using the shadow map bound after it.
 pub struct LightBinder {
     layout: wgpu::BindGroupLayout,
+    shadow_layout: wgpu::BindGroupLayout,
+    lights_binding_type: wgpu::BufferBindingType,
 }
 
 impl LightBinder {
     pub fn new(device: &wgpu::Device) -> Self {
+        Self::with_storage(
+            device,
+            device.limits().max_storage_buffers_per_shader_stage > 0,
+        )
+    }
+
+    /// Like [`Self::new`] but picks between a storage buffer and the uniform
+    /// fallback explicitly.
+    pub fn with_storage(device: &wgpu::Device, storage: bool) -> Self {
+        let lights_binding_type = if storage {
+            wgpu::BufferBindingType::Storage { read_only: true }
+        } else {
+            wgpu::BufferBindingType::Uniform
+        };
         Self {
             layout: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("LightBinder::layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: lights_binding_type,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        count: None,
+                    },
+                ],
+            }),
+            shadow_layout: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("LightBinder::shadow_layout"),
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    visibility: wgpu::ShaderStages::VERTEX,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -27,37 +236,293 @@ impl LightBinder {
                     count: None,
                 }],
             }),
+            lights_binding_type,
         }
     }
 
-    pub fn bind(&self, device: &wgpu::Device, buffer: &BackedBuffer<LightUniform>) -> LightBinding {
-        assert_ne!(
-            buffer.buffer().usage() & wgpu::BufferUsages::UNIFORM,
-            wgpu::BufferUsages::empty()
+    /// The binding type of the light list, binding 0 in the layout.
+    pub fn lights_binding_type(&self) -> wgpu::BufferBindingType {
+        self.lights_binding_type
+    }
+
+    pub fn uses_storage(&self) -> bool {
+        matches!(
+            self.lights_binding_type,
+            wgpu::BufferBindingType::Storage { .. }
+        )
+    }
+
+    /// Uploads `lights` and binds them. With the uniform fallback only the
+    /// first [`MAX_UNIFORM_LIGHTS`] are used. The directional light starts
+    /// off, see [`LightBinding::set_sun`], and so do its shadows, see
+    /// [`LightBinding::set_shadow_view_proj`].
+    pub fn bind(&self, device: &wgpu::Device, lights: &[LightUniform]) -> LightBinding {
+        let lights = self.clamp_lights(lights);
+        let capacity = if self.uses_storage() {
+            // Bindings can't be empty, so there's always room for one light
+            lights.len().max(1)
+        } else {
+            MAX_UNIFORM_LIGHTS
+        };
+
+        let mut contents = lights.to_vec();
+        contents.resize(capacity, bytemuck::Zeroable::zeroed());
+        let usage = wgpu::BufferUsages::COPY_DST
+            | if self.uses_storage() {
+                wgpu::BufferUsages::STORAGE
+            } else {
+                wgpu::BufferUsages::UNIFORM
+            };
+        let lights_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("LightBinding::lights_buffer"),
+            contents: bytemuck::cast_slice(&contents),
+            usage,
+        });
+        let count_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("LightBinding::count_buffer"),
+            contents: bytemuck::cast_slice(&count_uniform(lights.len())),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        });
+        let sun_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("LightBinding::sun_buffer"),
+            contents: bytemuck::bytes_of(&DirectionalLight::OFF),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        });
+        let shadow_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("LightBinding::shadow_buffer"),
+            contents: bytemuck::bytes_of(&glam::Mat4::ZERO),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        });
+        let shadow_map = Texture::from_descriptor(
+            device,
+            &wgpu::TextureDescriptor {
+                label: Some("LightBinding::shadow_map"),
+                size: wgpu::Extent3d {
+                    width: SHADOW_MAP_SIZE,
+                    height: SHADOW_MAP_SIZE,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: SHADOW_MAP_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            Default::default(),
         );
+
         LightBinding {
             bind_group: device.create_bind_group(&wgpu::BindGroupDescriptor {
                 label: Some("LightBinding"),
                 layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: lights_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: count_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: sun_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: shadow_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(&shadow_map.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: wgpu::BindingResource::Sampler(&shadow_map.sampler),
+                    },
+                ],
+            }),
+            shadow_bind_group: device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("LightBinding::shadow_bind_group"),
+                layout: &self.shadow_layout,
                 entries: &[wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: buffer.buffer().as_entire_binding(),
+                    resource: shadow_buffer.as_entire_binding(),
                 }],
             }),
+            lights_buffer,
+            count_buffer,
+            sun_buffer,
+            shadow_buffer,
+            shadow_map,
+            capacity,
+            count: lights.len(),
+            sun: DirectionalLight::OFF,
+            shadow_view_proj: glam::Mat4::ZERO,
         }
     }
-    
+
+    /// Replaces the lights in `binding`, only rebinding if they don't fit.
+    pub fn update(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        binding: &mut LightBinding,
+        lights: &[LightUniform],
+    ) {
+        let lights = self.clamp_lights(lights);
+        if lights.len() > binding.capacity {
+            let (sun, shadow_view_proj) = (binding.sun, binding.shadow_view_proj);
+            *binding = self.bind(device, lights);
+            binding.set_sun(queue, sun);
+            binding.set_shadow_view_proj(queue, shadow_view_proj);
+            return;
+        }
+
+        queue.write_buffer(&binding.lights_buffer, 0, bytemuck::cast_slice(lights));
+        queue.write_buffer(
+            &binding.count_buffer,
+            0,
+            bytemuck::cast_slice(&count_uniform(lights.len())),
+        );
+        binding.count = lights.len();
+    }
+
     pub(crate) fn layout(&self) -> &wgpu::BindGroupLayout {
         &self.layout
     }
+
+    /// Just the shadow view-proj, for rendering the shadow map.
+    pub(crate) fn shadow_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.shadow_layout
+    }
+
+    fn clamp_lights<'a>(&self, lights: &'a [LightUniform]) -> &'a [LightUniform] {
+        if self.uses_storage() || lights.len() <= MAX_UNIFORM_LIGHTS {
+            return lights;
+        }
+        log::warn!(
+            "Only the first {MAX_UNIFORM_LIGHTS} of {} lights are used without storage buffers",
+            lights.len()
+        );
+        &lights[..MAX_UNIFORM_LIGHTS]
+    }
 }
 
 pub struct LightBinding {
     bind_group: wgpu::BindGroup,
+    shadow_bind_group: wgpu::BindGroup,
+    lights_buffer: wgpu::Buffer,
+    count_buffer: wgpu::Buffer,
+    sun_buffer: wgpu::Buffer,
+    shadow_buffer: wgpu::Buffer,
+    /// Depth of the scene as seen by the directional light, drawn with
+    /// [`super::shadow::ShadowPipeline`].
+    shadow_map: Texture,
+    capacity: usize,
+    count: usize,
+    sun: DirectionalLight,
+    shadow_view_proj: glam::Mat4,
 }
 
 impl LightBinding {
     pub fn bind_group(&self) -> &wgpu::BindGroup {
         &self.bind_group
     }
+
+    /// How many lights the shader loops over.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn sun(&self) -> DirectionalLight {
+        self.sun
+    }
+
+    /// Replaces the directional light. Cheap to call every frame, as nothing
+    /// is uploaded unless it changed.
+    pub fn set_sun(&mut self, queue: &wgpu::Queue, sun: DirectionalLight) {
+        if sun == self.sun {
+            return;
+        }
+        queue.write_buffer(&self.sun_buffer, 0, bytemuck::bytes_of(&sun));
+        self.sun = sun;
+    }
+
+    pub fn shadow_view_proj(&self) -> glam::Mat4 {
+        self.shadow_view_proj
+    }
+
+    /// Sets where the shadow map is rendered from, usually
+    /// [`DirectionalLight::view_proj`] of the sun. Zero turns shadows off.
+    /// Like [`Self::set_sun`], nothing is uploaded unless it changed.
+    pub fn set_shadow_view_proj(&mut self, queue: &wgpu::Queue, view_proj: glam::Mat4) {
+        if view_proj == self.shadow_view_proj {
+            return;
+        }
+        queue.write_buffer(&self.shadow_buffer, 0, bytemuck::bytes_of(&view_proj));
+        self.shadow_view_proj = view_proj;
+    }
+
+    pub fn shadow_map(&self) -> &Texture {
+        &self.shadow_map
+    }
+
+    pub(crate) fn shadow_bind_group(&self) -> &wgpu::BindGroup {
+        &self.shadow_bind_group
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_light_uniform_layout() {
+        assert_eq!(size_of::<LightUniform>(), 64);
+        assert_eq!(std::mem::offset_of!(LightUniform, color), 16);
+        assert_eq!(std::mem::offset_of!(LightUniform, ambient), 32);
+        assert_eq!(std::mem::offset_of!(LightUniform, range), 48);
+        assert_eq!(std::mem::offset_of!(LightUniform, intensity), 52);
+        assert_eq!(size_of::<DirectionalLight>(), 32);
+    }
+
+    #[test]
+    fn test_sun_direction() {
+        let noon = sun_direction(std::f32::consts::FRAC_PI_2);
+        assert!(noon.abs_diff_eq(glam::Vec3::Y, 1e-6));
+        let sunrise = sun_direction(0.0);
+        assert!(sunrise.abs_diff_eq(glam::Vec3::X, 1e-6));
+        let sunset = sun_direction(std::f32::consts::PI);
+        assert!(sunset.abs_diff_eq(-glam::Vec3::X, 1e-6));
+        assert!((sun_direction(1.0).length() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_shadow_view_proj() {
+        let center = glam::vec3(1.0, 0.0, 0.0);
+        let radius = 2.0;
+        let project = |sun: &DirectionalLight, point: glam::Vec3| {
+            sun.view_proj(center, radius).project_point3(point)
+        };
+        for direction in [glam::Vec3::Y, glam::vec3(1.0, 1.0, 0.0), -glam::Vec3::X] {
+            let sun = DirectionalLight::new(direction, glam::Vec3::ONE);
+            let direction = sun.direction.truncate();
+
+            // The covered sphere ends up inside the depth range
+            let middle = project(&sun, center);
+            assert!(middle.truncate().abs_diff_eq(glam::Vec2::ZERO, 1e-5));
+            assert!((0.0..=1.0).contains(&middle.z));
+            let near = project(&sun, center + direction * radius);
+            let far = project(&sun, center - direction * radius);
+            assert!(near.z >= 0.0 && near.z < middle.z);
+            assert!(far.z <= 1.0 && far.z > middle.z);
+        }
+    }
 }
@@ -1,22 +1,56 @@
 use std::{
+    cell::RefCell,
+    collections::HashMap,
     fs,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
 };
 
+use anyhow::Context;
+
 pub mod buffer;
 pub mod camera;
 pub mod font;
+pub mod grid;
 pub mod model;
 pub mod texture;
 pub mod vertex;
 pub mod light;
+pub mod shadow;
 
+#[cfg(target_arch = "wasm32")]
+mod fetch;
+#[cfg(target_arch = "wasm32")]
+pub use fetch::FetchResources;
 
+/// Loads assets right away. The asset loaders take this.
 pub trait Resources {
     fn load_string(&self, path: impl AsRef<Path>) -> anyhow::Result<String>;
     fn load_binary(&self, path: impl AsRef<Path>) -> anyhow::Result<Vec<u8>>;
 }
 
+/// Loads assets in the background, which is the only way a browser can.
+/// Every [`Resources`] is one too.
+///
+/// Use [`MemoryResources::preload`] to get the files an asset loader needs
+/// ahead of time.
+// The futures can't be Send on wasm anyway, since they hold JS values
+#[allow(async_fn_in_trait)]
+pub trait AsyncResources {
+    async fn load_string(&self, path: impl AsRef<Path>) -> anyhow::Result<String>;
+    async fn load_binary(&self, path: impl AsRef<Path>) -> anyhow::Result<Vec<u8>>;
+}
+
+impl<R: Resources> AsyncResources for R {
+    async fn load_string(&self, path: impl AsRef<Path>) -> anyhow::Result<String> {
+        Resources::load_string(self, path)
+    }
+
+    async fn load_binary(&self, path: impl AsRef<Path>) -> anyhow::Result<Vec<u8>> {
+        Resources::load_binary(self, path)
+    }
+}
+
 pub struct FsResources {
     base_dir: PathBuf,
 }
@@ -31,16 +65,323 @@ impl FsResources {
 
 impl Resources for FsResources {
     fn load_binary(&self, path: impl AsRef<Path>) -> anyhow::Result<Vec<u8>> {
-        // TODO: WASM
         let full_path = self.base_dir.join(path);
         log::info!("load_binary({})", full_path.display());
         Ok(fs::read(full_path)?)
     }
 
     fn load_string(&self, path: impl AsRef<Path>) -> anyhow::Result<String> {
-        // TODO: WASM
         let full_path = self.base_dir.join(path);
         log::info!("load_string({})", full_path.display());
         Ok(fs::read_to_string(full_path)?)
     }
 }
+
+/// `path` appended to `base` with forward slashes, whatever the platform.
+fn join_url(base: &str, path: &Path) -> String {
+    let path = path
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    match base.trim_end_matches('/') {
+        "" => path,
+        base => format!("{base}/{path}"),
+    }
+}
+
+/// Remembers what `R` loaded so loading it again doesn't go back to `R`.
+/// Once more than the byte budget is cached, the least recently loaded files
+/// are dropped first. Paths are normalized, so `./a/b` and `a/b` are the same
+/// file.
+pub struct CachingResources<R> {
+    inner: R,
+    budget: usize,
+    cache: RefCell<ResourceCache>,
+}
+
+#[derive(Default)]
+struct ResourceCache {
+    files: HashMap<String, CachedFile>,
+    bytes: usize,
+    clock: u64,
+}
+
+struct CachedFile {
+    bytes: Rc<[u8]>,
+    last_used: u64,
+}
+
+impl<R: Resources> CachingResources<R> {
+    pub const DEFAULT_BUDGET: usize = 64 * 1024 * 1024;
+
+    /// Caches up to `budget` bytes. Files bigger than that aren't cached.
+    pub fn new(inner: R, budget: usize) -> Self {
+        Self {
+            inner,
+            budget,
+            cache: Default::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    /// How many bytes are cached right now.
+    pub fn cached_bytes(&self) -> usize {
+        self.cache.borrow().bytes
+    }
+
+    pub fn clear(&self) {
+        *self.cache.borrow_mut() = Default::default();
+    }
+
+    fn load(&self, path: &Path) -> anyhow::Result<Rc<[u8]>> {
+        let key = join_url("", path);
+        let mut cache = self.cache.borrow_mut();
+        cache.clock += 1;
+        let now = cache.clock;
+        if let Some(file) = cache.files.get_mut(&key) {
+            file.last_used = now;
+            return Ok(file.bytes.clone());
+        }
+
+        let bytes = Rc::<[u8]>::from(self.inner.load_binary(path)?);
+        if bytes.len() > self.budget {
+            return Ok(bytes);
+        }
+        while cache.bytes + bytes.len() > self.budget {
+            let Some(oldest) = cache
+                .files
+                .iter()
+                .min_by_key(|(_, file)| file.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some(file) = cache.files.remove(&oldest) {
+                cache.bytes -= file.bytes.len();
+            }
+        }
+        cache.bytes += bytes.len();
+        cache.files.insert(
+            key,
+            CachedFile {
+                bytes: bytes.clone(),
+                last_used: now,
+            },
+        );
+        Ok(bytes)
+    }
+}
+
+impl<R: Resources> Resources for CachingResources<R> {
+    fn load_binary(&self, path: impl AsRef<Path>) -> anyhow::Result<Vec<u8>> {
+        Ok(self.load(path.as_ref())?.to_vec())
+    }
+
+    fn load_string(&self, path: impl AsRef<Path>) -> anyhow::Result<String> {
+        Ok(String::from_utf8(self.load(path.as_ref())?.to_vec())?)
+    }
+}
+
+/// Files compiled into the binary, so nothing has to ship next to it. Build
+/// one with [`embed_resources!`](crate::embed_resources).
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddedResources {
+    files: &'static [(&'static str, &'static [u8])],
+}
+
+impl EmbeddedResources {
+    /// `files` pairs paths using forward slashes with their contents.
+    pub const fn new(files: &'static [(&'static str, &'static [u8])]) -> Self {
+        Self { files }
+    }
+}
+
+impl Resources for EmbeddedResources {
+    fn load_binary(&self, path: impl AsRef<Path>) -> anyhow::Result<Vec<u8>> {
+        let path = join_url("", path.as_ref());
+        self.files
+            .iter()
+            .find(|(file, _)| *file == path)
+            .map(|(_, bytes)| bytes.to_vec())
+            .with_context(|| format!("{path} wasn't embedded"))
+    }
+
+    fn load_string(&self, path: impl AsRef<Path>) -> anyhow::Result<String> {
+        Ok(String::from_utf8(Resources::load_binary(self, path)?)?)
+    }
+}
+
+/// Embeds files from `$dir`, relative to the crate root, as
+/// [`EmbeddedResources`](crate::resources::EmbeddedResources).
+///
+/// ```
+/// use passive_logic_challenge::{embed_resources, resources::Resources};
+///
+/// let res = embed_resources!("res", ["models/connection.obj", "models/connection.mtl"]);
+/// assert!(res.load_string("models/connection.obj").is_ok());
+/// ```
+#[macro_export]
+macro_rules! embed_resources {
+    ($dir:literal, [$($path:literal),* $(,)?]) => {
+        $crate::resources::EmbeddedResources::new(&[$((
+            $path,
+            include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $dir, "/", $path)),
+        )),*])
+    };
+}
+
+/// Files held in memory, keyed by the path they're loaded with.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryResources {
+    files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryResources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads every file in `paths` from `res`, so they can be loaded again
+    /// right away.
+    pub async fn preload(
+        res: &impl AsyncResources,
+        paths: impl IntoIterator<Item = impl AsRef<Path>>,
+    ) -> anyhow::Result<Self> {
+        let mut resources = Self::new();
+        for path in paths {
+            let path = path.as_ref();
+            let bytes = res
+                .load_binary(path)
+                .await
+                .with_context(|| format!("Failed to preload {}", path.display()))?;
+            resources.insert(path, bytes);
+        }
+        Ok(resources)
+    }
+
+    pub fn insert(&mut self, path: impl AsRef<Path>, bytes: Vec<u8>) {
+        self.files.insert(path.as_ref().to_owned(), bytes);
+    }
+}
+
+impl Resources for MemoryResources {
+    fn load_binary(&self, path: impl AsRef<Path>) -> anyhow::Result<Vec<u8>> {
+        let path = path.as_ref();
+        self.files
+            .get(path)
+            .cloned()
+            .with_context(|| format!("{} wasn't loaded", path.display()))
+    }
+
+    fn load_string(&self, path: impl AsRef<Path>) -> anyhow::Result<String> {
+        Ok(String::from_utf8(Resources::load_binary(self, path)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_url() {
+        let path = Path::new("fonts/OpenSans MSDF.zip");
+        assert_eq!(join_url("res", path), "res/fonts/OpenSans MSDF.zip");
+        assert_eq!(
+            join_url("https://example.com/res/", path),
+            "https://example.com/res/fonts/OpenSans MSDF.zip"
+        );
+        assert_eq!(join_url("", path), "fonts/OpenSans MSDF.zip");
+    }
+
+    /// Counts how often each file is loaded.
+    #[derive(Default)]
+    struct CountingResources {
+        loads: RefCell<HashMap<PathBuf, usize>>,
+    }
+
+    impl CountingResources {
+        fn loads(&self, path: &str) -> usize {
+            self.loads
+                .borrow()
+                .get(Path::new(path))
+                .copied()
+                .unwrap_or(0)
+        }
+    }
+
+    impl Resources for CountingResources {
+        fn load_binary(&self, path: impl AsRef<Path>) -> anyhow::Result<Vec<u8>> {
+            let path = path.as_ref();
+            *self.loads.borrow_mut().entry(path.to_owned()).or_default() += 1;
+            // Each file is ten bytes of its first letter
+            let first = path.to_string_lossy().bytes().next().unwrap_or(b'_');
+            Ok(vec![first; 10])
+        }
+
+        fn load_string(&self, path: impl AsRef<Path>) -> anyhow::Result<String> {
+            Ok(String::from_utf8(Resources::load_binary(self, path)?)?)
+        }
+    }
+
+    #[test]
+    fn test_caching_resources() {
+        let res = CachingResources::new(CountingResources::default(), 25);
+
+        assert_eq!(Resources::load_string(&res, "a").unwrap(), "aaaaaaaaaa");
+        assert_eq!(Resources::load_string(&res, "./a").unwrap(), "aaaaaaaaaa");
+        assert_eq!(Resources::load_binary(&res, "a").unwrap(), vec![b'a'; 10]);
+        assert_eq!(res.inner().loads("a"), 1);
+        assert_eq!(res.cached_bytes(), 10);
+
+        // Only two files fit, so the least recently used one goes
+        Resources::load_string(&res, "b").unwrap();
+        Resources::load_string(&res, "a").unwrap();
+        Resources::load_string(&res, "c").unwrap();
+        assert_eq!(res.cached_bytes(), 20);
+        Resources::load_string(&res, "a").unwrap();
+        Resources::load_string(&res, "b").unwrap();
+        assert_eq!(res.inner().loads("a"), 1);
+        assert_eq!(res.inner().loads("b"), 2);
+
+        res.clear();
+        assert_eq!(res.cached_bytes(), 0);
+        Resources::load_string(&res, "a").unwrap();
+        assert_eq!(res.inner().loads("a"), 2);
+    }
+
+    #[test]
+    fn test_embedded_resources() {
+        let res = crate::embed_resources!("res", ["models/connection.mtl"]);
+        let expected = concat!(env!("CARGO_MANIFEST_DIR"), "/res/models/connection.mtl");
+
+        assert_eq!(
+            Resources::load_string(&res, "models/connection.mtl").unwrap(),
+            fs::read_to_string(expected).unwrap(),
+        );
+        assert!(Resources::load_string(&res, "models/connection.obj").is_err());
+    }
+
+    #[test]
+    fn test_memory_resources_preload() {
+        let res = FsResources::new(concat!(env!("CARGO_MANIFEST_DIR"), "/res"));
+        let memory = pollster::block_on(MemoryResources::preload(
+            &res,
+            ["models/connection.obj", "models/connection.mtl"],
+        ))
+        .unwrap();
+
+        assert_eq!(
+            Resources::load_string(&memory, "models/connection.obj").unwrap(),
+            Resources::load_string(&res, "models/connection.obj").unwrap(),
+        );
+        assert!(Resources::load_binary(&memory, "models/spherical-cube.obj").is_err());
+
+        let missing = pollster::block_on(MemoryResources::preload(&res, ["models/missing.obj"]));
+        assert!(missing.is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use anyhow::*;
 use std::{
-    io::{BufReader, Cursor},
+    f32::consts::{PI, TAU},
+    io::{BufRead, Cursor},
     path::Path,
 };
 use wgpu::util::DeviceExt;
@@ -8,11 +9,12 @@ use wgpu::util::DeviceExt;
 use crate::resources::{
     buffer,
     camera::{CameraBinder, CameraBinding},
-    light::{LightBinder, LightBinding},
+    light::{LightBinder, LightBinding, MAX_UNIFORM_LIGHTS},
     texture,
     vertex::{InstanceVertex, NormalMappedVertex},
     Resources,
 };
+use crate::utils::WireframePipelines;
 
 pub struct MaterialId(usize);
 
@@ -24,6 +26,42 @@ pub struct Material {
 }
 
 impl Material {
+    /// White with a flat normal map.
+    fn untextured(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        material_binder: &MaterialBinder,
+    ) -> Self {
+        Self::new(
+            device,
+            "default",
+            texture::Texture::from_color(
+                device,
+                queue,
+                1,
+                1,
+                wgpu::Color::WHITE,
+                true,
+                wgpu::TextureUsages::TEXTURE_BINDING,
+            ),
+            texture::Texture::from_color(
+                device,
+                queue,
+                1,
+                1,
+                wgpu::Color {
+                    r: 0.5,
+                    g: 0.5,
+                    b: 1.0,
+                    a: 1.0,
+                },
+                false,
+                wgpu::TextureUsages::TEXTURE_BINDING,
+            ),
+            material_binder,
+        )
+    }
+
     pub fn new(
         device: &wgpu::Device,
         name: &str,
@@ -50,11 +88,280 @@ pub struct Mesh {
     pub material: usize,
 }
 
+impl Mesh {
+    /// Uploads `data` to the GPU.
+    pub fn from_data(device: &wgpu::Device, data: &MeshData) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Vertex Buffer", data.name)),
+            contents: bytemuck::cast_slice(&data.vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", data.name)),
+            contents: bytemuck::cast_slice(&data.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            name: data.name.clone(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: data.indices.len() as u32,
+            material: data.material,
+        }
+    }
+
+    /// See [`MeshData::plane`].
+    pub fn plane(device: &wgpu::Device, size: f32, subdivisions: u32) -> Self {
+        Self::from_data(device, &MeshData::plane(size, subdivisions))
+    }
+
+    /// See [`MeshData::cylinder`].
+    pub fn cylinder(device: &wgpu::Device, radius: f32, height: f32, segments: u32) -> Self {
+        Self::from_data(device, &MeshData::cylinder(radius, height, segments))
+    }
+
+    /// See [`MeshData::uv_sphere`].
+    pub fn uv_sphere(device: &wgpu::Device, radius: f32, sectors: u32, stacks: u32) -> Self {
+        Self::from_data(device, &MeshData::uv_sphere(radius, sectors, stacks))
+    }
+}
+
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
 }
 
+/// CPU side mesh data, ready to be uploaded to the GPU.
+pub struct MeshData {
+    pub name: String,
+    pub vertices: Vec<NormalMappedVertex>,
+    pub indices: Vec<u32>,
+    pub material: usize,
+}
+
+impl MeshData {
+    /// Builds the vertices for a triangulated, single index `tobj` mesh.
+    /// Missing texture coordinates and normals are treated as zero.
+    pub fn from_obj_mesh(name: String, mesh: &tobj::Mesh) -> Result<Self> {
+        let mut vertices = Vec::new();
+        for i in 0..mesh.positions.len() / 3 {
+            let uv = |j| mesh.texcoords.get(i * 2 + j).copied().unwrap_or(0.0);
+            let normal = |j| mesh.normals.get(i * 3 + j).copied().unwrap_or(0.0);
+            vertices.push(NormalMappedVertex {
+                position: glam::vec3(
+                    mesh.positions[i * 3],
+                    mesh.positions[i * 3 + 1],
+                    mesh.positions[i * 3 + 2],
+                ),
+                uv: glam::vec2(uv(0), uv(1)),
+                normal: glam::vec3(normal(0), normal(1), normal(2)),
+                // We'll calculate these later
+                tangent: [0.0; 3].into(),
+                bitangent: [0.0; 3].into(),
+            });
+        }
+
+        let indices = &mesh.indices;
+
+        if !indices.len().is_multiple_of(3) {
+            bail!("{name}: index count {} isn't a multiple of 3", indices.len());
+        }
+        if let Some(i) = indices.iter().find(|&&i| i as usize >= vertices.len()) {
+            bail!("{name}: index {i} out of bounds for {} vertices", vertices.len());
+        }
+
+        // Calculate tangents and bitangets. We're going to
+        // use the triangles, so we need to loop through the
+        // indices in chunks of 3
+        for c in indices.chunks(3) {
+            let v0 = vertices[c[0] as usize];
+            let v1 = vertices[c[1] as usize];
+            let v2 = vertices[c[2] as usize];
+
+            let pos0 = v0.position;
+            let pos1 = v1.position;
+            let pos2 = v2.position;
+
+            let uv0 = v0.uv;
+            let uv1 = v1.uv;
+            let uv2 = v2.uv;
+
+            // Calculate the edges of the triangle
+            let delta_pos1 = pos1 - pos0;
+            let delta_pos2 = pos2 - pos0;
+
+            // This will give us a direction to calculate the
+            // tangent and bitangent
+            let delta_uv1 = uv1 - uv0;
+            let delta_uv2 = uv2 - uv0;
+
+            // Solving the following system of equations will
+            // give us the tangent and bitangent.
+            //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
+            //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
+            // Luckily, the place I found this equation provided
+            // the solution!
+            let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
+            let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+            let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * r;
+
+            // We'll use the same tangent/bitangent for each vertex in the triangle
+            vertices[c[0] as usize].tangent = tangent;
+            vertices[c[1] as usize].tangent = tangent;
+            vertices[c[2] as usize].tangent = tangent;
+
+            vertices[c[0] as usize].bitangent = bitangent;
+            vertices[c[1] as usize].bitangent = bitangent;
+            vertices[c[2] as usize].bitangent = bitangent;
+        }
+
+        Ok(Self {
+            name,
+            vertices,
+            indices: indices.clone(),
+            material: mesh.material_id.unwrap_or(0),
+        })
+    }
+}
+
+impl MeshData {
+    /// A square in the XZ plane facing +Y, centered on the origin and split
+    /// into `subdivisions` quads along each side.
+    pub fn plane(size: f32, subdivisions: u32) -> Self {
+        let n = subdivisions.max(1);
+        let mut vertices = Vec::new();
+        for z in 0..=n {
+            for x in 0..=n {
+                let uv = glam::vec2(x as f32, z as f32) / n as f32;
+                vertices.push(NormalMappedVertex {
+                    position: glam::vec3(uv.x - 0.5, 0.0, uv.y - 0.5) * size,
+                    uv,
+                    normal: glam::Vec3::Y,
+                    tangent: glam::Vec3::X,
+                    bitangent: glam::Vec3::Z,
+                });
+            }
+        }
+
+        let mut indices = Vec::new();
+        for z in 0..n {
+            for x in 0..n {
+                let i = z * (n + 1) + x;
+                let below = i + n + 1;
+                indices.extend([i, below, i + 1, i + 1, below, below + 1]);
+            }
+        }
+
+        Self {
+            name: "plane".to_string(),
+            vertices,
+            indices,
+            material: 0,
+        }
+    }
+
+    /// An open tube along Y centered on the origin, the same orientation as
+    /// the connection model.
+    pub fn cylinder(radius: f32, height: f32, segments: u32) -> Self {
+        let segments = segments.max(3);
+        let mut vertices = Vec::new();
+        for i in 0..=segments {
+            let u = i as f32 / segments as f32;
+            let (sin, cos) = (u * TAU).sin_cos();
+            let normal = glam::vec3(cos, 0.0, sin);
+            for v in [0.0, 1.0] {
+                vertices.push(NormalMappedVertex {
+                    position: normal * radius + glam::Vec3::Y * (v - 0.5) * height,
+                    uv: glam::vec2(u, v),
+                    normal,
+                    tangent: glam::vec3(-sin, 0.0, cos),
+                    bitangent: glam::Vec3::Y,
+                });
+            }
+        }
+
+        let mut indices = Vec::new();
+        for i in 0..segments {
+            let bottom = i * 2;
+            let top = bottom + 1;
+            indices.extend([bottom, top, bottom + 2, bottom + 2, top, top + 2]);
+        }
+
+        Self {
+            name: "cylinder".to_string(),
+            vertices,
+            indices,
+            material: 0,
+        }
+    }
+
+    /// A sphere centered on the origin with `sectors` slices around Y and
+    /// `stacks` bands from pole to pole.
+    pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> Self {
+        let sectors = sectors.max(3);
+        let stacks = stacks.max(2);
+        let mut vertices = Vec::new();
+        for i in 0..=stacks {
+            let v = i as f32 / stacks as f32;
+            let (sin_phi, cos_phi) = (v * PI).sin_cos();
+            for j in 0..=sectors {
+                let u = j as f32 / sectors as f32;
+                let (sin_theta, cos_theta) = (u * TAU).sin_cos();
+                let normal = glam::vec3(sin_phi * cos_theta, cos_phi, sin_phi * sin_theta);
+                vertices.push(NormalMappedVertex {
+                    position: normal * radius,
+                    uv: glam::vec2(u, v),
+                    normal,
+                    // Unscaled so they don't vanish at the poles
+                    tangent: glam::vec3(-sin_theta, 0.0, cos_theta),
+                    bitangent: glam::vec3(cos_phi * cos_theta, -sin_phi, cos_phi * sin_theta),
+                });
+            }
+        }
+
+        let mut indices = Vec::new();
+        for i in 0..stacks {
+            for j in 0..sectors {
+                let a = i * (sectors + 1) + j;
+                let b = a + sectors + 1;
+                indices.extend([a, a + 1, b, a + 1, b + 1, b]);
+            }
+        }
+
+        Self {
+            name: "uv_sphere".to_string(),
+            vertices,
+            indices,
+            material: 0,
+        }
+    }
+}
+
+/// Parses an OBJ file into mesh data without touching the GPU. `load_mtl` is
+/// called with the path of every material library the OBJ references.
+pub fn parse_obj<B: BufRead>(
+    reader: &mut B,
+    load_mtl: impl Fn(&Path) -> tobj::MTLLoadResult,
+) -> Result<(Vec<MeshData>, Vec<tobj::Material>)> {
+    let (obj_models, obj_materials) = tobj::load_obj_buf(
+        reader,
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+        load_mtl,
+    )?;
+
+    let meshes = obj_models
+        .into_iter()
+        .map(|m| MeshData::from_obj_mesh(m.name, &m.mesh))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok((meshes, obj_materials?))
+}
+
 impl Model {
     pub fn load_obj<P: AsRef<Path>>(
         device: &wgpu::Device,
@@ -66,29 +373,27 @@ impl Model {
         let path = path.as_ref();
         let mut reader = Cursor::new(res.load_string(path)?);
 
-        let parent_dir = path.parent().unwrap();
-
-        let (obj_models, obj_materials) = tobj::load_obj_buf(
-            &mut reader,
-            &tobj::LoadOptions {
-                triangulate: true,
-                single_index: true,
-                ..Default::default()
-            },
-            |path| {
-                let rel_path = parent_dir.join(path);
-                let mut reader = Cursor::new(res.load_string(rel_path).unwrap());
-                tobj::load_mtl_buf(&mut reader)
-            },
-        )?;
-
         // We're assuming that the texture files are stored with the obj file
         let containing_folder = path.parent().context("Directory has no parent")?;
 
+        let (obj_meshes, obj_materials) = parse_obj(&mut reader, |path| {
+            let rel_path = containing_folder.join(path);
+            let mtl = res
+                .load_string(rel_path)
+                .map_err(|_| tobj::LoadError::OpenFileFailed)?;
+            tobj::load_mtl_buf(&mut Cursor::new(mtl))
+        })?;
+
         let mut materials = Vec::new();
-        for mat in obj_materials? {
+        for mat in obj_materials {
             let diffuse_texture = if let Some(path) = mat.diffuse_texture {
-                texture::Texture::load(device, queue, containing_folder.join(path), false)?
+                texture::Texture::load(
+                    device,
+                    queue,
+                    containing_folder.join(path),
+                    false,
+                    texture::SamplerOptions::default(),
+                )?
             } else {
                 texture::Texture::from_color(
                     device,
@@ -102,7 +407,13 @@ impl Model {
             };
 
             let normal_texture = if let Some(path) = mat.normal_texture {
-                texture::Texture::load(device, queue, containing_folder.join(path), true)?
+                texture::Texture::load(
+                    device,
+                    queue,
+                    containing_folder.join(path),
+                    true,
+                    texture::SamplerOptions::default(),
+                )?
             } else {
                 texture::Texture::from_color(
                     device,
@@ -130,138 +441,53 @@ impl Model {
         }
 
         if materials.is_empty() {
-            materials.push(Material::new(
-                device,
-                "default",
-                texture::Texture::from_color(
-                    device,
-                    queue,
-                    1,
-                    1,
-                    wgpu::Color::WHITE,
-                    true,
-                    wgpu::TextureUsages::TEXTURE_BINDING,
-                ),
-                texture::Texture::from_color(
-                    device,
-                    queue,
-                    1,
-                    1,
-                    wgpu::Color {
-                        r: 0.5,
-                        g: 0.5,
-                        b: 1.0,
-                        a: 1.0,
-                    },
-                    false,
-                    wgpu::TextureUsages::TEXTURE_BINDING,
-                ),
-                material_binder,
-            ));
+            materials.push(Material::untextured(device, queue, material_binder));
         }
 
         let mut meshes = Vec::new();
-        for m in obj_models {
-            let mut vertices = Vec::new();
-            for i in 0..m.mesh.positions.len() / 3 {
-                vertices.push(NormalMappedVertex {
-                    position: glam::vec3(
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ),
-                    uv: glam::vec2(m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]),
-                    normal: glam::vec3(
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ),
-                    // We'll calculate these later
-                    tangent: [0.0; 3].into(),
-                    bitangent: [0.0; 3].into(),
-                });
-            }
-
-            let indices = &m.mesh.indices;
-
-            // Calculate tangents and bitangets. We're going to
-            // use the triangles, so we need to loop through the
-            // indices in chunks of 3
-            for c in indices.chunks(3) {
-                let v0 = vertices[c[0] as usize];
-                let v1 = vertices[c[1] as usize];
-                let v2 = vertices[c[2] as usize];
-
-                let pos0 = v0.position;
-                let pos1 = v1.position;
-                let pos2 = v2.position;
-
-                let uv0 = v0.uv;
-                let uv1 = v1.uv;
-                let uv2 = v2.uv;
-
-                // Calculate the edges of the triangle
-                let delta_pos1 = pos1 - pos0;
-                let delta_pos2 = pos2 - pos0;
-
-                // This will give us a direction to calculate the
-                // tangent and bitangent
-                let delta_uv1 = uv1 - uv0;
-                let delta_uv2 = uv2 - uv0;
-
-                // Solving the following system of equations will
-                // give us the tangent and bitangent.
-                //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
-                //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
-                // Luckily, the place I found this equation provided
-                // the solution!
-                let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-                let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-                let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * r;
-
-                // We'll use the same tangent/bitangent for each vertex in the triangle
-                vertices[c[0] as usize].tangent = tangent.into();
-                vertices[c[1] as usize].tangent = tangent.into();
-                vertices[c[2] as usize].tangent = tangent.into();
-
-                vertices[c[0] as usize].bitangent = bitangent.into();
-                vertices[c[1] as usize].bitangent = bitangent.into();
-                vertices[c[2] as usize].bitangent = bitangent.into();
+        for m in obj_meshes {
+            if m.material >= materials.len() {
+                bail!("{}: unknown material {}", m.name, m.material);
             }
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", path)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", path)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            meshes.push(Mesh {
-                name: m.name,
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
-            });
+            meshes.push(Mesh::from_data(device, &m));
         }
 
         Ok(Self { meshes, materials })
     }
+
+    /// Builds a model out of generated meshes, all using a plain white
+    /// material.
+    pub fn from_mesh_data(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        material_binder: &MaterialBinder,
+        meshes: &[MeshData],
+    ) -> Self {
+        Self {
+            meshes: meshes
+                .iter()
+                .map(|data| Mesh {
+                    material: 0,
+                    ..Mesh::from_data(device, data)
+                })
+                .collect(),
+            materials: vec![Material::untextured(device, queue, material_binder)],
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ModelId(usize);
 
 pub struct ModelPipeline {
-    draw_model_pipeline: wgpu::RenderPipeline,
+    pipelines: WireframePipelines,
     models: Vec<Model>,
 }
 
 impl ModelPipeline {
+    /// `sample_count` is 1 without MSAA, see
+    /// [`crate::resources::texture::supported_sample_count`].
     pub fn new(
         device: &wgpu::Device,
         surface_format: wgpu::TextureFormat,
@@ -269,6 +495,7 @@ impl ModelPipeline {
         camera_binder: &CameraBinder,
         material_binder: &MaterialBinder,
         light_binder: &LightBinder,
+        sample_count: u32,
     ) -> Self {
         let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("ModelPipeline PipelineLayout"),
@@ -280,49 +507,76 @@ impl ModelPipeline {
             push_constant_ranges: &[],
         });
 
-        let module = device.create_shader_module(wgpu::include_wgsl!("normal_mapped.wgsl"));
-
-        let draw_model_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("ModelPipeline"),
-            layout: Some(&layout),
-            vertex: wgpu::VertexState {
-                module: &module,
-                entry_point: Some("vs_main"),
-                compilation_options: Default::default(),
-                buffers: &[NormalMappedVertex::VB_DESC, InstanceVertex::VB_DESC],
-            },
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                ..Default::default()
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: depth_format,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState::default(),
-            fragment: Some(wgpu::FragmentState {
-                module: &module,
-                entry_point: Some("fs_main"),
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format,
-                    blend: None,
-                    write_mask: wgpu::ColorWrites::all(),
-                })],
-            }),
-            multiview: None,
-            cache: None,
+        let mut source = include_str!("normal_mapped.wgsl").to_string();
+        if !light_binder.uses_storage() {
+            source = source.replace(
+                "var<storage, read> lights: array<Light>;",
+                &format!("var<uniform> lights: array<Light, {MAX_UNIFORM_LIGHTS}>;"),
+            );
+        }
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("normal_mapped.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        let pipelines = WireframePipelines::new(device, |polygon_mode| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("ModelPipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[NormalMappedVertex::VB_DESC, InstanceVertex::VB_DESC],
+                },
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    polygon_mode,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: depth_format,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point: Some("fs_main"),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: surface_format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::all(),
+                    })],
+                }),
+                multiview: None,
+                cache: None,
+            })
         });
 
         Self {
-            draw_model_pipeline,
+            pipelines,
             models: Vec::new(),
         }
     }
 
+    pub fn wireframe(&self) -> bool {
+        self.pipelines.wireframe()
+    }
+
+    /// Draws only the edges of triangles, for debugging geometry. Needs
+    /// [`wgpu::Features::POLYGON_MODE_LINE`], without which this warns and
+    /// returns `false`, leaving models filled.
+    pub fn set_wireframe(&mut self, wireframe: bool) -> bool {
+        self.pipelines.set_wireframe(wireframe)
+    }
+
     pub fn load_obj<P: AsRef<Path>>(
         &mut self,
         device: &wgpu::Device,
@@ -339,6 +593,24 @@ impl ModelPipeline {
         Ok(id)
     }
 
+    /// Adds a model built from generated meshes, see [`Model::from_mesh_data`].
+    pub fn add_mesh_data(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        material_binder: &MaterialBinder,
+        meshes: &[MeshData],
+    ) -> ModelId {
+        let id = ModelId(self.models.len());
+        self.models.push(Model::from_mesh_data(
+            device,
+            queue,
+            material_binder,
+            meshes,
+        ));
+        id
+    }
+
     pub fn draw<'a, 'b: 'a>(
         &'a self,
         pass: &'a mut wgpu::RenderPass<'b>,
@@ -352,7 +624,7 @@ impl ModelPipeline {
             None => return,
         };
 
-        pass.set_pipeline(&self.draw_model_pipeline);
+        pass.set_pipeline(self.pipelines.current());
         pass.set_bind_group(1, camera.bind_group(), &[]);
         pass.set_bind_group(2, lights.bind_group(), &[]);
         pass.set_vertex_buffer(1, instances.slice());
@@ -464,3 +736,48 @@ impl MaterialBinding {
         &self.bind_group
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> Result<(Vec<MeshData>, Vec<tobj::Material>)> {
+        parse_obj(&mut Cursor::new(src), |_| {
+            Err(tobj::LoadError::OpenFileFailed)
+        })
+    }
+
+    #[test]
+    fn test_parse_obj_without_uvs_or_normals() {
+        let (meshes, _) = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(meshes[0].vertices.len(), 3);
+        assert_eq!(meshes[0].indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_parse_obj_rejects_malformed_input() {
+        assert!(parse("v 0 0 0\nf 1 2 3\n").is_err());
+        assert!(parse("f 1 2 3\n").is_err());
+        assert!(parse("mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").is_err());
+    }
+
+    fn assert_valid(mesh: &MeshData, vertices: usize, indices: usize) {
+        assert_eq!(mesh.vertices.len(), vertices);
+        assert_eq!(mesh.indices.len(), indices);
+        assert!(mesh.indices.iter().all(|&i| (i as usize) < vertices));
+        for v in &mesh.vertices {
+            assert!(v.tangent.is_finite() && v.tangent.length() > 0.0);
+            assert!(v.bitangent.is_finite() && v.bitangent.length() > 0.0);
+            assert!((v.normal.length() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_procedural_meshes() {
+        assert_valid(&MeshData::plane(2.0, 4), 25, 4 * 4 * 6);
+        assert_valid(&MeshData::cylinder(1.0, 2.0, 16), 34, 16 * 6);
+        assert_valid(&MeshData::uv_sphere(1.0, 16, 8), 17 * 9, 16 * 8 * 6);
+    }
+}
@@ -7,12 +7,41 @@ struct Camera {
 @group(1) @binding(0)
 var<uniform> camera: Camera;
 
+// Mirrors `LightUniform` in light.rs. `ambient` is a vec4 so that `range`
+// starts at offset 48 instead of packing into the end of a vec3.
 struct Light {
     position: vec3<f32>,
     color: vec3<f32>,
+    ambient: vec4<f32>,
+    range: f32,
+    intensity: f32,
 }
+// ModelPipeline swaps this for a fixed size uniform array when the device
+// has no storage buffers
 @group(2) @binding(0)
-var<uniform> light: Light;
+var<storage, read> lights: array<Light>;
+
+struct LightCount {
+    count: u32,
+}
+@group(2) @binding(1)
+var<uniform> light_count: LightCount;
+
+// Mirrors `DirectionalLight` in light.rs
+struct DirectionalLight {
+    direction: vec4<f32>,
+    color: vec4<f32>,
+}
+@group(2) @binding(2)
+var<uniform> sun: DirectionalLight;
+
+// Where the sun's shadow map was rendered from, all zeros without shadows
+@group(2) @binding(3)
+var<uniform> shadow_view_proj: mat4x4<f32>;
+@group(2) @binding(4)
+var shadow_map: texture_depth_2d;
+@group(2) @binding(5)
+var shadow_sampler: sampler_comparison;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
@@ -36,8 +65,13 @@ struct VertexOutput {
     @location(0) debug: vec4<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) tangent_position: vec3<f32>,
-    @location(3) tangent_light_position: vec3<f32>,
-    @location(4) tangent_view_position: vec3<f32>,
+    @location(3) tangent_view_position: vec3<f32>,
+    // Columns of the world to tangent space matrix, so each light can be
+    // moved into tangent space in the fragment shader
+    @location(4) tangent_matrix_0: vec3<f32>,
+    @location(5) tangent_matrix_1: vec3<f32>,
+    @location(6) tangent_matrix_2: vec3<f32>,
+    @location(7) shadow_position: vec4<f32>,
 }
 
 @vertex
@@ -74,7 +108,10 @@ fn vs_main(
     out.tex_coords = model.tex_coords;
     out.tangent_position = tangent_matrix * world_position.xyz;
     out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
-    out.tangent_light_position = tangent_matrix * light.position;
+    out.tangent_matrix_0 = tangent_matrix[0];
+    out.tangent_matrix_1 = tangent_matrix[1];
+    out.tangent_matrix_2 = tangent_matrix[2];
+    out.shadow_position = shadow_view_proj * world_position;
     out.debug = vec4(world_normal * 0.5 + 0.5, 0.0);
     return out;
 }
@@ -90,27 +127,91 @@ var t_normal: texture_2d<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
 
+// Inverse square falloff, smoothly windowed to zero at `range` if it's set
+fn light_attenuation(light: Light, distance: f32) -> f32 {
+    let falloff = light.intensity / max(distance * distance, 0.0001);
+    if light.range <= 0.0 {
+        return falloff;
+    }
+    let window = saturate(1.0 - pow(distance / light.range, 4.0));
+    return falloff * window * window;
+}
+
+// Keeps surfaces from shadowing themselves
+const SHADOW_BIAS: f32 = 0.002;
+
+// How much of the sun reaches a fragment, averaging the comparison over a 3x3
+// block of texels to soften the edges of shadows
+fn sun_visibility(shadow_position: vec4<f32>) -> f32 {
+    if shadow_position.w <= 0.0 {
+        return 1.0;
+    }
+    let ndc = shadow_position.xyz / shadow_position.w;
+    let uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+    // Nothing outside the shadow map is known to be in the way
+    if any(uv < vec2(0.0)) || any(uv > vec2(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+    var visibility = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let offset = vec2(f32(x), f32(y)) * texel;
+            visibility += textureSampleCompareLevel(
+                shadow_map,
+                shadow_sampler,
+                uv + offset,
+                ndc.z - SHADOW_BIAS,
+            );
+        }
+    }
+    return visibility / 9.0;
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
     let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
     
-    let ambient_strength = 0.1;
-    let ambient_color = light.color * ambient_strength;
+    let tangent_matrix = mat3x3<f32>(
+        in.tangent_matrix_0,
+        in.tangent_matrix_1,
+        in.tangent_matrix_2,
+    );
 
     // Create the lighting vectors
     let tangent_normal = object_normal.xyz * 2.0 - 1.0;
-    let light_dir = normalize(in.tangent_light_position - in.tangent_position);
     let view_dir = normalize(in.tangent_view_position - in.tangent_position);
-    let half_dir = normalize(view_dir + light_dir);
 
-    let diffuse_strength = max(dot(tangent_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+    var light_color = vec3<f32>(0.0);
+    for (var i = 0u; i < light_count.count; i++) {
+        let light = lights[i];
+        let tangent_light_position = tangent_matrix * light.position;
+        let to_light = tangent_light_position - in.tangent_position;
+        let light_dir = normalize(to_light);
+        let half_dir = normalize(view_dir + light_dir);
+
+        let attenuation = light_attenuation(light, length(to_light));
+
+        let diffuse_strength = max(dot(tangent_normal, light_dir), 0.0);
+        let diffuse_color = light.color * diffuse_strength;
+
+        let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
+        let specular_color = specular_strength * light.color;
+
+        light_color += light.ambient.rgb + (diffuse_color + specular_color) * attenuation;
+    }
 
-    let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
-    let specular_color = specular_strength * light.color;
+    // Far enough away to come from the same direction everywhere
+    let sun_dir = normalize(tangent_matrix * sun.direction.xyz);
+    let sun_half_dir = normalize(view_dir + sun_dir);
+    let sun_diffuse = max(dot(tangent_normal, sun_dir), 0.0);
+    let sun_specular = pow(max(dot(tangent_normal, sun_half_dir), 0.0), 32.0);
+    let sun_visible = sun_visibility(in.shadow_position);
+    light_color += sun.color.rgb * (sun_diffuse + sun_specular) * sun_visible;
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+    let result = light_color * object_color.xyz;
     // let result = in.debug.xyz;
     // let result = tangent_normal * 0.5 + 0.5;
     // let result = vec3(in.tex_coords, 0.0);
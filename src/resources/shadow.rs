@@ -0,0 +1,108 @@
+use crate::resources::{
+    buffer::BackedBuffer,
+    light::{LightBinder, LightBinding, SHADOW_MAP_FORMAT},
+    model::{ModelId, ModelPipeline},
+    vertex::{ColoredInstance, NormalMappedVertex},
+};
+
+/// Slope scaled so surfaces facing away from the light don't shadow
+/// themselves.
+const SHADOW_DEPTH_BIAS: wgpu::DepthBiasState = wgpu::DepthBiasState {
+    constant: 2,
+    slope_scale: 2.0,
+    clamp: 0.0,
+};
+
+/// Renders the depth of models as seen by a [`LightBinding`]'s directional
+/// light into its shadow map, which the model shader then reads to tell
+/// what the sun doesn't reach. Takes the same instances as
+/// [`crate::simulation::visualization::VisualizationPipeline`].
+pub struct ShadowPipeline {
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowPipeline {
+    pub fn new(device: &wgpu::Device, light_binder: &LightBinder) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ShadowPipeline"),
+            bind_group_layouts: &[light_binder.shadow_layout()],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shadow.wgsl"));
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("ShadowPipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[NormalMappedVertex::VB_DESC, ColoredInstance::VB_DESC],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: SHADOW_MAP_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: Default::default(),
+                bias: SHADOW_DEPTH_BIAS,
+            }),
+            multisample: Default::default(),
+            fragment: None,
+            multiview: Default::default(),
+            cache: None,
+        });
+
+        Self { pipeline }
+    }
+
+    /// Clears the shadow map of `lights` and draws `batch` into it. Returns
+    /// the number of instances drawn, counting each mesh separately.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        lights: &LightBinding,
+        batch: &[(ModelId, &BackedBuffer<ColoredInstance>)],
+        model_pipeline: &ModelPipeline,
+    ) -> u32 {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("ShadowPipeline::render"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &lights.shadow_map().view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            ..Default::default()
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, lights.shadow_bind_group(), &[]);
+
+        let mut drawn = 0;
+        for &(model, instances) in batch {
+            if instances.is_empty() {
+                continue;
+            }
+            let Some(model) = model_pipeline.get_model(model) else {
+                continue;
+            };
+
+            pass.set_vertex_buffer(1, instances.slice());
+            for mesh in &model.meshes {
+                pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                pass.draw_indexed(0..mesh.num_elements, 0, 0..instances.len());
+                drawn += instances.len();
+            }
+        }
+
+        drawn
+    }
+}
@@ -2,7 +2,86 @@ use anyhow::*;
 use image::GenericImageView;
 use std::path::Path;
 
-// use crate::buffer;
+/// How a [`Texture`] is sampled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SamplerOptions {
+    /// Anisotropic filtering samples, clamped to `1..=16`. Anything above 1
+    /// switches every filter to linear, since wgpu requires that. Adapters
+    /// without anisotropic filtering quietly use 1.
+    pub anisotropy: u16,
+}
+
+impl Default for SamplerOptions {
+    fn default() -> Self {
+        Self { anisotropy: 1 }
+    }
+}
+
+impl SamplerOptions {
+    pub fn anisotropic(anisotropy: u16) -> Self {
+        Self { anisotropy }
+    }
+
+    pub fn descriptor(
+        &self,
+        compare: Option<wgpu::CompareFunction>,
+    ) -> wgpu::SamplerDescriptor<'static> {
+        let anisotropy_clamp = self.anisotropy.clamp(1, 16);
+        let min_filter = if anisotropy_clamp > 1 {
+            wgpu::FilterMode::Linear
+        } else {
+            wgpu::FilterMode::Nearest
+        };
+        wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter,
+            mipmap_filter: min_filter,
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 100.0,
+            compare,
+            anisotropy_clamp,
+            ..Default::default()
+        }
+    }
+}
+
+/// The highest MSAA sample count up to `requested` that `color_format` can
+/// be multisampled and resolved with and `depth_format` multisampled with,
+/// or 1 if there's none. Unless `device` has
+/// [`wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES`], counts the
+/// formats don't guarantee are left out, since wgpu rejects them.
+pub fn supported_sample_count(
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+    color_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    requested: u32,
+) -> u32 {
+    let features = device.features();
+    let flags = |format: wgpu::TextureFormat| {
+        let flags = adapter.get_texture_format_features(format).flags;
+        if features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+            flags
+        } else {
+            flags & format.guaranteed_format_features(features).flags
+        }
+    };
+    let color = flags(color_format);
+    let depth = flags(depth_format);
+
+    [16, 8, 4, 2]
+        .into_iter()
+        .filter(|&count| count <= requested)
+        .find(|&count| {
+            color.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+                && color.sample_count_supported(count)
+                && depth.sample_count_supported(count)
+        })
+        .unwrap_or(1)
+}
 
 #[derive(Debug)]
 pub struct Texture {
@@ -19,36 +98,29 @@ impl Texture {
         queue: &wgpu::Queue,
         path: P,
         is_normal_map: bool,
+        options: SamplerOptions,
     ) -> Result<Self> {
         let path_copy = path.as_ref().to_path_buf();
         let label = path_copy.to_str().unwrap();
         let img = image::open(path)?;
-        Self::from_image(device, queue, &img, Some(label), is_normal_map)
+        Self::from_image(device, queue, &img, Some(label), is_normal_map, options)
     }
 
     pub fn from_descriptor<'a>(
         device: &'a wgpu::Device,
         desc: &'a wgpu::TextureDescriptor<'a>,
+        options: SamplerOptions,
     ) -> Self {
         let texture = device.create_texture(&desc);
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            lod_min_clamp: 0.0,
-            lod_max_clamp: 100.0,
-            compare: if desc.format.is_depth_stencil_format() {
+        let sampler = device.create_sampler(&options.descriptor(
+            if desc.format.is_depth_stencil_format() {
                 Some(wgpu::CompareFunction::LessEqual)
             } else {
                 None
             },
-            ..Default::default()
-        });
+        ));
 
         Self {
             texture,
@@ -63,9 +135,10 @@ impl Texture {
         label: Option<&str>,
         is_normal_map: bool,
         bytes: &[u8],
+        options: SamplerOptions,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, label, is_normal_map)
+        Self::from_image(device, queue, &img, label, is_normal_map, options)
     }
 
     pub fn from_image(
@@ -74,6 +147,7 @@ impl Texture {
         img: &image::DynamicImage,
         _label: Option<&str>,
         is_normal_map: bool,
+        options: SamplerOptions,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -117,18 +191,8 @@ impl Texture {
         );
 
         let view = texture.create_view(&Default::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            lod_min_clamp: 0.0,
-            lod_max_clamp: 100.0,
-            compare: Some(wgpu::CompareFunction::Always),
-            ..Default::default()
-        });
+        let sampler =
+            device.create_sampler(&options.descriptor(Some(wgpu::CompareFunction::Always)));
 
         Ok(Self {
             texture,
@@ -155,30 +219,78 @@ impl Texture {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             view_formats: &[Self::DEPTH_FORMAT],
         };
-        Self::from_descriptor(device, &desc)
+        Self::from_descriptor(device, &desc, SamplerOptions::default())
     }
 
-    // pub fn prepare_buffer_rgba(&self, device: &wgpu::Device) -> buffer::RawBuffer<[f32; 4]> {
-    //     let num_pixels = self.texture.size().width
-    //         * self.texture.size().height
-    //         * self.texture.size().depth_or_array_layers;
-
-    //     let buffer_size = num_pixels * mem::size_of::<[f32; 4]>() as u32;
-    //     let buffer_usage = wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ;
-    //     let buffer_desc = wgpu::BufferDescriptor {
-    //         size: buffer_size as wgpu::BufferAddress,
-    //         usage: buffer_usage,
-    //         label: None,
-    //         mapped_at_creation: false,
-    //     };
-    //     let buffer = device.create_buffer(&buffer_desc);
+    /// Copies the first layer of this texture back to the CPU. The texture
+    /// needs `COPY_SRC` and an 8 bit RGBA or BGRA format. Blocks until the
+    /// GPU is done.
+    pub fn read_to_image(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<image::RgbaImage> {
+        let swap_red_blue = match self.texture.format() {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            format => bail!("Can't read {format:?} textures into an image"),
+        };
 
-    //     let data = Vec::with_capacity(num_pixels as usize);
+        let wgpu::Extent3d { width, height, .. } = self.texture.size();
+        // Rows in buffer copies have to be aligned to 256 bytes
+        let unpadded_bytes_per_row = width * 4;
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Texture::read_to_image"),
+            size: (padded_bytes_per_row * height) as _,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
 
-    //     let raw_buffer = buffer::RawBuffer::from_parts(buffer, data, buffer_usage);
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit([encoder.finish()]);
 
-    //     raw_buffer
-    // }
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::PollType::Wait)?;
+        receiver.recv()??;
+
+        let data = slice.get_mapped_range();
+        let mut pixels = data
+            .chunks(padded_bytes_per_row as _)
+            .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+            .copied()
+            .collect::<Vec<_>>();
+        if swap_red_blue {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        image::RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow!("Readback was the wrong size"))
+    }
 
     pub fn from_color(
         device: &wgpu::Device,
@@ -214,7 +326,7 @@ impl Texture {
 
         desc.usage = usage | wgpu::TextureUsages::COPY_DST;
 
-        let texture = Self::from_descriptor(device, &desc);
+        let texture = Self::from_descriptor(device, &desc, SamplerOptions::default());
 
         let mut encoder = device.create_command_encoder(&Default::default());
 
@@ -319,3 +431,30 @@ impl TextureBinding {
         &self.bind_group
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampler_options_descriptor() {
+        let desc = SamplerOptions::default().descriptor(None);
+        assert_eq!(desc.anisotropy_clamp, 1);
+        assert_eq!(desc.min_filter, wgpu::FilterMode::Nearest);
+
+        // Anisotropy needs every filter to be linear
+        let desc = SamplerOptions::anisotropic(8).descriptor(None);
+        assert_eq!(desc.anisotropy_clamp, 8);
+        assert_eq!(desc.mag_filter, wgpu::FilterMode::Linear);
+        assert_eq!(desc.min_filter, wgpu::FilterMode::Linear);
+        assert_eq!(desc.mipmap_filter, wgpu::FilterMode::Linear);
+
+        let clamp = |anisotropy| {
+            SamplerOptions::anisotropic(anisotropy)
+                .descriptor(None)
+                .anisotropy_clamp
+        };
+        assert_eq!(clamp(0), 1);
+        assert_eq!(clamp(64), 16);
+    }
+}
@@ -76,26 +76,6 @@ impl InstanceVertex {
             normal_matrix_2: glam::vec4(0.0, 0.0, 1.0, 0.0),
         }
     }
-
-    pub(crate) fn extend_between(a: glam::Vec3, b: glam::Vec3, radial_scale: f32) -> Self {
-        let ab = a - b;
-        let dist = ab.length();
-        let dir = if dist == 0.0 {
-            glam::Vec3::Y
-        } else {
-            ab / dist
-        };
-        let scale = glam::vec3(radial_scale, dist * 0.5, radial_scale);
-        let position = (a + b) * 2.0;
-        let rotation = glam::Quat::from_rotation_arc(glam::Vec3::Y, dir);
-        let rot_mat = glam::Mat4::from_rotation_translation(rotation, glam::Vec3::ZERO);
-        Self {
-            model_matrix: glam::Mat4::from_scale_rotation_translation(scale, rotation, position),
-            normal_matrix_0: rot_mat.x_axis,
-            normal_matrix_1: rot_mat.y_axis,
-            normal_matrix_2: rot_mat.z_axis,
-        }
-    }
 }
 
 impl Default for InstanceVertex {
@@ -130,7 +110,7 @@ impl ColoredInstance {
         ],
     };
     
-    pub(crate) fn with_position_scale(color: glam::Vec3, position: glam::Vec3, scale: f32) -> Self {
+    pub fn with_position_scale(color: glam::Vec3, position: glam::Vec3, scale: f32) -> Self {
         Self {
             color: glam::vec4(color.x, color.y, color.z, 1.0),
             model_matrix: glam::Mat4::from_scale_rotation_translation(
@@ -141,7 +121,7 @@ impl ColoredInstance {
         }
     }
 
-    pub(crate) fn extend_between(
+    pub fn extend_between(
         color: glam::Vec3,
         a: glam::Vec3,
         b: glam::Vec3,
@@ -163,6 +143,40 @@ impl ColoredInstance {
             model_matrix: glam::Mat4::from_scale_rotation_translation(scale, rotation, position),
         }
     }
+
+    #[cfg(any(test, feature = "gltf"))]
+    pub(crate) fn color(&self) -> glam::Vec4 {
+        self.color
+    }
+
+    pub(crate) fn model_matrix(&self) -> glam::Mat4 {
+        self.model_matrix
+    }
+}
+
+/// One glyph of a label. Each is drawn as an instanced quad so any number of
+/// labels can go out in a single draw.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GlyphInstance {
+    /// Min and max corners, relative to the label's origin before scaling.
+    pub rect: glam::Vec4,
+    pub uv_rect: glam::Vec4,
+    pub origin: glam::Vec3,
+    pub scale: f32,
+}
+
+impl GlyphInstance {
+    pub const VB_DESC: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<Self>() as _,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: &wgpu::vertex_attr_array![
+            0 => Float32x4,
+            1 => Float32x4,
+            2 => Float32x3,
+            3 => Float32,
+        ],
+    };
 }
 
 trait MoreSwizzles {
@@ -180,3 +194,19 @@ impl MoreSwizzles for glam::Vec4 {
         glam::vec4(self.x, self.y, self.z, 0.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extend_between_midpoint() {
+        let a = glam::vec3(1.0, 2.0, -3.0);
+        let b = glam::vec3(3.0, -2.0, 1.0);
+        let midpoint = glam::vec3(2.0, 0.0, -1.0);
+
+        let instance = ColoredInstance::extend_between(glam::Vec3::ONE, a, b, 0.1);
+        let translation = instance.model_matrix.w_axis.truncate();
+        assert!(translation.abs_diff_eq(midpoint, 1e-6));
+    }
+}
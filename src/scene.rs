@@ -0,0 +1,96 @@
+//! Saving and restoring everything needed to pick up where the app left off.
+
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::{
+    resources::camera::CameraState,
+    simulation::{Environment, Simulation},
+};
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct SceneState {
+    pub simulation: Simulation,
+    pub environment: Environment,
+    pub camera: CameraState,
+}
+
+impl SceneState {
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_json()?)
+            .with_context(|| format!("Unable to save scene to {}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read scene from {}", path.display()))?;
+        Self::from_json(&json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::SolarPanel;
+
+    #[test]
+    fn test_scene_round_trip() {
+        let mut simulation = Simulation::new();
+        let tank = simulation.add_node(50.0, 60.0, 0.9, 100.0, glam::Vec3::ZERO);
+        let panel = simulation.add_node(5.0, 20.0, 0.5, 10.0, glam::Vec3::X);
+        simulation.set_node_name(tank, "tank");
+        simulation.connect_node(tank, panel, 2.0);
+        simulation.set_connection_volume(0, 3.0);
+        simulation.attach_solar_panel(
+            panel,
+            SolarPanel {
+                area: 2.0,
+                efficiency: 0.8,
+            },
+        );
+
+        let mut environment = Environment::default();
+        environment.set_sun_angle(1.0);
+        environment.set_cloud_cover(0.25);
+        simulation.tick(&environment, 1.0);
+
+        let mut scene = SceneState {
+            simulation,
+            environment,
+            camera: CameraState {
+                position: glam::vec3(1.0, 2.0, 3.0),
+                yaw: 0.5,
+                pitch: -0.25,
+                fovy: 1.0,
+                znear: 0.1,
+                zfar: 100.0,
+            },
+        };
+        let mut loaded = SceneState::from_json(&scene.to_json().unwrap()).unwrap();
+
+        assert_eq!(loaded.camera, scene.camera);
+        assert_eq!(loaded.environment.sun_angle(), 1.0);
+        assert_eq!(loaded.environment.cloud_cover(), 0.25);
+        assert_eq!(loaded.simulation.node_by_name("tank"), Some(tank));
+        assert_eq!(
+            loaded.simulation.connections(),
+            scene.simulation.connections()
+        );
+
+        // Both carry on identically, including the fluid still in the pipe
+        loaded.simulation.tick(&loaded.environment, 1.0);
+        scene.simulation.tick(&scene.environment, 1.0);
+        assert_eq!(loaded.simulation.nodes(), scene.simulation.nodes());
+    }
+}
@@ -47,6 +47,29 @@ fn textured(in: TexturedVertex) -> VsOut {
     return VsOut(camera.view_proj * vec4(in.position, 0.0, 1.0), in.uv);
 }
 
+struct GlyphInstance {
+    @location(0)
+    rect: vec4<f32>,
+    @location(1)
+    uv_rect: vec4<f32>,
+    @location(2)
+    origin: vec3<f32>,
+    @location(3)
+    scale: f32,
+}
+
+// Draw with 6 vertices per instance
+@vertex
+fn glyph_instance(@builtin(vertex_index) i: u32, glyph: GlyphInstance) -> VsOut {
+    var corners = array(vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0));
+    var order = array(0u, 1u, 2u, 0u, 2u, 3u);
+    let corner = corners[order[i]];
+
+    let position = mix(glyph.rect.xy, glyph.rect.zw, corner) * glyph.scale;
+    let uv = mix(glyph.uv_rect.xy, glyph.uv_rect.zw, corner);
+    return VsOut(camera.view_proj * vec4(glyph.origin + vec3(position, 0.0), 1.0), uv);
+}
+
 @group(0)
 @binding(0)
 var font_texture: texture_2d<f32>;
@@ -67,6 +90,11 @@ struct FontUniforms {
 @binding(0)
 var<uniform> uniforms: FontUniforms;
 
+// Per text buffer, see `TextBuffer::set_color`
+@group(3)
+@binding(0)
+var<uniform> text_color: vec4<f32>;
+
 fn median(msd: vec3<f32>) -> f32 {
     return max(min(msd.r, msd.g), min(max(msd.r, msd.g), msd.b));
 }
@@ -107,7 +135,5 @@ fn msdf_text(vs: VsOut) -> @location(0) vec4<f32> {
     opacity = mix(opacity, (opacity + 0.5 * asum) / 3.0, uniforms.super_sample);
     opacity = pow(opacity, uniforms.inv_gamma);
 
-    let col = vec3(1.0);
-
-    return vec4(col, opacity);
+    return vec4(text_color.rgb, opacity * text_color.a);
 }
\ No newline at end of file
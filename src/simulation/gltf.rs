@@ -0,0 +1,242 @@
+//! Exports the node graph as a binary glTF (`.glb`) for viewers like Blender.
+
+use std::path::Path;
+
+use anyhow::Context;
+use serde_json::json;
+
+use crate::{
+    canvas::{build_connection_instances, build_node_instances, default_node_style},
+    resources::{model::MeshData, vertex::ColoredInstance},
+    simulation::Simulation,
+};
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: &[u8; 4] = b"JSON";
+const CHUNK_BIN: &[u8; 4] = b"BIN\0";
+
+// glTF enums
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+pub trait GltfExport {
+    /// One glTF node and mesh per simulation node and connection, placed and
+    /// colored like the 3D view. The meshes share a sphere and a cylinder.
+    fn to_glb(&self) -> anyhow::Result<Vec<u8>>;
+
+    fn export_gltf(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_glb()?)
+            .with_context(|| format!("Unable to export glTF to {}", path.display()))
+    }
+}
+
+impl GltfExport for Simulation {
+    fn to_glb(&self) -> anyhow::Result<Vec<u8>> {
+        let mut builder = GlbBuilder::default();
+        // Match the unit sized models the instances are scaled for
+        let sphere = builder.add_mesh_data(&MeshData::uv_sphere(1.0, 16, 8));
+        let cylinder = builder.add_mesh_data(&MeshData::cylinder(1.0, 2.0, 12));
+
+        for instance in build_node_instances(self, 1.0, &default_node_style) {
+            builder.add_instance(&sphere, &instance);
+        }
+        for instance in build_connection_instances(self) {
+            builder.add_instance(&cylinder, &instance);
+        }
+
+        builder.finish()
+    }
+}
+
+/// Accessor indices of a mesh in the binary buffer.
+struct Primitive {
+    attributes: serde_json::Value,
+    indices: usize,
+}
+
+#[derive(Default)]
+struct GlbBuilder {
+    bin: Vec<u8>,
+    buffer_views: Vec<serde_json::Value>,
+    accessors: Vec<serde_json::Value>,
+    materials: Vec<serde_json::Value>,
+    meshes: Vec<serde_json::Value>,
+    nodes: Vec<serde_json::Value>,
+}
+
+impl GlbBuilder {
+    fn add_mesh_data(&mut self, mesh: &MeshData) -> Primitive {
+        let (min, max) = mesh.vertices.iter().fold(
+            (glam::Vec3::INFINITY, glam::Vec3::NEG_INFINITY),
+            |(min, max), v| (min.min(v.position), max.max(v.position)),
+        );
+
+        let positions = self.add_view(
+            mesh.vertices.iter().map(|v| v.position.to_array()),
+            ARRAY_BUFFER,
+        );
+        let positions = self.add_accessor(json!({
+            "bufferView": positions,
+            "componentType": FLOAT,
+            "count": mesh.vertices.len(),
+            "type": "VEC3",
+            "min": min.to_array(),
+            "max": max.to_array(),
+        }));
+
+        let normals = self.add_view(
+            mesh.vertices.iter().map(|v| v.normal.to_array()),
+            ARRAY_BUFFER,
+        );
+        let normals = self.add_accessor(json!({
+            "bufferView": normals,
+            "componentType": FLOAT,
+            "count": mesh.vertices.len(),
+            "type": "VEC3",
+        }));
+
+        let indices = self.add_view(mesh.indices.iter().copied(), ELEMENT_ARRAY_BUFFER);
+        let indices = self.add_accessor(json!({
+            "bufferView": indices,
+            "componentType": UNSIGNED_INT,
+            "count": mesh.indices.len(),
+            "type": "SCALAR",
+        }));
+
+        Primitive {
+            attributes: json!({ "POSITION": positions, "NORMAL": normals }),
+            indices,
+        }
+    }
+
+    fn add_view<T: bytemuck::Pod>(
+        &mut self,
+        items: impl IntoIterator<Item = T>,
+        target: u32,
+    ) -> usize {
+        let offset = self.bin.len();
+        for item in items {
+            self.bin.extend_from_slice(bytemuck::bytes_of(&item));
+        }
+        self.buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": self.bin.len() - offset,
+            "target": target,
+        }));
+        self.buffer_views.len() - 1
+    }
+
+    fn add_accessor(&mut self, accessor: serde_json::Value) -> usize {
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    /// Materials are per mesh in glTF, so each instance gets its own mesh
+    /// sharing `primitive`'s data.
+    fn add_instance(&mut self, primitive: &Primitive, instance: &ColoredInstance) {
+        self.materials.push(json!({
+            "pbrMetallicRoughness": {
+                "baseColorFactor": instance.color().to_array(),
+                "metallicFactor": 0.0,
+            },
+        }));
+        self.meshes.push(json!({
+            "primitives": [{
+                "attributes": primitive.attributes,
+                "indices": primitive.indices,
+                "material": self.materials.len() - 1,
+            }],
+        }));
+        self.nodes.push(json!({
+            "mesh": self.meshes.len() - 1,
+            "matrix": instance.model_matrix().to_cols_array(),
+        }));
+    }
+
+    fn finish(self) -> anyhow::Result<Vec<u8>> {
+        let document = json!({
+            "asset": { "version": "2.0", "generator": "passive-logic-challenge" },
+            "scene": 0,
+            "scenes": [{ "nodes": (0..self.nodes.len()).collect::<Vec<_>>() }],
+            "nodes": self.nodes,
+            "meshes": self.meshes,
+            "materials": self.materials,
+            "accessors": self.accessors,
+            "bufferViews": self.buffer_views,
+            "buffers": [{ "byteLength": self.bin.len() }],
+        });
+
+        // Chunks have to be 4 byte aligned, JSON padded with spaces
+        let mut json = serde_json::to_vec(&document)?;
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut bin = self.bin;
+        bin.resize(bin.len().next_multiple_of(4), 0);
+
+        let length = 12 + 8 + json.len() + 8 + bin.len();
+        let mut glb = Vec::with_capacity(length);
+        glb.extend_from_slice(GLB_MAGIC);
+        glb.extend_from_slice(&GLB_VERSION.to_le_bytes());
+        glb.extend_from_slice(&u32::try_from(length)?.to_le_bytes());
+        for (kind, data) in [(CHUNK_JSON, json), (CHUNK_BIN, bin)] {
+            glb.extend_from_slice(&u32::try_from(data.len())?.to_le_bytes());
+            glb.extend_from_slice(kind);
+            glb.extend_from_slice(&data);
+        }
+        Ok(glb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits a GLB into its JSON document and binary chunk.
+    fn parse_glb(glb: &[u8]) -> (serde_json::Value, &[u8]) {
+        let u32_at = |i: usize| u32::from_le_bytes(glb[i..i + 4].try_into().unwrap()) as usize;
+        assert_eq!(&glb[0..4], GLB_MAGIC);
+        assert_eq!(u32_at(8), glb.len());
+
+        let json_len = u32_at(12);
+        assert_eq!(&glb[16..20], CHUNK_JSON);
+        let document = serde_json::from_slice(&glb[20..20 + json_len]).unwrap();
+
+        let bin_start = 20 + json_len;
+        assert_eq!(&glb[bin_start + 4..bin_start + 8], CHUNK_BIN);
+        (document, &glb[bin_start + 8..])
+    }
+
+    #[test]
+    fn test_export_gltf() {
+        let mut sim = Simulation::new();
+        let a = sim.add_node(10.0, 100.0, 1.0, 100.0, glam::Vec3::ZERO);
+        let b = sim.add_node(10.0, 0.0, 1.0, 100.0, glam::Vec3::X);
+        let c = sim.add_node(10.0, 50.0, 1.0, 100.0, glam::Vec3::Y);
+        sim.connect_node(a, b, 1.0);
+        sim.connect_node(b, c, 1.0);
+
+        let path = std::env::temp_dir().join(format!("plc-export-{}.glb", std::process::id()));
+        sim.export_gltf(&path).unwrap();
+        let glb = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (document, bin) = parse_glb(&glb);
+        assert_eq!(document["nodes"].as_array().unwrap().len(), 5);
+        assert_eq!(document["meshes"].as_array().unwrap().len(), 5);
+        assert_eq!(document["scenes"][0]["nodes"].as_array().unwrap().len(), 5);
+        // Every instance shares the sphere's or cylinder's data
+        assert_eq!(document["accessors"].as_array().unwrap().len(), 6);
+        let byte_length = document["buffers"][0]["byteLength"].as_u64().unwrap() as usize;
+        assert!(byte_length <= bin.len() && bin.len() % 4 == 0);
+        // The first node is the hot one, at the origin
+        assert_eq!(
+            document["materials"][0]["pbrMetallicRoughness"]["baseColorFactor"],
+            json!([1.0, 0.0, 0.0, 1.0])
+        );
+        assert_eq!(document["nodes"][1]["matrix"][12], json!(1.0));
+    }
+}
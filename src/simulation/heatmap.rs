@@ -0,0 +1,307 @@
+//! A translucent overlay interpolating node temperatures across the screen.
+
+use crate::{
+    canvas::{COLD_COLOR, COLD_TEMP, HOT_COLOR, HOT_TEMP, WARM_COLOR},
+    resources::camera::Camera,
+    simulation::Simulation,
+};
+
+/// Node positions in NDC with their temperatures, as read by heatmap.wgsl.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct HeatmapPoint {
+    pub position: glam::Vec2,
+    pub temp: f32,
+    _padding: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct HeatmapParams {
+    cold_color: glam::Vec4,
+    warm_color: glam::Vec4,
+    hot_color: glam::Vec4,
+    cold_temp: f32,
+    hot_temp: f32,
+    radius: f32,
+    power: f32,
+    opacity: f32,
+    aspect: f32,
+    num_points: u32,
+    _padding: u32,
+}
+
+/// Normalized inverse distance weights for samples `distances` away, using
+/// `1 / d^power`. Samples `radius` or further away get no weight and one at
+/// distance zero gets all of it. `None` if every sample is out of range.
+pub fn idw_weights(distances: &[f32], power: f32, radius: f32) -> Option<Vec<f32>> {
+    if let Some(exact) = distances.iter().position(|&d| d == 0.0) {
+        let mut weights = vec![0.0; distances.len()];
+        weights[exact] = 1.0;
+        return Some(weights);
+    }
+
+    let mut weights = distances
+        .iter()
+        .map(|&d| if d < radius { d.powf(-power) } else { 0.0 })
+        .collect::<Vec<_>>();
+    let total = weights.iter().sum::<f32>();
+    if total == 0.0 {
+        return None;
+    }
+    for w in &mut weights {
+        *w /= total;
+    }
+    Some(weights)
+}
+
+/// Projects every node in front of `camera` into NDC.
+pub fn project_nodes(simulation: &Simulation, camera: &impl Camera) -> Vec<HeatmapPoint> {
+    let view_proj = camera.view_proj();
+    simulation
+        .nodes()
+        .iter()
+        .filter_map(|node| {
+            let clip = view_proj * node.position.extend(1.0);
+            (clip.w > 0.0).then(|| HeatmapPoint {
+                position: clip.truncate().truncate() / clip.w,
+                temp: node.fluid.temp,
+                _padding: 0.0,
+            })
+        })
+        .collect()
+}
+
+/// Draws the overlay with a fullscreen triangle. Each pixel blends the
+/// temperatures of the nodes within [`Self::radius`] of it by inverse
+/// distance. Every node is checked per pixel, so this is meant for hundreds
+/// of nodes, not millions.
+pub struct HeatmapPipeline {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    points_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    params: HeatmapParams,
+}
+
+impl HeatmapPipeline {
+    /// How far in NDC a node's temperature reaches by default.
+    pub const DEFAULT_RADIUS: f32 = 0.5;
+    pub const DEFAULT_POWER: f32 = 2.0;
+    pub const DEFAULT_OPACITY: f32 = 0.5;
+
+    pub fn new(
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("HeatmapPipeline::layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("HeatmapPipeline"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("heatmap.wgsl"));
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("HeatmapPipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            primitive: Default::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: Default::default(),
+            cache: None,
+        });
+
+        let params = HeatmapParams {
+            cold_color: COLD_COLOR.extend(1.0),
+            warm_color: WARM_COLOR.extend(1.0),
+            hot_color: HOT_COLOR.extend(1.0),
+            cold_temp: COLD_TEMP,
+            hot_temp: HOT_TEMP,
+            radius: Self::DEFAULT_RADIUS,
+            power: Self::DEFAULT_POWER,
+            opacity: Self::DEFAULT_OPACITY,
+            aspect: 1.0,
+            num_points: 0,
+            _padding: 0,
+        };
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("HeatmapPipeline::params_buffer"),
+            size: size_of::<HeatmapParams>() as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // Bindings can't be empty, so there's always room for one point
+        let points_buffer = Self::create_points_buffer(device, 1);
+        let bind_group = Self::bind(device, &layout, &params_buffer, &points_buffer);
+
+        Self {
+            pipeline,
+            layout,
+            params_buffer,
+            points_buffer,
+            bind_group,
+            params,
+        }
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.params.radius
+    }
+
+    /// Sets how far in NDC a node's temperature reaches.
+    pub fn set_radius(&mut self, radius: f32) {
+        self.params.radius = radius;
+    }
+
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.params.opacity = opacity.clamp(0.0, 1.0);
+    }
+
+    /// Uploads where the nodes of `simulation` are on screen and how hot
+    /// they are. `aspect_ratio` keeps the sampled area round.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        simulation: &Simulation,
+        camera: &impl Camera,
+        aspect_ratio: f32,
+    ) {
+        let points = project_nodes(simulation, camera);
+        let size = (points.len() * size_of::<HeatmapPoint>()) as wgpu::BufferAddress;
+        if size > self.points_buffer.size() {
+            self.points_buffer = Self::create_points_buffer(device, points.len());
+            self.bind_group = Self::bind(
+                device,
+                &self.layout,
+                &self.params_buffer,
+                &self.points_buffer,
+            );
+        }
+        queue.write_buffer(&self.points_buffer, 0, bytemuck::cast_slice(&points));
+
+        self.params.num_points = points.len() as _;
+        self.params.aspect = aspect_ratio;
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&self.params));
+    }
+
+    pub fn draw(&self, pass: &mut wgpu::RenderPass<'_>) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    fn create_points_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("HeatmapPipeline::points_buffer"),
+            size: (capacity.max(1) * size_of::<HeatmapPoint>()) as _,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn bind(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        params_buffer: &wgpu::Buffer,
+        points_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("HeatmapPipeline::bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: points_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::camera::OrthoCamera;
+
+    #[test]
+    fn test_idw_weights() {
+        // Half the distance is four times the weight with the default power
+        let weights = idw_weights(&[0.1, 0.2], 2.0, 1.0).unwrap();
+        assert!((weights[0] - 0.8).abs() < 1e-6);
+        assert!((weights[1] - 0.2).abs() < 1e-6);
+
+        let weights = idw_weights(&[0.3, 0.3, 0.3], 2.0, 1.0).unwrap();
+        assert!(weights.iter().all(|w| (w - 1.0 / 3.0).abs() < 1e-6));
+
+        // Out of range samples don't count, and exact hits take everything
+        assert_eq!(idw_weights(&[0.5, 2.0], 2.0, 1.0), Some(vec![1.0, 0.0]));
+        assert_eq!(idw_weights(&[0.5, 0.0], 2.0, 1.0), Some(vec![0.0, 1.0]));
+        assert_eq!(idw_weights(&[1.0, 2.0], 2.0, 1.0), None);
+        assert_eq!(idw_weights(&[], 2.0, 1.0), None);
+    }
+
+    #[test]
+    fn test_project_nodes() {
+        let mut sim = Simulation::new();
+        sim.add_node(10.0, 40.0, 1.0, 100.0, glam::vec3(1.0, -1.0, 0.0));
+        let camera = OrthoCamera::new(-2.0, 2.0, -2.0, 2.0);
+
+        let points = project_nodes(&sim, &camera);
+        assert_eq!(points.len(), 1);
+        assert!(points[0].position.abs_diff_eq(glam::vec2(0.5, -0.5), 1e-6));
+        assert_eq!(points[0].temp, 40.0);
+    }
+}
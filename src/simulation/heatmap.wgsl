@@ -0,0 +1,79 @@
+struct HeatmapParams {
+    cold_color: vec4<f32>,
+    warm_color: vec4<f32>,
+    hot_color: vec4<f32>,
+    cold_temp: f32,
+    hot_temp: f32,
+    radius: f32,
+    power: f32,
+    opacity: f32,
+    aspect: f32,
+    num_points: u32,
+}
+
+struct HeatmapPoint {
+    position: vec2<f32>,
+    temp: f32,
+}
+
+@group(0)
+@binding(0)
+var<uniform> params: HeatmapParams;
+
+@group(0)
+@binding(1)
+var<storage, read> points: array<HeatmapPoint>;
+
+struct VsOut {
+    @builtin(position)
+    frag_position: vec4<f32>,
+    @location(0)
+    ndc: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) i: u32) -> VsOut {
+    let uv = vec2(f32(i % 2u), f32(i > 1u)) * 2.0;
+    let ndc = uv * 2.0 - 1.0;
+    return VsOut(vec4(ndc, 0.0, 1.0), ndc);
+}
+
+// Mirrors `idw_weights` in heatmap.rs
+@fragment
+fn fs_main(vs: VsOut) -> @location(0) vec4<f32> {
+    let scale = vec2(params.aspect, 1.0);
+    var total_weight = 0.0;
+    var weighted_temp = 0.0;
+    var nearest = params.radius;
+    for (var i = 0u; i < params.num_points; i++) {
+        let point = points[i];
+        let d = distance(vs.ndc * scale, point.position * scale);
+        if d >= params.radius {
+            continue;
+        }
+        nearest = min(nearest, d);
+        if d == 0.0 {
+            total_weight = 1.0;
+            weighted_temp = point.temp;
+            break;
+        }
+        let w = 1.0 / pow(d, params.power);
+        total_weight += w;
+        weighted_temp += w * point.temp;
+    }
+
+    if total_weight == 0.0 {
+        discard;
+    }
+
+    let temp = weighted_temp / total_weight;
+    let s = clamp((temp - params.cold_temp) / (params.hot_temp - params.cold_temp), 0.0, 1.0);
+    // Mirrors `temperature_to_color_in` in canvas.rs
+    var color = mix(params.warm_color.rgb, params.hot_color.rgb, s * 2.0 - 1.0);
+    if s < 0.5 {
+        color = mix(params.cold_color.rgb, params.warm_color.rgb, s * 2.0);
+    }
+    // Fade out towards the edge of the sampled area
+    let alpha = params.opacity * (1.0 - nearest / params.radius);
+    return vec4(color, alpha);
+}
@@ -0,0 +1,115 @@
+//! Draws the node graph on the CPU, for reports on machines without a GPU.
+
+use crate::{
+    canvas::{connection_color, node_color},
+    resources::camera::{Camera, OrthoCamera},
+    simulation::Simulation,
+};
+
+/// Radius of each node in pixels.
+const NODE_RADIUS: f32 = 4.0;
+const BACKGROUND: image::Rgba<u8> = image::Rgba([0, 0, 0, 255]);
+
+pub trait Schematic {
+    /// Draws nodes as circles and connections as lines, colored by
+    /// temperature and tint like the 3D view. Only x and y are used, so
+    /// `camera` looks down the z axis.
+    fn render_schematic(&self, width: u32, height: u32, camera: &OrthoCamera) -> image::RgbaImage;
+}
+
+impl Schematic for Simulation {
+    fn render_schematic(&self, width: u32, height: u32, camera: &OrthoCamera) -> image::RgbaImage {
+        let mut image = image::RgbaImage::from_pixel(width, height, BACKGROUND);
+        let view_proj = camera.view_proj();
+        let to_pixel = |position: glam::Vec3| {
+            let ndc = view_proj.transform_point3(position.with_z(0.0));
+            glam::vec2(
+                (ndc.x + 1.0) * 0.5 * width as f32,
+                (1.0 - ndc.y) * 0.5 * height as f32,
+            )
+        };
+
+        // Connections first so the nodes cover their ends
+        for (_, input, output) in self.connected_nodes() {
+            let color = to_rgba(connection_color(input));
+            draw_line(
+                &mut image,
+                to_pixel(input.position),
+                to_pixel(output.position),
+                color,
+            );
+        }
+
+        for node in self.nodes() {
+            let color = to_rgba(node_color(node));
+            draw_circle(&mut image, to_pixel(node.position), NODE_RADIUS, color);
+        }
+
+        image
+    }
+}
+
+fn to_rgba(color: glam::Vec3) -> image::Rgba<u8> {
+    let [r, g, b] = (color.clamp(glam::Vec3::ZERO, glam::Vec3::ONE) * 255.0)
+        .round()
+        .to_array()
+        .map(|c| c as u8);
+    image::Rgba([r, g, b, 255])
+}
+
+fn put_pixel(image: &mut image::RgbaImage, x: i64, y: i64, color: image::Rgba<u8>) {
+    if (0..image.width() as i64).contains(&x) && (0..image.height() as i64).contains(&y) {
+        image.put_pixel(x as u32, y as u32, color);
+    }
+}
+
+fn draw_line(image: &mut image::RgbaImage, a: glam::Vec2, b: glam::Vec2, color: image::Rgba<u8>) {
+    let steps = (b - a).abs().max_element().ceil().max(1.0);
+    for i in 0..=steps as i64 {
+        let p = a.lerp(b, i as f32 / steps);
+        put_pixel(image, p.x.round() as i64, p.y.round() as i64, color);
+    }
+}
+
+fn draw_circle(
+    image: &mut image::RgbaImage,
+    center: glam::Vec2,
+    radius: f32,
+    color: image::Rgba<u8>,
+) {
+    let min = (center - radius).floor();
+    let max = (center + radius).ceil();
+    for y in min.y as i64..=max.y as i64 {
+        for x in min.x as i64..=max.x as i64 {
+            let pixel_center = glam::vec2(x as f32 + 0.5, y as f32 + 0.5);
+            if pixel_center.distance_squared(center) <= radius * radius {
+                put_pixel(image, x, y, color);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_schematic() {
+        let mut sim = Simulation::new();
+        let hot = sim.add_node(10.0, 100.0, 1.0, 100.0, glam::vec3(-1.0, 0.0, 0.0));
+        let cold = sim.add_node(10.0, 0.0, 1.0, 100.0, glam::vec3(1.0, 0.0, 0.0));
+        sim.connect_node(hot, cold, 1.0);
+
+        let camera = OrthoCamera::new(-2.0, 2.0, -2.0, 2.0);
+        let image = sim.render_schematic(64, 64, &camera);
+
+        let red = image::Rgba([255, 0, 0, 255]);
+        let blue = image::Rgba([0, 0, 255, 255]);
+        assert_eq!(*image.get_pixel(16, 32), red);
+        assert_eq!(*image.get_pixel(48, 32), blue);
+        // The connection takes the color of its input
+        assert_eq!(*image.get_pixel(32, 32), red);
+        assert_eq!(*image.get_pixel(0, 0), BACKGROUND);
+        assert_eq!(*image.get_pixel(32, 10), BACKGROUND);
+    }
+}
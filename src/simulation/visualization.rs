@@ -1,17 +1,71 @@
 use crate::resources::{
     buffer::BackedBuffer, camera::{CameraBinder, CameraBinding}, model::{ModelId, ModelPipeline}, vertex::{ColoredInstance, NormalMappedVertex}
 };
+use crate::utils::WireframePipelines;
+
+/// Pulls connections slightly towards the camera so they don't z-fight
+/// with the node surfaces they meet. Pass it as the `bias` of
+/// [`VisualizationPipeline::new`].
+pub const CONNECTION_DEPTH_BIAS: wgpu::DepthBiasState = wgpu::DepthBiasState {
+    constant: -2,
+    slope_scale: -1.0,
+    clamp: 0.0,
+};
 
 pub struct VisualizationPipeline {
-    pipeline: wgpu::RenderPipeline,
+    pipelines: WireframePipelines,
+    bias: wgpu::DepthBiasState,
 }
 
 impl VisualizationPipeline {
+    /// `bias` offsets the depth of everything drawn with this pipeline, e.g.
+    /// so connections win against the node surfaces they touch instead of
+    /// z-fighting. Use [`wgpu::DepthBiasState::default`] for no bias.
     pub fn new(
         device: &wgpu::Device,
         surface_format: wgpu::TextureFormat,
         depth_format: wgpu::TextureFormat,
         camera_binder: &CameraBinder,
+        bias: wgpu::DepthBiasState,
+        sample_count: u32,
+    ) -> Self {
+        Self::with_fragment(
+            device,
+            surface_format,
+            depth_format,
+            camera_binder,
+            "fs_main",
+            bias,
+            sample_count,
+        )
+    }
+
+    /// Draws instance ids into a [`PickingTarget`] instead of colors. Ids
+    /// can't be blended, so this is never multisampled.
+    pub fn new_id(
+        device: &wgpu::Device,
+        depth_format: wgpu::TextureFormat,
+        camera_binder: &CameraBinder,
+    ) -> Self {
+        Self::with_fragment(
+            device,
+            PickingTarget::FORMAT,
+            depth_format,
+            camera_binder,
+            "fs_id",
+            Default::default(),
+            1,
+        )
+    }
+
+    fn with_fragment(
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        camera_binder: &CameraBinder,
+        fragment_entry: &str,
+        bias: wgpu::DepthBiasState,
+        sample_count: u32,
     ) -> Self {
         let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("VisualizationPipeline"),
@@ -21,66 +75,276 @@ impl VisualizationPipeline {
 
         let shader = device.create_shader_module(wgpu::include_wgsl!("visualization.wgsl"));
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("VisualizationPipeline"),
-            layout: Some(&layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                compilation_options: Default::default(),
-                buffers: &[NormalMappedVertex::VB_DESC, ColoredInstance::VB_DESC],
-            },
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                ..Default::default()
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: depth_format,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: Default::default(),
-                bias: Default::default(),
-            }),
-            multisample: Default::default(),
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                compilation_options: Default::default(),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format,
-                    blend: None,
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            multiview: Default::default(),
-            cache: None,
+        let pipelines = WireframePipelines::new(device, |polygon_mode| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("VisualizationPipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[NormalMappedVertex::VB_DESC, ColoredInstance::VB_DESC],
+                },
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    polygon_mode,
+                    ..Default::default()
+                },
+                depth_stencil: Some(depth_stencil_state(depth_format, bias)),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(fragment_entry),
+                    compilation_options: Default::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: surface_format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                multiview: Default::default(),
+                cache: None,
+            })
         });
 
-        Self { pipeline }
+        Self { pipelines, bias }
     }
 
-    pub(crate) fn draw(
+    pub fn bias(&self) -> wgpu::DepthBiasState {
+        self.bias
+    }
+
+    pub fn wireframe(&self) -> bool {
+        self.pipelines.wireframe()
+    }
+
+    /// Like [`ModelPipeline::set_wireframe`], draws only the edges of
+    /// triangles if the device supports it.
+    pub fn set_wireframe(&mut self, wireframe: bool) -> bool {
+        self.pipelines.set_wireframe(wireframe)
+    }
+
+    /// Returns the number of instances drawn, counting each mesh of the
+    /// model separately.
+    pub fn draw(
         &self,
         pass: &mut wgpu::RenderPass<'_>,
         model: ModelId,
         model_pipeline: &ModelPipeline,
         camera: &CameraBinding,
         instances: &BackedBuffer<ColoredInstance>,
-    ) {
-        let model = if let Some(model) = model_pipeline.get_model(model) {
-            model
-        } else {
-            return;
-        };
+    ) -> u32 {
+        self.draw_batch(pass, &[(model, instances)], model_pipeline, camera)
+    }
 
-        pass.set_pipeline(&self.pipeline);
+    /// Draws several models, only binding the pipeline and camera once.
+    pub fn draw_batch(
+        &self,
+        pass: &mut wgpu::RenderPass<'_>,
+        batch: &[(ModelId, &BackedBuffer<ColoredInstance>)],
+        model_pipeline: &ModelPipeline,
+        camera: &CameraBinding,
+    ) -> u32 {
+        pass.set_pipeline(self.pipelines.current());
         pass.set_bind_group(0, camera.bind_group(), &[]);
-        pass.set_vertex_buffer(1, instances.slice());
 
-        for mesh in &model.meshes {
-            pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-            pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-            pass.draw_indexed(0..mesh.num_elements, 0, 0..instances.len());
+        let mut drawn = 0;
+        for &(model, instances) in batch {
+            if instances.is_empty() {
+                continue;
+            }
+
+            let model = if let Some(model) = model_pipeline.get_model(model) {
+                model
+            } else {
+                continue;
+            };
+
+            pass.set_vertex_buffer(1, instances.slice());
+
+            for mesh in &model.meshes {
+                pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                pass.draw_indexed(0..mesh.num_elements, 0, 0..instances.len());
+                drawn += instances.len();
+            }
         }
+
+        drawn
+    }
+}
+
+fn depth_stencil_state(
+    format: wgpu::TextureFormat,
+    bias: wgpu::DepthBiasState,
+) -> wgpu::DepthStencilState {
+    wgpu::DepthStencilState {
+        format,
+        depth_write_enabled: true,
+        depth_compare: wgpu::CompareFunction::Less,
+        stencil: Default::default(),
+        bias,
+    }
+}
+
+/// Offscreen instance id buffer for picking. Render into it with a pipeline
+/// from [`VisualizationPipeline::new_id`] and read back the id under the
+/// cursor with [`PickingTarget::read_id`].
+pub struct PickingTarget {
+    id_texture: wgpu::Texture,
+    id_view: wgpu::TextureView,
+    depth_view: wgpu::TextureView,
+    readback: wgpu::Buffer,
+}
+
+impl PickingTarget {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        depth_format: wgpu::TextureFormat,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let id_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("PickingTarget::id_texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("PickingTarget::depth_texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: depth_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        // Only ever holds one texel, but copies need the row to be aligned
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("PickingTarget::readback"),
+            size: wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as _,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            id_view: id_texture.create_view(&Default::default()),
+            depth_view: depth_texture.create_view(&Default::default()),
+            id_texture,
+            readback,
+        }
+    }
+
+    /// Starts a pass that clears the ids to "nothing".
+    pub fn begin_pass<'a>(&self, encoder: &'a mut wgpu::CommandEncoder) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("PickingTarget"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.id_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            ..Default::default()
+        })
+    }
+
+    /// Returns the index of the instance drawn at `(x, y)`, or `None` if
+    /// nothing was drawn there. Blocks until the GPU is done.
+    pub fn read_id(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        x: u32,
+        y: u32,
+    ) -> anyhow::Result<Option<u32>> {
+        let size = self.id_texture.size();
+        if x >= size.width || y >= size.height {
+            return Ok(None);
+        }
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.id_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &self.readback,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: Some(1),
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit([encoder.finish()]);
+
+        let slice = self.readback.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::PollType::Wait)?;
+        receiver.recv()??;
+
+        let id = {
+            let data = slice.get_mapped_range();
+            u32::from_ne_bytes([data[0], data[1], data[2], data[3]])
+        };
+        self.readback.unmap();
+
+        Ok(id.checked_sub(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_depth_stencil_state_bias() {
+        let bias = wgpu::DepthBiasState {
+            constant: -2,
+            slope_scale: -1.0,
+            clamp: 0.0,
+        };
+        let state = depth_stencil_state(wgpu::TextureFormat::Depth32Float, bias);
+        assert_eq!(state.bias, bias);
+        assert_eq!(state.format, wgpu::TextureFormat::Depth32Float);
+        let unbiased = depth_stencil_state(wgpu::TextureFormat::Depth32Float, Default::default());
+        assert!(!unbiased.bias.is_enabled());
     }
 }
@@ -23,12 +23,14 @@ struct InstanceInput {
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) color: vec4<f32>,
+    @location(1) @interpolate(flat) instance_index: u32,
 }
 
 @vertex
 fn vs_main(
     model: VertexInput,
     instance: InstanceInput,
+    @builtin(instance_index) instance_index: u32,
 ) -> VertexOutput {
     let model_matrix = mat4x4<f32>(
         instance.model_matrix_0,
@@ -42,10 +44,17 @@ fn vs_main(
     var out: VertexOutput;
     out.clip_position = camera.view_proj * world_position;
     out.color = instance.color;
+    out.instance_index = instance_index;
     return out;
 }
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     return in.color;
-}
\ No newline at end of file
+}
+
+// Writes instance ids for picking, offset by one so that 0 means nothing
+@fragment
+fn fs_id(in: VertexOutput) -> @location(0) u32 {
+    return in.instance_index + 1u;
+}
@@ -80,6 +80,11 @@ impl<'a> RenderPipelineBuilder<'a> {
         self
     }
 
+    pub fn sample_count(mut self, count: u32) -> Self {
+        self.multisample.count = count;
+        self
+    }
+
     pub fn build(self, device: &wgpu::Device) -> anyhow::Result<wgpu::RenderPipeline> {
         Ok(
             device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
@@ -97,6 +102,192 @@ impl<'a> RenderPipelineBuilder<'a> {
     }
 }
 
+/// A filled pipeline and, if the device supports
+/// [`wgpu::Features::POLYGON_MODE_LINE`], a wireframe one to switch to.
+pub struct WireframePipelines {
+    fill: wgpu::RenderPipeline,
+    line: Option<wgpu::RenderPipeline>,
+    wireframe: bool,
+}
+
+impl WireframePipelines {
+    /// Calls `create_pipeline` once per polygon mode the device supports.
+    pub fn new(
+        device: &wgpu::Device,
+        create_pipeline: impl Fn(wgpu::PolygonMode) -> wgpu::RenderPipeline,
+    ) -> Self {
+        let fill = create_pipeline(wgpu::PolygonMode::Fill);
+        let line = device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE)
+            .then(|| create_pipeline(wgpu::PolygonMode::Line));
+        Self {
+            fill,
+            line,
+            wireframe: false,
+        }
+    }
+
+    pub fn wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    /// Warns and returns `false` if there's no wireframe pipeline.
+    pub fn set_wireframe(&mut self, wireframe: bool) -> bool {
+        if wireframe && self.line.is_none() {
+            log::warn!("Wireframes need POLYGON_MODE_LINE, which the device lacks");
+            return false;
+        }
+        self.wireframe = wireframe;
+        true
+    }
+
+    /// The pipeline to draw with.
+    pub fn current(&self) -> &wgpu::RenderPipeline {
+        match &self.line {
+            Some(line) if self.wireframe => line,
+            _ => &self.fill,
+        }
+    }
+}
+
 pub fn rev_lerp(a: f32, b: f32, c: f32) -> f32 {
     (c - a) / (b - a)
-}
\ No newline at end of file
+}
+
+/// How far along `dir` from `origin` the ray first touches the sphere, or
+/// `None` if it misses or the sphere is behind it. `dir` must be normalized.
+/// Starting inside the sphere counts as a hit at zero.
+pub fn ray_sphere_intersection(
+    origin: glam::Vec3,
+    dir: glam::Vec3,
+    center: glam::Vec3,
+    radius: f32,
+) -> Option<f32> {
+    let to_center = center - origin;
+    let along = to_center.dot(dir);
+    let miss_sq = to_center.length_squared() - along * along;
+    let radius_sq = radius * radius;
+    if miss_sq > radius_sq {
+        return None;
+    }
+    let half_chord = (radius_sq - miss_sq).sqrt();
+    let (near, far) = (along - half_chord, along + half_chord);
+    if far < 0.0 {
+        None
+    } else {
+        Some(near.max(0.0))
+    }
+}
+/// Set to use wgpu's fallback (software) adapter even if a hardware adapter is
+/// available. Useful for CI runners without a GPU.
+pub const FORCE_FALLBACK_ADAPTER_ENV: &str = "PLC_FORCE_FALLBACK_ADAPTER";
+
+/// Requests a hardware adapter, falling back to a software one (such as
+/// llvmpipe via GL) if that fails or [`FORCE_FALLBACK_ADAPTER_ENV`] is set.
+pub async fn request_adapter(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface<'_>>,
+) -> anyhow::Result<wgpu::Adapter> {
+    let force_fallback_adapter = std::env::var_os(FORCE_FALLBACK_ADAPTER_ENV).is_some();
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter,
+            compatible_surface,
+        })
+        .await
+        .context("No compatible adapter");
+
+    match adapter {
+        Ok(adapter) => Ok(adapter),
+        Err(e) if force_fallback_adapter => Err(e),
+        Err(e) => {
+            log::warn!("{e}, trying the fallback adapter");
+            instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    force_fallback_adapter: true,
+                    compatible_surface,
+                    ..Default::default()
+                })
+                .await
+                .context("No compatible fallback adapter")
+        }
+    }
+}
+
+/// Exponential moving average, used to smooth out noisy per frame values.
+#[derive(Debug, Clone, Copy)]
+pub struct Ema {
+    value: Option<f32>,
+    smoothing: f32,
+}
+
+impl Ema {
+    /// `smoothing` is the weight given to each new sample, between 0 and 1.
+    pub fn new(smoothing: f32) -> Self {
+        Self {
+            value: None,
+            smoothing: smoothing.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn update(&mut self, sample: f32) -> f32 {
+        let value = match self.value {
+            Some(value) => value + (sample - value) * self.smoothing,
+            None => sample,
+        };
+        self.value = Some(value);
+        value
+    }
+
+    pub fn value(&self) -> Option<f32> {
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ray_sphere_intersection() {
+        let origin = glam::vec3(0.0, 0.0, -5.0);
+        let hit = ray_sphere_intersection(origin, glam::Vec3::Z, glam::Vec3::ZERO, 1.0);
+        assert_eq!(hit, Some(4.0));
+
+        // Grazing the edge still hits, just past it doesn't
+        let edge = glam::vec3(1.0, 0.0, -5.0);
+        assert!(ray_sphere_intersection(edge, glam::Vec3::Z, glam::Vec3::ZERO, 1.0).is_some());
+        let past = glam::vec3(1.01, 0.0, -5.0);
+        assert_eq!(
+            ray_sphere_intersection(past, glam::Vec3::Z, glam::Vec3::ZERO, 1.0),
+            None
+        );
+
+        // Behind the ray, and starting inside
+        assert_eq!(
+            ray_sphere_intersection(origin, -glam::Vec3::Z, glam::Vec3::ZERO, 1.0),
+            None
+        );
+        assert_eq!(
+            ray_sphere_intersection(glam::Vec3::ZERO, glam::Vec3::Z, glam::Vec3::ZERO, 1.0),
+            Some(0.0)
+        );
+    }
+
+    #[test]
+    fn test_ema_converges() {
+        let mut ema = Ema::new(0.1);
+        assert_eq!(ema.value(), None);
+        assert_eq!(ema.update(1.0), 1.0);
+
+        // A single hitch only nudges the average
+        assert!(ema.update(11.0) < 3.0);
+
+        for _ in 0..200 {
+            ema.update(5.0);
+        }
+        assert!((ema.value().unwrap() - 5.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,1108 @@
+//! Golden image tests for the visualization.
+//!
+//! Each scene is rendered offscreen, read back and compared against a PNG in
+//! `tests/golden`. Run with `UPDATE_GOLDEN=1` to regenerate the references.
+
+use std::path::PathBuf;
+
+use passive_logic_challenge::{
+    resources::{
+        buffer::BackedBuffer,
+        camera::{CameraBinder, CameraBinding, OrthoCamera, PerspectiveCamera},
+        font::{Alignment, Font, Label, TextLayout, TextPipeline},
+        grid::GridPipeline,
+        light::{
+            DirectionalLight, LightBinder, LightBinding, LightUniform, MAX_UNIFORM_LIGHTS,
+            SHADOW_MAP_FORMAT, SHADOW_MAP_SIZE,
+        },
+        model::{MaterialBinder, MeshData, ModelId, ModelPipeline},
+        shadow::ShadowPipeline,
+        texture::{supported_sample_count, SamplerOptions, Texture, TextureBinder},
+        vertex::{ColoredInstance, InstanceVertex},
+        FsResources,
+    },
+    simulation::{
+        heatmap::HeatmapPipeline,
+        visualization::{PickingTarget, VisualizationPipeline},
+    },
+};
+
+const WIDTH: u32 = 128;
+const HEIGHT: u32 = 128;
+const COLOR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// Maximum per channel difference before a pixel counts as mismatched.
+const PIXEL_TOLERANCE: u8 = 8;
+/// Fraction of pixels allowed to mismatch. Different rasterizers disagree on
+/// some edge pixels, so this can't be zero.
+const MISMATCH_TOLERANCE: f32 = 0.01;
+
+fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    device_with_features(wgpu::Features::empty())
+}
+
+/// Like [`device`] but enabling whichever of `features` the adapter has.
+fn device_with_features(features: wgpu::Features) -> Option<(wgpu::Device, wgpu::Queue)> {
+    request_device(&adapter()?, features)
+}
+
+fn adapter() -> Option<wgpu::Adapter> {
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..Default::default()
+    });
+    pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        force_fallback_adapter: std::env::var_os("PLC_FORCE_FALLBACK_ADAPTER").is_some(),
+        ..Default::default()
+    }))
+    .ok()
+}
+
+fn request_device(
+    adapter: &wgpu::Adapter,
+    features: wgpu::Features,
+) -> Option<(wgpu::Device, wgpu::Queue)> {
+    pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+        required_features: adapter.features() & features,
+        required_limits: wgpu::Limits::downlevel_defaults(),
+        ..Default::default()
+    }))
+    .ok()
+}
+
+/// Two nodes joined by a connection, as the visualization draws them.
+struct TwoNodes {
+    camera_binder: CameraBinder,
+    model_pipeline: ModelPipeline,
+    node_model: ModelId,
+    connection_model: ModelId,
+    camera_binding: CameraBinding,
+    node_instances: BackedBuffer<ColoredInstance>,
+    connection_instances: BackedBuffer<ColoredInstance>,
+}
+
+impl TwoNodes {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let res = FsResources::new(concat!(env!("CARGO_MANIFEST_DIR"), "/res"));
+
+        let camera_binder = CameraBinder::new(device);
+        let material_binder = MaterialBinder::new(device);
+        let light_binder = LightBinder::new(device);
+        let mut model_pipeline = ModelPipeline::new(
+            device,
+            COLOR_FORMAT,
+            DEPTH_FORMAT,
+            &camera_binder,
+            &material_binder,
+            &light_binder,
+            1,
+        );
+        let node_model = model_pipeline
+            .load_obj(
+                device,
+                queue,
+                &material_binder,
+                &res,
+                "models/spherical-cube.obj",
+            )
+            .unwrap();
+        let connection_model = model_pipeline
+            .load_obj(
+                device,
+                queue,
+                &material_binder,
+                &res,
+                "models/connection.obj",
+            )
+            .unwrap();
+
+        let camera = PerspectiveCamera::new(
+            glam::vec3(0.0, 0.0, 2.0),
+            -std::f32::consts::FRAC_PI_2,
+            0.0,
+            WIDTH,
+            HEIGHT,
+            std::f32::consts::FRAC_PI_4,
+            0.1,
+            100.0,
+        );
+        let camera_binding = camera_binder.bind(device, &camera);
+
+        let hot = glam::vec3(1.0, 0.0, 0.0);
+        let cold = glam::vec3(0.0, 0.0, 1.0);
+        let a = glam::vec3(-0.5, 0.0, 0.0);
+        let b = glam::vec3(0.5, 0.0, 0.0);
+        let node_instances = BackedBuffer::with_data(
+            device,
+            vec![
+                ColoredInstance::with_position_scale(hot, a, 0.1),
+                ColoredInstance::with_position_scale(cold, b, 0.1),
+            ],
+            wgpu::BufferUsages::VERTEX,
+        );
+        let connection_instances = BackedBuffer::with_data(
+            device,
+            vec![ColoredInstance::extend_between(
+                hot.lerp(cold, 0.5),
+                a,
+                b,
+                0.02,
+            )],
+            wgpu::BufferUsages::VERTEX,
+        );
+
+        Self {
+            camera_binder,
+            model_pipeline,
+            node_model,
+            connection_model,
+            camera_binding,
+            node_instances,
+            connection_instances,
+        }
+    }
+}
+
+/// Renders the scene and returns the image along with the number of instances
+/// drawn. `batched` draws both models with a single `draw_batch` call.
+fn render_two_nodes(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    batched: bool,
+) -> (image::RgbaImage, u32) {
+    let scene = TwoNodes::new(device, queue);
+    let visualization_pipeline = VisualizationPipeline::new(
+        device,
+        COLOR_FORMAT,
+        DEPTH_FORMAT,
+        &scene.camera_binder,
+        Default::default(),
+        1,
+    );
+
+    let size = wgpu::Extent3d {
+        width: WIDTH,
+        height: HEIGHT,
+        depth_or_array_layers: 1,
+    };
+    let color_texture = create_color_texture(device);
+    let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("depth"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    let depth_view = depth_texture.create_view(&Default::default());
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    let drawn = {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &color_texture.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            ..Default::default()
+        });
+
+        if batched {
+            visualization_pipeline.draw_batch(
+                &mut pass,
+                &[
+                    (scene.node_model, &scene.node_instances),
+                    (scene.connection_model, &scene.connection_instances),
+                ],
+                &scene.model_pipeline,
+                &scene.camera_binding,
+            )
+        } else {
+            visualization_pipeline.draw(
+                &mut pass,
+                scene.node_model,
+                &scene.model_pipeline,
+                &scene.camera_binding,
+                &scene.node_instances,
+            ) + visualization_pipeline.draw(
+                &mut pass,
+                scene.connection_model,
+                &scene.model_pipeline,
+                &scene.camera_binding,
+                &scene.connection_instances,
+            )
+        }
+    };
+    queue.submit([encoder.finish()]);
+
+    (color_texture.read_to_image(device, queue).unwrap(), drawn)
+}
+
+/// Returns the largest channel difference and the number of pixels whose
+/// difference exceeds `tolerance`.
+fn compare_images(a: &image::RgbaImage, b: &image::RgbaImage, tolerance: u8) -> (u8, usize) {
+    assert_eq!(a.dimensions(), b.dimensions());
+
+    let mut max_diff = 0;
+    let mut mismatched = 0;
+    for (pa, pb) in a.pixels().zip(b.pixels()) {
+        let diff =
+            pa.0.iter()
+                .zip(pb.0)
+                .map(|(ca, cb)| ca.abs_diff(cb))
+                .max()
+                .unwrap_or(0);
+        max_diff = max_diff.max(diff);
+        if diff > tolerance {
+            mismatched += 1;
+        }
+    }
+
+    (max_diff, mismatched)
+}
+
+fn check_golden(name: &str, actual: &image::RgbaImage) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        actual.save(&path).unwrap();
+        return;
+    }
+
+    let expected = image::open(&path)
+        .unwrap_or_else(|e| panic!("{}: {e}, run with UPDATE_GOLDEN=1", path.display()))
+        .to_rgba8();
+
+    let (max_diff, mismatched) = compare_images(&expected, actual, PIXEL_TOLERANCE);
+    let allowed = (actual.len() / 4) as f32 * MISMATCH_TOLERANCE;
+    assert!(
+        mismatched as f32 <= allowed,
+        "{name}: {mismatched} pixels differ from the reference (max diff {max_diff})"
+    );
+}
+
+#[test]
+fn test_two_nodes_golden() {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+
+    let (image, _) = render_two_nodes(&device, &queue, false);
+    check_golden("two_nodes", &image);
+}
+
+#[test]
+fn test_draw_batch_matches_separate_draws() {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+
+    let (separate, separate_drawn) = render_two_nodes(&device, &queue, false);
+    let (batched, batched_drawn) = render_two_nodes(&device, &queue, true);
+
+    assert_eq!(separate_drawn, 3);
+    assert_eq!(batched_drawn, separate_drawn);
+    assert_eq!(compare_images(&separate, &batched, 0), (0, 0));
+}
+
+#[test]
+fn test_pick_node_id() {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+
+    let scene = TwoNodes::new(&device, &queue);
+    let id_pipeline = VisualizationPipeline::new_id(&device, DEPTH_FORMAT, &scene.camera_binder);
+    let target = PickingTarget::new(&device, WIDTH, HEIGHT, DEPTH_FORMAT);
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let mut pass = target.begin_pass(&mut encoder);
+        id_pipeline.draw(
+            &mut pass,
+            scene.node_model,
+            &scene.model_pipeline,
+            &scene.camera_binding,
+            &scene.node_instances,
+        );
+    }
+    queue.submit([encoder.finish()]);
+
+    // The nodes project to roughly x = 25 and x = 102
+    let y = HEIGHT / 2;
+    assert_eq!(target.read_id(&device, &queue, 25, y).unwrap(), Some(0));
+    assert_eq!(target.read_id(&device, &queue, 102, y).unwrap(), Some(1));
+    assert_eq!(target.read_id(&device, &queue, WIDTH / 2, y).unwrap(), None);
+    assert_eq!(target.read_id(&device, &queue, 0, 0).unwrap(), None);
+}
+
+#[test]
+fn test_wireframe_pipelines() {
+    let Some((device, queue)) = device_with_features(wgpu::Features::POLYGON_MODE_LINE) else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+    let supported = device
+        .features()
+        .contains(wgpu::Features::POLYGON_MODE_LINE);
+    if !supported {
+        eprintln!("No POLYGON_MODE_LINE, only checking the fallback");
+    }
+
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let mut scene = TwoNodes::new(&device, &queue);
+    let mut visualization_pipeline = VisualizationPipeline::new(
+        &device,
+        COLOR_FORMAT,
+        DEPTH_FORMAT,
+        &scene.camera_binder,
+        Default::default(),
+        1,
+    );
+    let error = pollster::block_on(device.pop_error_scope());
+    assert!(error.is_none(), "{error:?}");
+
+    // Without the feature both stay filled
+    assert_eq!(scene.model_pipeline.set_wireframe(true), supported);
+    assert_eq!(scene.model_pipeline.wireframe(), supported);
+    assert_eq!(visualization_pipeline.set_wireframe(true), supported);
+    assert_eq!(visualization_pipeline.wireframe(), supported);
+    assert!(visualization_pipeline.set_wireframe(false));
+    assert!(!visualization_pipeline.wireframe());
+}
+
+#[test]
+fn test_msaa_pipelines() {
+    let Some(adapter) = adapter() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+    let (device, queue) = request_device(&adapter, Default::default()).unwrap();
+    // Never more than asked for
+    assert_eq!(
+        supported_sample_count(&adapter, &device, COLOR_FORMAT, DEPTH_FORMAT, 1),
+        1
+    );
+    let sample_count = supported_sample_count(&adapter, &device, COLOR_FORMAT, DEPTH_FORMAT, 4);
+    if sample_count < 4 {
+        eprintln!("No 4x MSAA, skipping");
+        return;
+    }
+
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let scene = TwoNodes::new(&device, &queue);
+    let material_binder = MaterialBinder::new(&device);
+    let _model_pipeline = ModelPipeline::new(
+        &device,
+        COLOR_FORMAT,
+        DEPTH_FORMAT,
+        &scene.camera_binder,
+        &material_binder,
+        &LightBinder::new(&device),
+        sample_count,
+    );
+    let visualization_pipeline = VisualizationPipeline::new(
+        &device,
+        COLOR_FORMAT,
+        DEPTH_FORMAT,
+        &scene.camera_binder,
+        Default::default(),
+        sample_count,
+    );
+    let _heatmap_pipeline = HeatmapPipeline::new(&device, COLOR_FORMAT, sample_count);
+    let shader = device.create_shader_module(wgpu::include_wgsl!("../src/shader.wgsl"));
+    let _text_pipeline = TextPipeline::new(
+        &load_font(&device, &queue),
+        &scene.camera_binder,
+        COLOR_FORMAT,
+        &TextureBinder::new(&device),
+        &shader,
+        &device,
+        sample_count,
+    )
+    .unwrap();
+
+    // Draws into the samples and resolves them into a plain texture
+    let multisampled = |format, usage| {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("multisampled"),
+            size: wgpu::Extent3d {
+                width: WIDTH,
+                height: HEIGHT,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        })
+    };
+    let msaa_texture = multisampled(COLOR_FORMAT, wgpu::TextureUsages::RENDER_ATTACHMENT);
+    let depth_texture = multisampled(DEPTH_FORMAT, wgpu::TextureUsages::RENDER_ATTACHMENT);
+    let color_texture = create_color_texture(&device);
+    let msaa_view = msaa_texture.create_view(&Default::default());
+    let depth_view = depth_texture.create_view(&Default::default());
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &msaa_view,
+                resolve_target: Some(&color_texture.view),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            ..Default::default()
+        });
+        visualization_pipeline.draw_batch(
+            &mut pass,
+            &[
+                (scene.node_model, &scene.node_instances),
+                (scene.connection_model, &scene.connection_instances),
+            ],
+            &scene.model_pipeline,
+            &scene.camera_binding,
+        );
+    }
+    queue.submit([encoder.finish()]);
+    let error = pollster::block_on(device.pop_error_scope());
+    assert!(error.is_none(), "{error:?}");
+
+    // Same scene, only the edges are smoothed
+    let resolved = color_texture.read_to_image(&device, &queue).unwrap();
+    let (aliased, _) = render_two_nodes(&device, &queue, true);
+    let (_, mismatched) = compare_images(&resolved, &aliased, PIXEL_TOLERANCE);
+    assert!(mismatched > 0);
+    assert!((mismatched as f32) < 0.05 * (WIDTH * HEIGHT) as f32);
+}
+
+#[test]
+fn test_bind_four_lights() {
+    let Some((device, _queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+
+    let lights = (0..4)
+        .map(|i| LightUniform::new(glam::vec3(i as f32, 2.0, 2.0), glam::Vec3::ONE))
+        .collect::<Vec<_>>();
+    let camera_binder = CameraBinder::new(&device);
+    let material_binder = MaterialBinder::new(&device);
+
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let light_binder = LightBinder::new(&device);
+    assert_eq!(
+        light_binder.lights_binding_type(),
+        wgpu::BufferBindingType::Storage { read_only: true }
+    );
+    assert_eq!(light_binder.bind(&device, &lights).len(), 4);
+
+    // The fallback caps how many lights it binds
+    let fallback = LightBinder::with_storage(&device, false);
+    assert_eq!(
+        fallback.lights_binding_type(),
+        wgpu::BufferBindingType::Uniform
+    );
+    assert_eq!(fallback.bind(&device, &lights).len(), 4);
+    let too_many = vec![lights[0]; MAX_UNIFORM_LIGHTS + 1];
+    assert_eq!(fallback.bind(&device, &too_many).len(), MAX_UNIFORM_LIGHTS);
+
+    // Both versions of the shader have to match their layouts
+    for binder in [&light_binder, &fallback] {
+        ModelPipeline::new(
+            &device,
+            COLOR_FORMAT,
+            DEPTH_FORMAT,
+            &camera_binder,
+            &material_binder,
+            binder,
+            1,
+        );
+    }
+    let error = pollster::block_on(device.pop_error_scope());
+    assert!(error.is_none(), "{error:?}");
+}
+
+#[test]
+fn test_read_to_image() {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+
+    // 100 pixels wide so each row has to be unpadded
+    let color = wgpu::Color {
+        r: 1.0,
+        g: 0.5,
+        b: 0.0,
+        a: 1.0,
+    };
+    let texture = Texture::from_color(
+        &device,
+        &queue,
+        100,
+        50,
+        color,
+        false,
+        wgpu::TextureUsages::COPY_SRC,
+    );
+    let image = texture.read_to_image(&device, &queue).unwrap();
+    assert_eq!(image.dimensions(), (100, 50));
+    let center = image.get_pixel(50, 25).0;
+    assert_eq!(center[0], 255);
+    assert!(center[1].abs_diff(128) <= 1, "{center:?}");
+    assert_eq!(center[2], 0);
+    assert_eq!(center[3], 255);
+    assert_eq!(image.get_pixel(99, 49), image.get_pixel(0, 0));
+}
+
+#[test]
+fn test_sun_shadows() {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let scene = TwoNodes::new(&device, &queue);
+    let light_binder = LightBinder::new(&device);
+    let mut lights = light_binder.bind(&device, &[]);
+    let shadow_pipeline = ShadowPipeline::new(&device, &light_binder);
+    let shadow_map = &lights.shadow_map().texture;
+    assert_eq!(shadow_map.format(), SHADOW_MAP_FORMAT);
+    assert_eq!(shadow_map.width(), SHADOW_MAP_SIZE);
+
+    // A wall just behind the nodes, facing the camera
+    let material_binder = MaterialBinder::new(&device);
+    let mut model_pipeline = ModelPipeline::new(
+        &device,
+        COLOR_FORMAT,
+        DEPTH_FORMAT,
+        &scene.camera_binder,
+        &material_binder,
+        &light_binder,
+        1,
+    );
+    let wall = model_pipeline.add_mesh_data(
+        &device,
+        &queue,
+        &material_binder,
+        &[MeshData::plane(3.0, 1)],
+    );
+    let rotation = glam::Mat4::from_rotation_x(std::f32::consts::FRAC_PI_2);
+    let wall_instances = BackedBuffer::with_data(
+        &device,
+        vec![InstanceVertex {
+            model_matrix: glam::Mat4::from_translation(glam::vec3(0.0, 0.0, -0.5)) * rotation,
+            normal_matrix_0: rotation.x_axis,
+            normal_matrix_1: rotation.y_axis,
+            normal_matrix_2: rotation.z_axis,
+        }],
+        wgpu::BufferUsages::VERTEX,
+    );
+
+    // From above and in front, so each node's shadow lands below it
+    let sun = DirectionalLight::new(glam::vec3(0.0, 1.0, 1.0), glam::Vec3::ONE);
+    lights.set_sun(&queue, sun);
+
+    let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("depth"),
+        size: wgpu::Extent3d {
+            width: WIDTH,
+            height: HEIGHT,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    let depth_view = depth_texture.create_view(&Default::default());
+    let render_wall = |lights: &LightBinding| {
+        let color_texture = create_color_texture(&device);
+        let mut encoder = device.create_command_encoder(&Default::default());
+        let drawn = shadow_pipeline.render(
+            &mut encoder,
+            lights,
+            &[
+                (scene.node_model, &scene.node_instances),
+                (scene.connection_model, &scene.connection_instances),
+            ],
+            &scene.model_pipeline,
+        );
+        assert_eq!(drawn, 3);
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &color_texture.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                ..Default::default()
+            });
+            model_pipeline.draw(
+                &mut pass,
+                wall,
+                &scene.camera_binding,
+                lights,
+                &wall_instances,
+            );
+        }
+        queue.submit([encoder.finish()]);
+        color_texture.read_to_image(&device, &queue).unwrap()
+    };
+
+    // Shadows start off
+    let unshadowed = render_wall(&lights);
+    lights.set_shadow_view_proj(&queue, sun.view_proj(glam::Vec3::ZERO, 2.0));
+    let shadowed = render_wall(&lights);
+    let error = pollster::block_on(device.pop_error_scope());
+    assert!(error.is_none(), "{error:?}");
+
+    let brightness = |image: &image::RgbaImage, x, y| {
+        image.get_pixel(x, y).0[..3]
+            .iter()
+            .map(|&c| c as u32)
+            .sum::<u32>()
+    };
+    // The left node's shadow
+    assert!(brightness(&unshadowed, 33, 95) > 0);
+    assert!(brightness(&shadowed, 33, 95) < brightness(&unshadowed, 33, 95) / 2);
+    // Above the nodes the wall stays lit
+    assert_eq!(
+        brightness(&shadowed, 64, 20),
+        brightness(&unshadowed, 64, 20)
+    );
+}
+
+#[test]
+fn test_grid_pipeline() {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let scene = TwoNodes::new(&device, &queue);
+    // Surfaces are usually sRGB
+    GridPipeline::new(
+        &device,
+        wgpu::TextureFormat::Bgra8UnormSrgb,
+        DEPTH_FORMAT,
+        &scene.camera_binder,
+        1,
+    );
+    let mut grid_pipeline =
+        GridPipeline::new(&device, COLOR_FORMAT, DEPTH_FORMAT, &scene.camera_binder, 1);
+    grid_pipeline.set_height(-0.5);
+    grid_pipeline.set_color(glam::Vec4::ONE);
+    grid_pipeline.update(&queue);
+
+    let color_texture = create_color_texture(&device);
+    let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("depth"),
+        size: color_texture.texture.size(),
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &color_texture.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth_texture.create_view(&Default::default()),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            ..Default::default()
+        });
+        grid_pipeline.draw(&mut pass, &scene.camera_binding);
+    }
+    queue.submit([encoder.finish()]);
+    let error = pollster::block_on(device.pop_error_scope());
+    assert!(error.is_none(), "{error:?}");
+
+    // The camera looks level, so the grid is only below the horizon
+    let image = color_texture.read_to_image(&device, &queue).unwrap();
+    let lit_rows = |rows: std::ops::Range<u32>| {
+        rows.filter(|&y| (0..WIDTH).any(|x| image.get_pixel(x, y).0[0] > 0))
+            .count()
+    };
+    assert_eq!(lit_rows(0..HEIGHT / 2), 0);
+    assert!(lit_rows(HEIGHT / 2..HEIGHT) > 0);
+}
+
+#[test]
+fn test_anisotropic_sampler() {
+    let Some((device, _queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let texture = Texture::from_descriptor(
+        &device,
+        &wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: 4,
+                height: 4,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        },
+        SamplerOptions::anisotropic(8),
+    );
+    // Binding it checks the sampler against a filtering layout
+    TextureBinder::new(&device).bind(&device, &texture);
+    let error = pollster::block_on(device.pop_error_scope());
+    assert!(error.is_none(), "{error:?}");
+}
+
+fn load_font(device: &wgpu::Device, queue: &wgpu::Queue) -> Font {
+    let res = FsResources::new(concat!(env!("CARGO_MANIFEST_DIR"), "/res"));
+    Font::load(&res, "fonts/OpenSans MSDF.zip", '\u{FFFD}', device, queue).unwrap()
+}
+
+/// A text pipeline with a camera mapping pixels onto the render target.
+fn text_pipeline(device: &wgpu::Device, font: &Font) -> (TextPipeline, CameraBinding) {
+    let camera_binder = CameraBinder::new(device);
+    let camera_binding = camera_binder.bind(
+        device,
+        &OrthoCamera::new(0.0, WIDTH as f32, HEIGHT as f32, 0.0),
+    );
+    let shader = device.create_shader_module(wgpu::include_wgsl!("../src/shader.wgsl"));
+    let text_pipeline = TextPipeline::new(
+        font,
+        &camera_binder,
+        COLOR_FORMAT,
+        &TextureBinder::new(device),
+        &shader,
+        device,
+        1,
+    )
+    .unwrap();
+    (text_pipeline, camera_binding)
+}
+
+fn create_color_texture(device: &wgpu::Device) -> Texture {
+    let desc = wgpu::TextureDescriptor {
+        label: Some("color"),
+        size: wgpu::Extent3d {
+            width: WIDTH,
+            height: HEIGHT,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: COLOR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    };
+    Texture::from_descriptor(device, &desc, SamplerOptions::default())
+}
+
+#[test]
+fn test_multiline_text_layout() {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+
+    let font = load_font(&device, &queue);
+    let line_height = font.info.common.line_height as f32;
+    let layout = TextLayout::at(glam::vec2(100.0, 50.0));
+
+    // The second line is one line height further down
+    let rects = font.glyph_rects("AB\nAB", &layout);
+    assert_eq!(rects.len(), 4);
+    assert_eq!(rects[0].0.y, rects[1].0.y);
+    assert_eq!(rects[2].0.y, rects[0].0.y + line_height);
+    assert_eq!(rects[2].0.x, rects[0].0.x);
+    let down = glam::vec2(0.0, line_height);
+    assert_eq!(rects[3], (rects[1].0 + down, rects[1].1 + down));
+
+    // Trailing newlines add nothing, and empty lines still take up space
+    assert_eq!(
+        font.glyph_rects("AB\n", &layout),
+        font.glyph_rects("AB", &layout)
+    );
+    let rects = font.glyph_rects("A\n\nA", &layout.with_line_height(10.0));
+    assert_eq!(rects.len(), 2);
+    assert_eq!(rects[1].0.y - rects[0].0.y, 20.0);
+
+    // Each line lines up on the origin however wide its glyphs are
+    let xoffset = |c| font.glyph(c).unwrap().xoffset as f32;
+    let rects = font.glyph_rects("i\nWW", &layout.with_alignment(Alignment::Right));
+    assert_eq!(rects[0].0.x, 100.0 - font.line_width("i") + xoffset('i'));
+    assert_eq!(rects[1].0.x, 100.0 - font.line_width("WW") + xoffset('W'));
+    let rects = font.glyph_rects("i\nWW", &layout.with_alignment(Alignment::Center));
+    assert_eq!(
+        rects[0].0.x,
+        100.0 - font.line_width("i") / 2.0 + xoffset('i')
+    );
+    assert_eq!(
+        rects[1].0.x,
+        100.0 - font.line_width("WW") / 2.0 + xoffset('W')
+    );
+}
+
+#[test]
+fn test_labels_single_draw() {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+
+    let font = load_font(&device, &queue);
+    let (text_pipeline, camera_binding) = text_pipeline(&device, &font);
+
+    let names = (0..100).map(|i| format!("N{i}")).collect::<Vec<_>>();
+    let labels = names
+        .iter()
+        .enumerate()
+        .map(|(i, text)| Label {
+            text,
+            position: glam::vec3((i % 10) as f32 * 12.0, (i / 10) as f32 * 12.0, 0.0),
+            scale: 0.2,
+        })
+        .collect::<Vec<_>>();
+    let batch = text_pipeline.buffer_labels(&font, &device, &labels);
+    assert_eq!(batch.num_labels(), 100);
+    // "N0" through "N99"
+    assert_eq!(batch.num_glyphs(), 10 * 2 + 90 * 3);
+
+    let color_texture = create_color_texture(&device);
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    let drawn = {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &color_texture.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        text_pipeline.draw_labels(&mut pass, &batch, &camera_binding)
+    };
+    queue.submit([encoder.finish()]);
+    assert_eq!(drawn, batch.num_glyphs());
+
+    // Every label shows up in its own cell
+    let image = color_texture.read_to_image(&device, &queue).unwrap();
+    let lit = |cell: u32| {
+        let (x0, y0) = (cell % 10 * 12, cell / 10 * 12);
+        (y0..y0 + 12)
+            .flat_map(|y| (x0..x0 + 12).map(move |x| (x, y)))
+            .any(|(x, y)| image.get_pixel(x, y).0[..3] != [0, 0, 0])
+    };
+    let missing = (0..100).filter(|&cell| !lit(cell)).collect::<Vec<_>>();
+    assert!(missing.is_empty(), "{missing:?}");
+
+    // Nothing from an empty batch
+    let empty = text_pipeline.buffer_labels(&font, &device, &[]);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: &color_texture.view,
+            resolve_target: None,
+            ops: Default::default(),
+        })],
+        ..Default::default()
+    });
+    assert_eq!(
+        text_pipeline.draw_labels(&mut pass, &empty, &camera_binding),
+        0
+    );
+}
+
+#[test]
+fn test_text_colors() {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+
+    let font = load_font(&device, &queue);
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let (text_pipeline, camera_binding) = text_pipeline(&device, &font);
+    let mut red = text_pipeline
+        .buffer_text(&font, &device, "II", &TextLayout::at(glam::vec2(0.0, 0.0)))
+        .unwrap();
+    let mut green = text_pipeline
+        .buffer_text(&font, &device, "II", &TextLayout::at(glam::vec2(64.0, 0.0)))
+        .unwrap();
+    red.set_color(&queue, glam::vec4(1.0, 0.0, 0.0, 1.0));
+    green.set_color(&queue, glam::vec4(0.0, 1.0, 0.0, 1.0));
+    assert_eq!(red.color(), glam::vec4(1.0, 0.0, 0.0, 1.0));
+
+    let color_texture = create_color_texture(&device);
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &color_texture.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        text_pipeline.draw_text(&mut pass, &red, &camera_binding);
+        text_pipeline.draw_text(&mut pass, &green, &camera_binding);
+    }
+    queue.submit([encoder.finish()]);
+    let error = pollster::block_on(device.pop_error_scope());
+    assert!(error.is_none(), "{error:?}");
+
+    // Each buffer only shows up in its own color
+    let image = color_texture.read_to_image(&device, &queue).unwrap();
+    let colors = |xs: std::ops::Range<u32>| {
+        xs.flat_map(|x| (0..HEIGHT).map(move |y| (x, y)))
+            .map(|(x, y)| image.get_pixel(x, y).0)
+            .filter(|pixel| pixel[..3] != [0, 0, 0])
+            .collect::<Vec<_>>()
+    };
+    let left = colors(0..WIDTH / 2);
+    let right = colors(WIDTH / 2..WIDTH);
+    assert!(!left.is_empty() && !right.is_empty());
+    assert!(left.iter().all(|pixel| pixel[1] == 0 && pixel[2] == 0));
+    assert!(right.iter().all(|pixel| pixel[0] == 0 && pixel[2] == 0));
+}
+
+#[test]
+fn test_backed_buffer_growth() {
+    let Some((device, queue)) = device() else {
+        eprintln!("No adapter available, skipping");
+        return;
+    };
+
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let usage = wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC;
+    let mut instances = BackedBuffer::<ColoredInstance>::with_capacity(&device, 0, usage);
+    let expected = (0..1000)
+        .map(|i| {
+            ColoredInstance::with_position_scale(glam::Vec3::ONE, glam::Vec3::X * i as f32, 1.0)
+        })
+        .collect::<Vec<_>>();
+    for &instance in &expected[..500] {
+        instances.push(&device, &queue, instance);
+    }
+    // Batches grow the buffer the same way once they're done
+    {
+        let mut batch = instances.batch(&device, &queue);
+        for &instance in &expected[500..] {
+            batch.push(instance);
+        }
+    }
+    let stride = size_of::<ColoredInstance>() as wgpu::BufferAddress;
+    assert_eq!(instances.len(), 1000);
+    assert!(instances.capacity() >= 1000);
+    assert_eq!(instances.slice().size().get(), 1000 * stride);
+    // Doubling means only a handful of reallocations
+    assert!(instances.version() <= 11, "{}", instances.version());
+
+    // Truncating keeps the capacity, and extending and batches refill it
+    let capacity = instances.capacity();
+    instances.truncate(10);
+    assert_eq!(instances.len(), 10);
+    assert_eq!(instances.slice().size().get(), 10 * stride);
+    instances.extend(&device, &queue, expected[10..500].iter().copied());
+    {
+        let mut batch = instances.batch(&device, &queue);
+        for &instance in &expected[500..] {
+            batch.push(instance);
+        }
+    }
+    assert_eq!(instances.capacity(), capacity);
+
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback"),
+        size: 1000 * stride,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_buffer_to_buffer(instances.buffer(), 0, &readback, 0, 1000 * stride);
+    queue.submit([encoder.finish()]);
+    readback.slice(..).map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::PollType::Wait).unwrap();
+    assert_eq!(
+        &readback.slice(..).get_mapped_range()[..],
+        bytemuck::cast_slice::<_, u8>(&expected)
+    );
+
+    let error = pollster::block_on(device.pop_error_scope());
+    assert!(error.is_none(), "{error:?}");
+}